@@ -0,0 +1,504 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The self-describing header written at the beginning of every payload.
+///
+/// All the fields are little endian:
+///
+/// | offset | size | field                                        |
+/// |--------|------|----------------------------------------------|
+/// | 0      | 4    | magic `ZPRF`                                 |
+/// | 4      | 1    | version                                      |
+/// | 5      | 1    | flags, bit 0 is set when a checksum is given |
+/// | 6      | 2    | reserved                                     |
+/// | 8      | 4    | producer id                                  |
+/// | 12     | 8    | sequence number                              |
+/// | 20     | 8    | send timestamp (ns since UNIX epoch)         |
+/// | 28     | 4    | total payload length, header included        |
+/// | 32     | 4    | FNV-1a checksum of the bytes after the header|
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub producer_id: u32,
+    pub sequence: u64,
+    pub timestamp: u64,
+    pub length: u32,
+    pub checksum: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderError {
+    TooShort(usize),
+    BadMagic([u8; 4]),
+    UnsupportedVersion(u8),
+    LengthMismatch { expected: usize, actual: usize },
+    ChecksumMismatch { expected: u32, actual: u32 },
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderError::TooShort(size) => write!(
+                f,
+                "payload of {} bytes is shorter than the {} bytes header",
+                size,
+                Header::SIZE
+            ),
+            HeaderError::BadMagic(magic) => write!(f, "bad magic {magic:02x?}"),
+            HeaderError::UnsupportedVersion(v) => write!(f, "unsupported header version {v}"),
+            HeaderError::LengthMismatch { expected, actual } => write!(
+                f,
+                "expect a payload of {expected} bytes, but received {actual} bytes"
+            ),
+            HeaderError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "expect checksum {expected:#010x}, but computed {actual:#010x}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HeaderError {}
+
+impl Header {
+    pub const MAGIC: [u8; 4] = *b"ZPRF";
+    pub const VERSION: u8 = 1;
+    pub const SIZE: usize = 36;
+    /// The most publishers per process, whose index is the low byte of their
+    /// producer id.
+    pub const MAX_PRODUCERS: usize = 256;
+
+    const FLAG_CHECKSUM: u8 = 0x01;
+
+    /// A header stamped with the current time and without checksum.
+    pub fn new(producer_id: u32, sequence: u64, length: usize) -> Self {
+        Self {
+            producer_id,
+            sequence,
+            timestamp: now(),
+            length: length as u32,
+            checksum: None,
+        }
+    }
+
+    /// Writes the header at the beginning of `buf`.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<(), HeaderError> {
+        if buf.len() < Self::SIZE {
+            return Err(HeaderError::TooShort(buf.len()));
+        }
+        let flags = match self.checksum {
+            Some(_) => Self::FLAG_CHECKSUM,
+            None => 0,
+        };
+        buf[0..4].copy_from_slice(&Self::MAGIC);
+        buf[4] = Self::VERSION;
+        buf[5] = flags;
+        buf[6..8].copy_from_slice(&[0u8; 2]);
+        buf[8..12].copy_from_slice(&self.producer_id.to_le_bytes());
+        buf[12..20].copy_from_slice(&self.sequence.to_le_bytes());
+        buf[20..28].copy_from_slice(&self.timestamp.to_le_bytes());
+        buf[28..32].copy_from_slice(&self.length.to_le_bytes());
+        buf[32..36].copy_from_slice(&self.checksum.unwrap_or(0).to_le_bytes());
+        Ok(())
+    }
+
+    /// Reads the header at the beginning of `buf`.
+    pub fn decode(buf: &[u8]) -> Result<Self, HeaderError> {
+        if buf.len() < Self::SIZE {
+            return Err(HeaderError::TooShort(buf.len()));
+        }
+        let magic: [u8; 4] = buf[0..4].try_into().unwrap();
+        if magic != Self::MAGIC {
+            return Err(HeaderError::BadMagic(magic));
+        }
+        if buf[4] != Self::VERSION {
+            return Err(HeaderError::UnsupportedVersion(buf[4]));
+        }
+        let checksum = u32::from_le_bytes(buf[32..36].try_into().unwrap());
+        Ok(Self {
+            producer_id: u32::from_le_bytes(buf[8..12].try_into().unwrap()),
+            sequence: u64::from_le_bytes(buf[12..20].try_into().unwrap()),
+            timestamp: u64::from_le_bytes(buf[20..28].try_into().unwrap()),
+            length: u32::from_le_bytes(buf[28..32].try_into().unwrap()),
+            checksum: (buf[5] & Self::FLAG_CHECKSUM != 0).then_some(checksum),
+        })
+    }
+
+    /// Reads the header from a payload split in several slices, e.g. a `ZBuf`.
+    pub fn decode_slices<'a, I>(slices: I) -> Result<Self, HeaderError>
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let mut buf = [0u8; Self::SIZE];
+        let mut read = 0;
+        for slice in slices {
+            let n = slice.len().min(Self::SIZE - read);
+            buf[read..read + n].copy_from_slice(&slice[..n]);
+            read += n;
+            if read == Self::SIZE {
+                return Self::decode(&buf);
+            }
+        }
+        Err(HeaderError::TooShort(read))
+    }
+
+    /// Checks the length and, if present, the checksum of the whole payload.
+    pub fn verify(&self, payload: &[u8]) -> Result<(), HeaderError> {
+        self.verify_slices(std::iter::once(payload))
+    }
+
+    /// Like [`Header::verify`] for a payload split in several slices.
+    pub fn verify_slices<'a, I>(&self, slices: I) -> Result<(), HeaderError>
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let mut hasher = Fnv1a::new();
+        let mut actual = 0;
+        for slice in slices {
+            let skip = Self::SIZE.saturating_sub(actual).min(slice.len());
+            if self.checksum.is_some() {
+                hasher.update(&slice[skip..]);
+            }
+            actual += slice.len();
+        }
+        if actual != self.length as usize {
+            return Err(HeaderError::LengthMismatch {
+                expected: self.length as usize,
+                actual,
+            });
+        }
+        match self.checksum {
+            Some(expected) if expected != hasher.finish() => Err(HeaderError::ChecksumMismatch {
+                expected,
+                actual: hasher.finish(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Time elapsed since the payload was stamped, zero if the clock went backward.
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(now().saturating_sub(self.timestamp))
+    }
+
+    /// Stamps `payload` in place with a new header, keeping its content.
+    pub fn stamp(payload: &mut [u8], producer_id: u32, sequence: u64, checksum: bool) -> Self {
+        let mut header = Self::new(producer_id, sequence, payload.len());
+        if checksum {
+            header.checksum = Some(Fnv1a::hash(&payload[Self::SIZE.min(payload.len())..]));
+        }
+        header.encode(payload).unwrap();
+        header
+    }
+
+    /// Builds a payload of `size` bytes: the header followed by a repeating pattern.
+    pub fn payload(producer_id: u32, sequence: u64, size: usize, checksum: bool) -> Vec<u8> {
+        assert!(
+            size >= Self::SIZE,
+            "The minimum payload size is {} bytes",
+            Self::SIZE
+        );
+        let mut payload: Vec<u8> = (0..size).map(|i| (i % 10) as u8).collect();
+        Self::stamp(&mut payload, producer_id, sequence, checksum);
+        payload
+    }
+
+    /// Like [`Header::payload`], but a payload too small to hold the header
+    /// is only made of the pattern.
+    pub fn payload_if_fits(
+        producer_id: u32,
+        sequence: u64,
+        size: usize,
+        checksum: bool,
+    ) -> Vec<u8> {
+        if size < Self::SIZE {
+            return (0..size).map(|i| (i % 10) as u8).collect();
+        }
+        Self::payload(producer_id, sequence, size, checksum)
+    }
+
+    /// The producer id of the `index`-th publisher of this process, unique
+    /// across the processes of a host: the process id followed by `index`.
+    ///
+    /// Fails rather than mixing up the publishers when `index` is beyond
+    /// [`Header::MAX_PRODUCERS`] or the process id leaves no room for it.
+    pub fn producer_id(index: usize) -> Result<u32, String> {
+        Self::producer_id_of(std::process::id(), index)
+    }
+
+    fn producer_id_of(pid: u32, index: usize) -> Result<u32, String> {
+        if index >= Self::MAX_PRODUCERS {
+            return Err(format!(
+                "At most {} publishers per process",
+                Self::MAX_PRODUCERS
+            ));
+        }
+        pid.checked_mul(Self::MAX_PRODUCERS as u32)
+            .map(|id| id | index as u32)
+            .ok_or_else(|| format!("The process id {pid} is too large for a producer id"))
+    }
+}
+
+/// Parses a payload size large enough to hold the header, meant to be the
+/// `value_parser` of the tools stamping every payload with it.
+pub fn parse_payload(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(size) if size >= Header::SIZE => Ok(size),
+        Ok(_) => Err(format!(
+            "The minimum payload size is {} bytes",
+            Header::SIZE
+        )),
+        Err(_) => Err(format!("Invalid payload size {s}")),
+    }
+}
+
+/// A payload sent over and over, as built by [`Header::payload_if_fits`].
+///
+/// Only the header is written per message: the body, i.e. the bytes after
+/// the header, is built once and shared by all the messages, so is its
+/// checksum rather than computed again over possibly hundreds of MiB.
+#[derive(Debug, Clone)]
+pub struct Payload {
+    producer_id: u32,
+    size: usize,
+    checksum: Option<u32>,
+    body: Arc<Vec<u8>>,
+}
+
+impl Payload {
+    pub fn new(producer_id: u32, size: usize, checksum: bool) -> Self {
+        // A payload too small to hold the header is only made of the pattern
+        let start = if size < Header::SIZE { 0 } else { Header::SIZE };
+        let body: Vec<u8> = (start..size).map(|i| (i % 10) as u8).collect();
+        Self {
+            producer_id,
+            size,
+            checksum: (checksum && start > 0).then(|| Fnv1a::hash(&body)),
+            body: Arc::new(body),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The header of the `sequence`-th message, empty when the payload is
+    /// too small to hold it.
+    pub fn header(&self, sequence: u64) -> Vec<u8> {
+        if self.size < Header::SIZE {
+            return vec![];
+        }
+        let mut header = Header::new(self.producer_id, sequence, self.size);
+        header.checksum = self.checksum;
+        let mut buf = vec![0u8; Header::SIZE];
+        header.encode(&mut buf).unwrap();
+        buf
+    }
+
+    pub fn body(&self) -> &Arc<Vec<u8>> {
+        &self.body
+    }
+}
+
+#[cfg(feature = "zenoh")]
+mod zenoh_ext {
+    use super::{Header, HeaderError, Payload};
+    use zenoh::prelude::Value;
+    use zenoh_buffers::{SplitBuffer, ZBuf};
+
+    impl Header {
+        pub fn decode_zbuf(zbuf: &ZBuf) -> Result<Self, HeaderError> {
+            Self::decode_slices(zbuf.slices())
+        }
+
+        pub fn decode_value(value: &Value) -> Result<Self, HeaderError> {
+            Self::decode_zbuf(&value.payload)
+        }
+
+        pub fn verify_zbuf(&self, zbuf: &ZBuf) -> Result<(), HeaderError> {
+            self.verify_slices(zbuf.slices())
+        }
+
+        pub fn verify_value(&self, value: &Value) -> Result<(), HeaderError> {
+            self.verify_zbuf(&value.payload)
+        }
+    }
+
+    impl Payload {
+        /// The `sequence`-th message, its header followed by the shared body.
+        pub fn zbuf(&self, sequence: u64) -> ZBuf {
+            let mut zbuf = ZBuf::default();
+            let header = self.header(sequence);
+            if !header.is_empty() {
+                zbuf.add_zslice(header.into());
+            }
+            if !self.body.is_empty() {
+                zbuf.add_zslice(self.body.clone().into());
+            }
+            zbuf
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+struct Fnv1a(u32);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Self(0x811c_9dc5)
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u32;
+            self.0 = self.0.wrapping_mul(0x0100_0193);
+        }
+    }
+
+    fn finish(&self) -> u32 {
+        self.0
+    }
+
+    fn hash(bytes: &[u8]) -> u32 {
+        let mut hasher = Self::new();
+        hasher.update(bytes);
+        hasher.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers_round_trip() {
+        let payload = Header::payload(7, 42, 128, true);
+        let header = Header::decode(&payload).unwrap();
+        assert_eq!((header.producer_id, header.sequence), (7, 42));
+        assert_eq!(header.length, 128);
+        assert!(header.checksum.is_some());
+        assert_eq!(header.verify(&payload), Ok(()));
+
+        let mut buf = [0u8; Header::SIZE];
+        header.encode(&mut buf).unwrap();
+        assert_eq!(Header::decode(&buf), Ok(header));
+    }
+
+    #[test]
+    fn split_payloads_decode_and_verify_alike() {
+        let payload = Header::payload(1, 2, 100, true);
+        let header = Header::decode(&payload).unwrap();
+        for at in [1, 20, Header::SIZE, 50] {
+            let (a, b) = payload.split_at(at);
+            assert_eq!(Header::decode_slices([a, b]), Ok(header));
+            assert_eq!(header.verify_slices([a, b]), Ok(()));
+        }
+    }
+
+    #[test]
+    fn corrupted_payloads_fail_the_checksum() {
+        let mut payload = Header::payload(1, 2, 64, true);
+        payload[Header::SIZE + 1] ^= 0xff;
+        let header = Header::decode(&payload).unwrap();
+        assert!(matches!(
+            header.verify(&payload),
+            Err(HeaderError::ChecksumMismatch { .. })
+        ));
+
+        // Without checksum, only the length is verified
+        let mut payload = Header::payload(1, 2, 64, false);
+        payload[Header::SIZE + 1] ^= 0xff;
+        let header = Header::decode(&payload).unwrap();
+        assert_eq!(header.verify(&payload), Ok(()));
+        assert_eq!(
+            header.verify(&payload[..60]),
+            Err(HeaderError::LengthMismatch {
+                expected: 64,
+                actual: 60
+            })
+        );
+    }
+
+    #[test]
+    fn foreign_payloads_are_rejected() {
+        assert_eq!(Header::decode(&[0u8; 8]), Err(HeaderError::TooShort(8)));
+        assert_eq!(
+            Header::decode_slices([&[0u8; 8][..], &[0u8; 8]]),
+            Err(HeaderError::TooShort(16))
+        );
+        let mut payload = Header::payload(1, 2, 64, false);
+        payload[4] = Header::VERSION + 1;
+        assert_eq!(
+            Header::decode(&payload),
+            Err(HeaderError::UnsupportedVersion(Header::VERSION + 1))
+        );
+        payload[0] = b'X';
+        assert!(matches!(
+            Header::decode(&payload),
+            Err(HeaderError::BadMagic(_))
+        ));
+    }
+
+    #[test]
+    fn shared_payloads_match_the_contiguous_ones() {
+        for (size, checksum) in [(64, true), (64, false), (Header::SIZE, true)] {
+            let payload = Payload::new(1, size, checksum);
+            let header = Header::decode(&payload.header(3)).unwrap();
+            let expected = Header::decode(&Header::payload(1, 3, size, checksum)).unwrap();
+            assert_eq!((header.sequence, header.length), (3, size as u32));
+            assert_eq!(header.checksum, expected.checksum);
+            assert_eq!(
+                header.verify_slices([&payload.header(3)[..], payload.body()]),
+                Ok(())
+            );
+        }
+
+        // Too small to hold a header, the payload is only the pattern
+        let payload = Payload::new(1, 8, true);
+        assert!(payload.header(3).is_empty());
+        assert_eq!(**payload.body(), (0..8).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn payload_sizes_hold_the_header() {
+        assert_eq!(parse_payload("36"), Ok(Header::SIZE));
+        assert_eq!(parse_payload("1024"), Ok(1024));
+        assert!(parse_payload("35").is_err());
+        assert!(parse_payload("8").is_err());
+        assert!(parse_payload("-1").is_err());
+        assert!(parse_payload("1K").is_err());
+    }
+
+    #[test]
+    fn producer_ids_end_with_the_index() {
+        assert_eq!(Header::producer_id_of(0x12_3456, 7), Ok(0x1234_5607));
+        assert_eq!(Header::producer_id_of(0xff_ffff, 255), Ok(u32::MAX));
+    }
+
+    #[test]
+    fn producer_ids_never_drop_bits() {
+        assert!(Header::producer_id_of(1, Header::MAX_PRODUCERS).is_err());
+        assert!(Header::producer_id_of(0x100_0000, 0).is_err());
+    }
+}
@@ -0,0 +1,213 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+use crate::{
+    header::{Header, HeaderError},
+    output::Output,
+    run::Run,
+    stats::Throughput,
+    sweep::Meter,
+};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+
+/// The deliveries counted by [`Integrity`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Deliveries {
+    verified: u64,
+    corrupted: u64,
+    truncated: u64,
+}
+
+/// Checks every payload delivered by zenoh, reassembled from its fragments
+/// when it exceeds the batch size, against its [`Header`]: its length and,
+/// when the publisher gave one, the checksum of its content.
+pub struct Integrity {
+    run: Arc<Run>,
+    meter: Meter,
+    verified: AtomicU64,
+    corrupted: AtomicU64,
+    truncated: AtomicU64,
+    reassembly: Throughput,
+    total: Mutex<Deliveries>,
+}
+
+impl Integrity {
+    pub fn new(run: &Arc<Run>) -> Self {
+        Self {
+            run: run.clone(),
+            meter: Meter::new(),
+            verified: AtomicU64::new(0),
+            corrupted: AtomicU64::new(0),
+            truncated: AtomicU64::new(0),
+            reassembly: Throughput::new(run.clone()),
+            total: Mutex::default(),
+        }
+    }
+
+    /// Verifies a payload split in several slices, e.g. a `ZBuf`.
+    ///
+    /// A payload shorter than announced by its header, or too short to hold
+    /// a header, is truncated, any other mismatch means it is corrupted.
+    pub fn add(&self, slices: &[&[u8]]) {
+        let length: usize = slices.iter().map(|slice| slice.len()).sum();
+        let result = Header::decode_slices(slices.iter().copied())
+            .and_then(|header| header.verify_slices(slices.iter().copied()));
+        match result {
+            Ok(()) => {
+                self.meter.add(length);
+                self.verified.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(HeaderError::TooShort(_)) => {
+                self.truncated.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(HeaderError::LengthMismatch { expected, actual }) if actual < expected => {
+                self.truncated.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                log::warn!("Corrupted payload of {length} bytes: {e}");
+                self.corrupted.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Emits the throughput of the payloads verified over the last `elapsed`
+    /// microseconds as `reassembly`, then the corrupted and the truncated
+    /// ones.
+    ///
+    /// The throughput is not emitted for an interval mixing several payload
+    /// sizes of a sweep, but the deliveries are still counted in the totals.
+    pub fn record(&self, output: &Output, elapsed: f64) {
+        let measured = self.meter.take();
+        let deliveries = Deliveries {
+            verified: self.verified.swap(0, Ordering::Relaxed),
+            corrupted: self.corrupted.swap(0, Ordering::Relaxed),
+            truncated: self.truncated.swap(0, Ordering::Relaxed),
+        };
+        if deliveries == Deliveries::default() {
+            return;
+        }
+        if !self.run.is_warming_up() {
+            let mut total = self.total.lock().unwrap();
+            total.verified += deliveries.verified;
+            total.corrupted += deliveries.corrupted;
+            total.truncated += deliveries.truncated;
+        }
+        let payload = match measured {
+            Some((payload, c)) if c > 0 => {
+                let value = (c as f64 * 1_000_000.0 / elapsed).floor();
+                self.reassembly
+                    .record(output, "reassembly", payload, value, &[]);
+                payload
+            }
+            _ => 0,
+        };
+        output.emit("corrupted", payload, deliveries.corrupted as f64, "msg");
+        output.emit("truncated", payload, deliveries.truncated as f64, "msg");
+    }
+
+    /// Emits the summaries of the reassembly throughput, then the totals of
+    /// the run after the warm-up: the verified, corrupted and truncated
+    /// payloads.
+    pub fn emit(&self, output: &Output) {
+        self.reassembly.emit(output, "reassembly");
+        let total = *self.total.lock().unwrap();
+        let payload = self.reassembly.payload();
+        output.emit("integrity.verified", payload, total.verified as f64, "msg");
+        output.emit(
+            "integrity.corrupted",
+            payload,
+            total.corrupted as f64,
+            "msg",
+        );
+        output.emit(
+            "integrity.truncated",
+            payload,
+            total.truncated as f64,
+            "msg",
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{output::Records, run::Warmup};
+
+    #[test]
+    fn fragments_count_as_one_payload() {
+        let (records, output) = Records::output();
+        let integrity = Integrity::new(&Run::new(None, None, None));
+        let payload = Header::payload(1, 0, 100, true);
+        let (a, rest) = payload.split_at(10);
+        let (b, c) = rest.split_at(Header::SIZE);
+        integrity.add(&[a, b, c]);
+        integrity.add(&[&payload]);
+        integrity.record(&output, 1_000_000.0);
+        // In msg/s then in bit/s
+        assert_eq!(records.values("reassembly"), vec![2.0, 1600.0]);
+        assert_eq!(records.values("corrupted"), vec![0.0]);
+        assert_eq!(records.values("truncated"), vec![0.0]);
+        assert!(records
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|record| record.payload == 100));
+    }
+
+    #[test]
+    fn mismatches_are_corrupted_or_truncated() {
+        let (records, output) = Records::output();
+        let integrity = Integrity::new(&Run::new(None, None, None));
+        let mut corrupted = Header::payload(1, 0, 100, true);
+        corrupted[Header::SIZE + 1] ^= 0xff;
+        integrity.add(&[&corrupted]);
+        let payload = Header::payload(1, 1, 100, true);
+        let (head, tail) = payload.split_at(64);
+        integrity.add(&[head]);
+        integrity.add(&[&payload[..10]]);
+        integrity.add(&[head, tail, tail]);
+        integrity.record(&output, 1_000_000.0);
+        // No payload verified, so no throughput
+        assert!(records.values("reassembly").is_empty());
+        assert_eq!(records.values("corrupted"), vec![2.0]);
+        assert_eq!(records.values("truncated"), vec![2.0]);
+
+        integrity.emit(&output);
+        assert_eq!(records.values("integrity.verified"), vec![0.0]);
+        assert_eq!(records.values("integrity.corrupted"), vec![2.0]);
+        assert_eq!(records.values("integrity.truncated"), vec![2.0]);
+    }
+
+    #[test]
+    fn totals_leave_the_warmup_out() {
+        let (records, output) = Records::output();
+        let run = Run::new(None, None, Some(Warmup::Messages(1)));
+        let integrity = Integrity::new(&run);
+        integrity.add(&[&Header::payload(1, 0, 64, true)]);
+        integrity.record(&output, 1_000_000.0);
+        run.tick();
+        integrity.add(&[&Header::payload(1, 1, 64, true)]);
+        integrity.add(&[&Header::payload(1, 2, 64, true)]);
+        integrity.record(&output, 1_000_000.0);
+        // The intervals without deliveries are not reported
+        integrity.record(&output, 1_000_000.0);
+        assert_eq!(records.values("corrupted"), vec![0.0, 0.0]);
+
+        integrity.emit(&output);
+        assert_eq!(records.values("integrity.verified"), vec![2.0]);
+    }
+}
@@ -0,0 +1,268 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+use crate::output::Output;
+use clap::{builder::RangedU64ValueParser, Args};
+use std::{fmt, str::FromStr};
+
+/// Options generating the key space published or subscribed to below the
+/// key of a tool.
+///
+/// Meant to be embedded in a binary's own options with `#[clap(flatten)]`.
+/// Without any of them the tool uses its key alone.
+#[derive(Debug, Clone, Args)]
+pub struct KeysOpt {
+    /// number of keys below the key of the tool, e.g. 1000 for test/thr/0 to test/thr/999
+    #[clap(long, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    pub keys: Option<usize>,
+
+    /// a tree of keys instead, as DEPTH:FANOUT, e.g. 3:10 for test/thr/0/0/0 to test/thr/9/9/9
+    #[clap(long, conflicts_with = "keys")]
+    pub tree: Option<Tree>,
+}
+
+impl KeysOpt {
+    /// The number of keys, 1 for the key of the tool alone.
+    pub fn count(&self) -> usize {
+        match (self.keys, self.tree) {
+            (Some(keys), _) => keys,
+            (None, Some(tree)) => tree.count,
+            (None, None) => 1,
+        }
+    }
+
+    // The number of chunks of the keys below the key of the tool
+    fn depth(&self) -> usize {
+        match (self.keys, self.tree) {
+            (Some(_), _) => 1,
+            (None, Some(tree)) => tree.depth,
+            (None, None) => 0,
+        }
+    }
+
+    /// The `index`-th key below `key`, the publishers cycle over them.
+    pub fn key(&self, key: &str, index: usize) -> String {
+        match (self.keys, self.tree) {
+            (Some(_), _) => format!("{key}/{index}"),
+            (None, Some(tree)) => {
+                let mut chunks = vec![];
+                let mut rest = index;
+                for _ in 0..tree.depth {
+                    chunks.push((rest % tree.fanout).to_string());
+                    rest /= tree.fanout;
+                }
+                chunks.reverse();
+                format!("{key}/{}", chunks.join("/"))
+            }
+            (None, None) => key.to_string(),
+        }
+    }
+
+    /// All the keys below `key`.
+    pub fn keys(&self, key: &str) -> Vec<String> {
+        (0..self.count()).map(|i| self.key(key, i)).collect()
+    }
+
+    /// The key expressions to subscribe to for all the keys below `key`.
+    pub fn subscriptions(&self, key: &str, matching: Matching) -> Vec<String> {
+        if self.depth() == 0 {
+            return vec![key.to_string()];
+        }
+        match matching {
+            Matching::Exact => self.keys(key),
+            Matching::Star => vec![format!("{key}{}", "/*".repeat(self.depth()))],
+            Matching::DoubleStar => vec![format!("{key}/**")],
+        }
+    }
+
+    /// Labels the records with the size of the key space, if any.
+    pub fn label(&self, output: Output) -> Output {
+        match self.depth() {
+            0 => output,
+            _ => output.label("keys", self.count()),
+        }
+    }
+}
+
+/// Whether the key expression `expr` matches `key`, chunk by chunk: `*`
+/// matches any chunk and `**` any number of chunks, wildcards within a chunk
+/// are not supported.
+pub fn matches(expr: &str, key: &str) -> bool {
+    let expr: Vec<&str> = expr.split('/').collect();
+    let key: Vec<&str> = key.split('/').collect();
+    matches_chunks(&expr, &key)
+}
+
+fn matches_chunks(expr: &[&str], key: &[&str]) -> bool {
+    match (expr.first(), key.first()) {
+        (None, None) => true,
+        (Some(&"**"), _) => {
+            matches_chunks(&expr[1..], key) || (!key.is_empty() && matches_chunks(expr, &key[1..]))
+        }
+        (Some(e), Some(k)) => (*e == "*" || e == k) && matches_chunks(&expr[1..], &key[1..]),
+        _ => false,
+    }
+}
+
+/// A tree of keys of the given depth, every chunk having `fanout` children.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tree {
+    pub depth: usize,
+    pub fanout: usize,
+    // The number of keys, i.e. its leaves
+    count: usize,
+}
+
+impl FromStr for Tree {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid tree {s}, expect DEPTH:FANOUT, e.g. 3:10");
+        let (depth, fanout) = s.split_once(':').ok_or_else(invalid)?;
+        let depth = depth.parse::<usize>().map_err(|_| invalid())?;
+        let fanout = fanout.parse::<usize>().map_err(|_| invalid())?;
+        if depth == 0 || fanout == 0 {
+            return Err(invalid());
+        }
+        let count = u32::try_from(depth)
+            .ok()
+            .and_then(|depth| fanout.checked_pow(depth))
+            .ok_or_else(|| format!("Invalid tree {s}, too many keys"))?;
+        Ok(Tree {
+            depth,
+            fanout,
+            count,
+        })
+    }
+}
+
+/// How the subscribers match the generated keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Matching {
+    /// a subscriber per key
+    #[default]
+    Exact,
+    /// a single subscriber with a `*` wildcard per chunk
+    Star,
+    /// a single subscriber with a `**` wildcard
+    DoubleStar,
+}
+
+impl FromStr for Matching {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exact" => Ok(Matching::Exact),
+            "star" => Ok(Matching::Star),
+            "double-star" => Ok(Matching::DoubleStar),
+            _ => Err(format!(
+                "Unsupported matching {s}, expect exact, star or double-star"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Matching {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Matching::Exact => write!(f, "exact"),
+            Matching::Star => write!(f, "star"),
+            Matching::DoubleStar => write!(f, "double-star"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Debug, Parser)]
+    struct Opt {
+        #[clap(flatten)]
+        keys: KeysOpt,
+    }
+
+    fn opt(args: &[&str]) -> Result<KeysOpt, clap::Error> {
+        Opt::try_parse_from(std::iter::once("test").chain(args.iter().copied())).map(|opt| opt.keys)
+    }
+
+    #[test]
+    fn keys_are_numbered_below_the_key() {
+        let keys = opt(&["--keys", "3"]).unwrap();
+        assert_eq!(keys.count(), 3);
+        assert_eq!(
+            keys.keys("test/thr"),
+            ["test/thr/0", "test/thr/1", "test/thr/2"]
+        );
+
+        let keys = opt(&[]).unwrap();
+        assert_eq!(keys.count(), 1);
+        assert_eq!(keys.keys("test/thr"), ["test/thr"]);
+
+        assert!(opt(&["--keys", "0"]).is_err());
+        assert!(opt(&["--keys", "1", "--tree", "2:2"]).is_err());
+    }
+
+    #[test]
+    fn trees_number_every_chunk() {
+        let keys = opt(&["--tree", "2:3"]).unwrap();
+        assert_eq!(keys.count(), 9);
+        assert_eq!(keys.key("test/thr", 0), "test/thr/0/0");
+        assert_eq!(keys.key("test/thr", 5), "test/thr/1/2");
+        assert_eq!(keys.key("test/thr", 8), "test/thr/2/2");
+
+        assert!("0:10".parse::<Tree>().is_err());
+        assert!("3:0".parse::<Tree>().is_err());
+        assert!("3".parse::<Tree>().is_err());
+        assert!(format!("64:{}", usize::MAX).parse::<Tree>().is_err());
+        assert!(format!("{}:2", u64::MAX).parse::<Tree>().is_err());
+    }
+
+    #[test]
+    fn subscriptions_cover_all_the_keys() {
+        let keys = opt(&["--tree", "2:3"]).unwrap();
+        for matching in [Matching::Exact, Matching::Star, Matching::DoubleStar] {
+            let subscriptions = keys.subscriptions("test/thr", matching);
+            for key in keys.keys("test/thr") {
+                assert!(subscriptions.iter().any(|expr| matches(expr, &key)));
+            }
+        }
+        assert_eq!(keys.subscriptions("test/thr", Matching::Exact).len(), 9);
+        assert_eq!(
+            keys.subscriptions("test/thr", Matching::Star),
+            ["test/thr/*/*"]
+        );
+        assert_eq!(
+            keys.subscriptions("test/thr", Matching::DoubleStar),
+            ["test/thr/**"]
+        );
+
+        // The key of the tool alone is subscribed to as is
+        let keys = opt(&[]).unwrap();
+        assert_eq!(keys.subscriptions("test/thr", Matching::Star), ["test/thr"]);
+    }
+
+    #[test]
+    fn wildcards_match_whole_chunks() {
+        assert!(matches("test/thr", "test/thr"));
+        assert!(!matches("test/thr", "test/thr/0"));
+        assert!(matches("test/*", "test/thr"));
+        assert!(!matches("test/*", "test/thr/0"));
+        assert!(matches("test/**", "test/thr/0/1"));
+        assert!(matches("test/**", "test"));
+        assert!(matches("**/1", "test/thr/0/1"));
+        assert!(!matches("test/t*", "test/thr"));
+    }
+}
@@ -0,0 +1,69 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+//! Building blocks shared by the zenoh-perf binaries.
+pub mod header;
+pub mod integrity;
+pub mod keys;
+#[cfg(feature = "zenoh")]
+pub mod link;
+#[cfg(feature = "zenoh")]
+pub mod multicast;
+#[cfg(feature = "zenoh")]
+pub mod opt;
+pub mod output;
+#[cfg(feature = "zenoh")]
+pub mod priority;
+pub mod producers;
+#[cfg(feature = "zenoh")]
+pub mod qos;
+pub mod rate;
+pub mod raw;
+pub mod run;
+pub mod sequence;
+#[cfg(feature = "shared-memory")]
+pub mod shm;
+pub mod stall;
+pub mod stats;
+pub mod sweep;
+#[cfg(feature = "stats")]
+pub mod wire;
+
+pub use header::{parse_payload, Header, HeaderError, Payload};
+pub use integrity::Integrity;
+pub use keys::{matches, KeysOpt, Matching, Tree};
+#[cfg(feature = "zenoh")]
+pub use link::LinkOpt;
+#[cfg(feature = "zenoh")]
+pub use multicast::{MulticastHandler, MulticastOpt, Transport};
+#[cfg(feature = "zenoh")]
+pub use opt::CommonOpt;
+pub use output::{Output, OutputFormat, Record, Sink};
+#[cfg(feature = "zenoh")]
+pub use priority::{priority_name, Flow, Priorities};
+pub use producers::{parse_publishers, Meters, Producers};
+#[cfg(feature = "zenoh")]
+pub use qos::{CongestionOpt, ReliabilityOpt};
+pub use rate::{Pacer, Pattern, RateOpt};
+pub use raw::{RawAddr, RawListener, RawStream};
+pub use run::{parse_secs, Run, RunOpt, Warmup};
+pub use sequence::{Counts, Losses, Sequences};
+#[cfg(feature = "shared-memory")]
+pub use shm::{Segment, ShmOpt};
+pub use stall::Stalls;
+pub use stats::{Histogram, Summaries, Summary, Throughput};
+pub use sweep::{Meter, Sizes, SweepOpt};
+#[cfg(feature = "stats")]
+pub use wire::Wire;
+#[cfg(feature = "zenoh")]
+pub use zenoh_config::{EndPoint, WhatAmI};
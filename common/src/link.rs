@@ -0,0 +1,86 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+use crate::output::Output;
+use clap::Args;
+use zenoh::config::{Config, ValidatedMap};
+
+// The transmission queues of the priorities in the configuration
+const QUEUES: [&str; 8] = [
+    "control",
+    "real_time",
+    "interactive_high",
+    "interactive_low",
+    "data_high",
+    "data",
+    "data_low",
+    "background",
+];
+
+/// Options tuning how the links batch the messages, on the publishers, which
+/// would otherwise need a configuration file.
+///
+/// Meant to be embedded in a binary's own options with `#[clap(flatten)]`.
+#[derive(Debug, Clone, Args)]
+pub struct LinkOpt {
+    /// maximum size of the batches of messages written on the links (bytes)
+    #[clap(long)]
+    pub batch_size: Option<u16>,
+
+    /// number of batches in the transmission queue of every priority
+    #[clap(long)]
+    pub queue_size: Option<usize>,
+
+    /// how long the transmission queues back off when there is nothing to send (ns)
+    #[clap(long)]
+    pub backoff: Option<u64>,
+}
+
+impl LinkOpt {
+    /// Overrides the settings of `config`, from the command line or from a
+    /// configuration file, with the given options.
+    pub fn apply(&self, mut config: Config) -> Config {
+        if let Some(batch_size) = self.batch_size {
+            config
+                .insert_json5("transport/link/tx/batch_size", &batch_size.to_string())
+                .unwrap();
+        }
+        if let Some(queue_size) = self.queue_size {
+            for queue in QUEUES {
+                let key = format!("transport/link/tx/queue/size/{queue}");
+                config.insert_json5(&key, &queue_size.to_string()).unwrap();
+            }
+        }
+        if let Some(backoff) = self.backoff {
+            config
+                .insert_json5("transport/link/tx/queue/backoff", &backoff.to_string())
+                .unwrap();
+        }
+        config
+    }
+
+    /// Labels the records with the given options.
+    pub fn label(&self, output: Output) -> Output {
+        let mut output = output;
+        if let Some(batch_size) = self.batch_size {
+            output = output.label("batch_size", batch_size);
+        }
+        if let Some(queue_size) = self.queue_size {
+            output = output.label("queue_size", queue_size);
+        }
+        if let Some(backoff) = self.backoff {
+            output = output.label("backoff", backoff);
+        }
+        output
+    }
+}
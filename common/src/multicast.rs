@@ -0,0 +1,151 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+use crate::{opt::CommonOpt, output::Output};
+use clap::Args;
+use std::{any::Any, sync::Arc};
+use zenoh::{
+    config::Config,
+    net::{
+        protocol::proto::ZenohMessage,
+        transport::{
+            TransportManager, TransportMulticast, TransportMulticastEventHandler, TransportPeer,
+            TransportPeerEventHandler, TransportUnicast,
+        },
+    },
+};
+use zenoh_config::EndPoint;
+use zenoh_core::Result as ZResult;
+
+/// Option joining multicast groups, on the transport-level tools.
+///
+/// Meant to be embedded in a binary's own options with `#[clap(flatten)]`,
+/// next to the [`CommonOpt`].
+#[derive(Debug, Clone, Args)]
+pub struct MulticastOpt {
+    /// which multicast groups to join, e.g. --multicast udp/224.0.0.224:7447
+    #[clap(long, value_delimiter = ',')]
+    pub multicast: Vec<EndPoint>,
+}
+
+impl MulticastOpt {
+    /// Builds the zenoh [`Config`] of `common`, the endpoints may be left out
+    /// when the tool joins multicast groups.
+    pub fn config(&self, common: &CommonOpt) -> Config {
+        if self.multicast.is_empty() {
+            common.config()
+        } else {
+            common.build()
+        }
+    }
+
+    /// Joins every multicast group with `manager`.
+    pub async fn join(&self, manager: &TransportManager) -> Vec<TransportMulticast> {
+        let mut transports = vec![];
+        for e in self.multicast.iter() {
+            let t = manager.open_transport_multicast(e.clone()).await.unwrap();
+            transports.push(t);
+        }
+        transports
+    }
+
+    /// Joins the first multicast group, or connects to the first endpoint of
+    /// `common` without any, for the tools sending on a single transport.
+    pub async fn open(&self, manager: &TransportManager, common: &CommonOpt) -> Transport {
+        match self.multicast.first() {
+            Some(e) => {
+                let t = manager.open_transport_multicast(e.clone()).await.unwrap();
+                t.into()
+            }
+            None => {
+                let e = common
+                    .connect_endpoints()
+                    .into_iter()
+                    .next()
+                    .expect("Missing --connect or --multicast endpoint");
+                let t = manager.open_transport_unicast(e).await.unwrap();
+                t.into()
+            }
+        }
+    }
+
+    /// Labels the records with `multicast=true` when joining groups.
+    pub fn label(&self, output: Output) -> Output {
+        match self.multicast.is_empty() {
+            true => output,
+            false => output.label("multicast", true),
+        }
+    }
+}
+
+/// A transport the messages are sent on, to a peer or to a multicast group.
+#[derive(Clone)]
+pub enum Transport {
+    Unicast(TransportUnicast),
+    Multicast(TransportMulticast),
+}
+
+impl Transport {
+    pub fn handle_message(&self, message: ZenohMessage) -> ZResult<()> {
+        match self {
+            Transport::Unicast(t) => t.handle_message(message),
+            Transport::Multicast(t) => t.handle_message(message),
+        }
+    }
+}
+
+impl From<TransportUnicast> for Transport {
+    fn from(transport: TransportUnicast) -> Self {
+        Transport::Unicast(transport)
+    }
+}
+
+impl From<TransportMulticast> for Transport {
+    fn from(transport: TransportMulticast) -> Self {
+        Transport::Multicast(transport)
+    }
+}
+
+type NewPeer = dyn Fn(TransportPeer) -> Arc<dyn TransportPeerEventHandler> + Send + Sync;
+
+/// Handler of a multicast group, the messages of every peer of the group are
+/// handled as if they came from a unicast peer.
+pub struct MulticastHandler {
+    new_peer: Box<NewPeer>,
+}
+
+impl MulticastHandler {
+    /// The handler calling `new_peer` for every peer joining the group.
+    pub fn new<F, H>(new_peer: F) -> Self
+    where
+        F: Fn(TransportPeer) -> H + Send + Sync + 'static,
+        H: TransportPeerEventHandler + 'static,
+    {
+        Self {
+            new_peer: Box::new(move |peer| Arc::new(new_peer(peer))),
+        }
+    }
+}
+
+impl TransportMulticastEventHandler for MulticastHandler {
+    fn new_peer(&self, peer: TransportPeer) -> ZResult<Arc<dyn TransportPeerEventHandler>> {
+        Ok((self.new_peer)(peer))
+    }
+
+    fn closing(&self) {}
+    fn closed(&self) {}
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
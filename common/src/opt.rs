@@ -0,0 +1,102 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+use clap::Args;
+use std::path::PathBuf;
+use zenoh::config::Config;
+use zenoh_config::{EndPoint, WhatAmI};
+
+/// Options shared by every tool that opens a zenoh session or runtime.
+///
+/// Meant to be embedded in a binary's own options with `#[clap(flatten)]`.
+#[derive(Debug, Clone, Args)]
+pub struct CommonOpt {
+    /// peer, router, or client
+    #[clap(short, long)]
+    pub mode: WhatAmI,
+
+    /// which endpoints to listen on, e.g. --listen tcp/127.0.0.1:7447,tcp/127.0.0.1:7448
+    #[clap(short, long, value_delimiter = ',')]
+    pub listen: Vec<EndPoint>,
+
+    /// which endpoints to connect to, e.g. --connect tcp/127.0.0.1:7447,tcp/127.0.0.1:7448
+    #[clap(short, long, value_delimiter = ',')]
+    pub connect: Vec<EndPoint>,
+
+    /// configuration file (json5 or yaml)
+    #[clap(long = "conf", value_parser)]
+    pub config: Option<PathBuf>,
+
+    /// former endpoints option, listened on by the peers and the routers and
+    /// connected to by the clients, prefer --listen and --connect
+    #[clap(short, long, value_delimiter = ',', hide = true)]
+    pub endpoint: Vec<EndPoint>,
+}
+
+impl CommonOpt {
+    /// Builds the zenoh [`Config`] described by the options.
+    ///
+    /// The configuration file, if any, is loaded first and the command line
+    /// overrides the mode and adds the endpoints on top of it. Multicast
+    /// scouting is always disabled so that only the given endpoints are used.
+    /// Peers and routers both listen and connect, clients can only connect.
+    pub fn config(&self) -> Config {
+        if self.listen.is_empty()
+            && self.connect.is_empty()
+            && self.endpoint.is_empty()
+            && self.config.is_none()
+        {
+            panic!(
+                "Either --listen or --connect needs to be specified, see --help for more details"
+            );
+        }
+        self.build()
+    }
+
+    // The configuration without checking that there are endpoints, e.g.
+    // for the tools only joining multicast groups
+    pub(crate) fn build(&self) -> Config {
+        let mut config = match &self.config {
+            Some(path) => Config::from_file(path).unwrap(),
+            None => Config::default(),
+        };
+        config.set_mode(Some(self.mode)).unwrap();
+        config.scouting.multicast.set_enabled(Some(false)).unwrap();
+        config.listen.endpoints.extend(self.listen_endpoints());
+        config.connect.endpoints.extend(self.connect_endpoints());
+        config
+    }
+
+    /// The endpoints to listen on, along with the --endpoint ones of the
+    /// peers and the routers.
+    pub fn listen_endpoints(&self) -> Vec<EndPoint> {
+        match self.mode {
+            WhatAmI::Peer | WhatAmI::Router => [&self.listen[..], &self.endpoint[..]].concat(),
+            WhatAmI::Client => {
+                if !self.listen.is_empty() {
+                    panic!("Unsupported option --listen in client mode");
+                }
+                vec![]
+            }
+        }
+    }
+
+    /// The endpoints to connect to, along with the --endpoint ones of the
+    /// clients.
+    pub fn connect_endpoints(&self) -> Vec<EndPoint> {
+        match self.mode {
+            WhatAmI::Peer | WhatAmI::Router => self.connect.clone(),
+            WhatAmI::Client => [&self.connect[..], &self.endpoint[..]].concat(),
+        }
+    }
+}
@@ -0,0 +1,433 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+use std::{
+    collections::HashSet,
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex, Once},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A single measurement produced by one of the tools.
+#[derive(Debug, Clone)]
+pub struct Record {
+    /// name of the binary, e.g. z_sub_thr
+    pub tool: String,
+    /// layer of the stack being measured, e.g. session, transport, router, link, kafka
+    pub layer: String,
+    /// what is measured, e.g. throughput or latency.sequential
+    pub metric: String,
+    /// payload size (bytes)
+    pub payload: usize,
+    pub value: f64,
+    /// unit of the value, e.g. msg/s, bit/s or us
+    pub unit: String,
+    pub timestamp: SystemTime,
+    pub labels: Vec<(String, String)>,
+}
+
+impl Record {
+    fn timestamp_ms(&self) -> u128 {
+        self.timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis()
+    }
+}
+
+/// Something able to persist [`Record`]s.
+pub trait Sink: Send + Sync {
+    fn write(&self, record: &Record);
+}
+
+/// Comma separated values, preceded by a header line.
+pub struct Csv;
+
+impl Csv {
+    const HEADER: &'static str = "tool,layer,metric,payload,value,unit,timestamp,labels";
+
+    fn field(text: &str) -> String {
+        if text.contains([',', '"', '\n']) {
+            format!("\"{}\"", text.replace('"', "\"\""))
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn line(record: &Record) -> String {
+        let labels = record
+            .labels
+            .iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect::<Vec<_>>()
+            .join(";");
+        format!(
+            "{},{},{},{},{},{},{},{}",
+            Self::field(&record.tool),
+            Self::field(&record.layer),
+            Self::field(&record.metric),
+            record.payload,
+            record.value,
+            Self::field(&record.unit),
+            record.timestamp_ms(),
+            Self::field(&labels),
+        )
+    }
+}
+
+impl Sink for Csv {
+    fn write(&self, record: &Record) {
+        static HEADER: Once = Once::new();
+        HEADER.call_once(|| println!("{}", Self::HEADER));
+        println!("{}", Self::line(record));
+    }
+}
+
+/// One JSON object per line.
+pub struct JsonLines;
+
+impl Sink for JsonLines {
+    fn write(&self, record: &Record) {
+        let labels: serde_json::Map<String, serde_json::Value> = record
+            .labels
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone().into()))
+            .collect();
+        let json = serde_json::json!({
+            "tool": record.tool,
+            "layer": record.layer,
+            "metric": record.metric,
+            "payload": record.payload,
+            "value": record.value,
+            "unit": record.unit,
+            "timestamp": record.timestamp_ms() as u64,
+            "labels": labels,
+        });
+        println!("{json}");
+    }
+}
+
+/// Prometheus text exposition format, one sample per line, each metric
+/// preceded by its `# TYPE` line the first time it is written.
+#[derive(Default)]
+pub struct Prometheus {
+    // The metric names whose type was already written
+    typed: Mutex<HashSet<String>>,
+}
+
+impl Prometheus {
+    // The labels written for the fields of every record
+    const RESERVED: [&'static str; 4] = ["tool", "layer", "payload", "unit"];
+
+    /// Turns `text` into a valid metric or label name: `[a-zA-Z_][a-zA-Z0-9_]*`.
+    fn sanitize(text: &str) -> String {
+        let mut name: String = text
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            name.insert(0, '_');
+        }
+        name
+    }
+
+    fn name(metric: &str) -> String {
+        format!("zenoh_perf_{}", Self::sanitize(metric))
+    }
+
+    // A record label named as one of the reserved ones is prefixed, rather
+    // than written twice
+    fn label(key: &str) -> String {
+        let name = Self::sanitize(key);
+        if Self::RESERVED.contains(&name.as_str()) {
+            format!("label_{name}")
+        } else {
+            name
+        }
+    }
+
+    fn escape(value: &str) -> String {
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    }
+
+    // The sample of a record, preceded by the `# TYPE` line of its metric
+    // the first time
+    fn lines(&self, record: &Record) -> Vec<String> {
+        let mut labels = vec![
+            format!("tool=\"{}\"", Self::escape(&record.tool)),
+            format!("layer=\"{}\"", Self::escape(&record.layer)),
+            format!("payload=\"{}\"", record.payload),
+            format!("unit=\"{}\"", Self::escape(&record.unit)),
+        ];
+        labels.extend(
+            record
+                .labels
+                .iter()
+                .map(|(k, v)| format!("{}=\"{}\"", Self::label(k), Self::escape(v))),
+        );
+        let name = Self::name(&record.metric);
+        let mut lines = vec![];
+        if self.typed.lock().unwrap().insert(name.clone()) {
+            lines.push(format!("# TYPE {name} gauge"));
+        }
+        lines.push(format!(
+            "{}{{{}}} {} {}",
+            name,
+            labels.join(","),
+            record.value,
+            record.timestamp_ms()
+        ));
+        lines
+    }
+}
+
+impl Sink for Prometheus {
+    fn write(&self, record: &Record) {
+        for line in self.lines(record) {
+            println!("{line}");
+        }
+    }
+}
+
+/// Keeps the records written to it, for the tests of the modules emitting
+/// records.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct Records(pub(crate) std::sync::Mutex<Vec<Record>>);
+
+#[cfg(test)]
+impl Records {
+    /// An output writing to new records.
+    pub(crate) fn output() -> (Arc<Records>, Output) {
+        let records = Arc::new(Records::default());
+        let output = Output::with_sink(records.clone(), "test", "test");
+        (records, output)
+    }
+
+    /// The value of every record with a `key` label, by this label.
+    pub(crate) fn by(&self, key: &str) -> Vec<(String, f64)> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|record| {
+                let (_, label) = record.labels.iter().find(|(k, _)| k == key)?;
+                Some((label.clone(), record.value))
+            })
+            .collect()
+    }
+
+    /// The values of the records of `metric`.
+    pub(crate) fn values(&self, metric: &str) -> Vec<f64> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|record| record.metric == metric)
+            .map(|record| record.value)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+impl Sink for Records {
+    fn write(&self, record: &Record) {
+        self.0.lock().unwrap().push(record.clone());
+    }
+}
+
+/// The formats selectable with `--output`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    Jsonl,
+    Prom,
+}
+
+impl OutputFormat {
+    pub fn sink(&self) -> Arc<dyn Sink> {
+        match self {
+            OutputFormat::Csv => Arc::new(Csv),
+            OutputFormat::Jsonl => Arc::new(JsonLines),
+            OutputFormat::Prom => Arc::new(Prometheus::default()),
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            "prom" => Ok(OutputFormat::Prom),
+            _ => Err(format!(
+                "Unsupported output format {s}, expect csv, jsonl or prom"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputFormat::Csv => write!(f, "csv"),
+            OutputFormat::Jsonl => write!(f, "jsonl"),
+            OutputFormat::Prom => write!(f, "prom"),
+        }
+    }
+}
+
+/// The per-tool handle used to emit [`Record`]s to a [`Sink`].
+///
+/// The tool name, the layer and the labels common to all the records of a
+/// run are given once, each measurement only brings its own values.
+#[derive(Clone)]
+pub struct Output {
+    sink: Arc<dyn Sink>,
+    tool: String,
+    layer: String,
+    labels: Vec<(String, String)>,
+}
+
+impl Output {
+    pub fn new(format: OutputFormat, tool: &str, layer: &str) -> Self {
+        Self::with_sink(format.sink(), tool, layer)
+    }
+
+    pub fn with_sink(sink: Arc<dyn Sink>, tool: &str, layer: &str) -> Self {
+        Self {
+            sink,
+            tool: tool.to_string(),
+            layer: layer.to_string(),
+            labels: vec![],
+        }
+    }
+
+    /// Adds a label attached to every record emitted from now on.
+    pub fn label<V: ToString>(mut self, key: &str, value: V) -> Self {
+        self.labels.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn emit(&self, metric: &str, payload: usize, value: f64, unit: &str) {
+        self.emit_with(metric, payload, value, unit, &[]);
+    }
+
+    /// Like [`Output::emit`] with some extra labels for this record only.
+    pub fn emit_with(
+        &self,
+        metric: &str,
+        payload: usize,
+        value: f64,
+        unit: &str,
+        labels: &[(&str, String)],
+    ) {
+        let mut all = self.labels.clone();
+        all.extend(labels.iter().map(|(k, v)| (k.to_string(), v.clone())));
+        self.sink.write(&Record {
+            tool: self.tool.clone(),
+            layer: self.layer.clone(),
+            metric: metric.to_string(),
+            payload,
+            value,
+            unit: unit.to_string(),
+            timestamp: SystemTime::now(),
+            labels: all,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn record(metric: &str, labels: &[(&str, &str)]) -> Record {
+        Record {
+            tool: "z_sub_thr".to_string(),
+            layer: "session".to_string(),
+            metric: metric.to_string(),
+            payload: 64,
+            value: 1.5,
+            unit: "msg/s".to_string(),
+            timestamp: UNIX_EPOCH + Duration::from_millis(42),
+            labels: labels
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        let line = Csv::line(&record("throughput", &[("name", "a")]));
+        assert_eq!(line, "z_sub_thr,session,throughput,64,1.5,msg/s,42,name=a");
+
+        let line = Csv::line(&record("a,b", &[("name", "say \"hi\""), ("n", "1")]));
+        assert_eq!(
+            line,
+            "z_sub_thr,session,\"a,b\",64,1.5,msg/s,42,\"name=say \"\"hi\"\";n=1\""
+        );
+    }
+
+    #[test]
+    fn prometheus_names_are_sanitized() {
+        assert_eq!(
+            Prometheus::name("latency.sequential"),
+            "zenoh_perf_latency_sequential"
+        );
+        assert_eq!(Prometheus::sanitize("9lives"), "_9lives");
+        assert_eq!(Prometheus::sanitize("pull-period"), "pull_period");
+        assert_eq!(Prometheus::sanitize("_ok1"), "_ok1");
+    }
+
+    #[test]
+    fn prometheus_label_values_are_escaped() {
+        let prometheus = Prometheus::default();
+        let lines = prometheus.lines(&record("pull.rtt", &[("name", "a\\b\"c\nd")]));
+        assert_eq!(
+            lines,
+            [
+                "# TYPE zenoh_perf_pull_rtt gauge",
+                "zenoh_perf_pull_rtt{tool=\"z_sub_thr\",layer=\"session\",payload=\"64\",\
+                 unit=\"msg/s\",name=\"a\\\\b\\\"c\\nd\"} 1.5 42"
+            ]
+        );
+    }
+
+    #[test]
+    fn prometheus_types_every_metric_once() {
+        let prometheus = Prometheus::default();
+        assert_eq!(prometheus.lines(&record("throughput", &[])).len(), 2);
+        assert_eq!(prometheus.lines(&record("throughput", &[])).len(), 1);
+        let lines = prometheus.lines(&record("latency", &[]));
+        assert_eq!(lines[0], "# TYPE zenoh_perf_latency gauge");
+        assert_eq!(prometheus.lines(&record("latency", &[])).len(), 1);
+    }
+
+    #[test]
+    fn prometheus_reserved_labels_are_prefixed() {
+        let prometheus = Prometheus::default();
+        let lines = prometheus.lines(&record("frames", &[("unit", "x"), ("tool", "y")]));
+        assert!(lines[1].contains(",unit=\"msg/s\","));
+        assert!(lines[1].contains(",label_unit=\"x\",label_tool=\"y\"}"));
+        assert_eq!(lines[1].matches("unit=").count(), 2);
+    }
+}
@@ -0,0 +1,232 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+use crate::{
+    output::Output,
+    rate::parse_rate,
+    run::Run,
+    stats::{Summaries, Throughput},
+    sweep::{Meter, Sizes},
+};
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use zenoh::prelude::Priority;
+
+// The priorities from the highest to the lowest, indexed by their value
+const PRIORITIES: [(Priority, &str); 8] = [
+    (Priority::Control, "control"),
+    (Priority::RealTime, "real-time"),
+    (Priority::InteractiveHigh, "interactive-high"),
+    (Priority::InteractiveLow, "interactive-low"),
+    (Priority::DataHigh, "data-high"),
+    (Priority::Data, "data"),
+    (Priority::DataLow, "data-low"),
+    (Priority::Background, "background"),
+];
+
+/// The name of a priority on the command line and in the labels.
+pub fn priority_name(priority: Priority) -> &'static str {
+    PRIORITIES[priority as usize].1
+}
+
+fn parse_priority(s: &str) -> Result<Priority, String> {
+    PRIORITIES
+        .iter()
+        .find(|(_, name)| *name == s)
+        .map(|(priority, _)| *priority)
+        .ok_or_else(|| {
+            let names: Vec<_> = PRIORITIES.iter().map(|(_, name)| *name).collect();
+            format!("Unsupported priority {s}, expect {}", names.join(", "))
+        })
+}
+
+/// A flow of messages sent on a priority, e.g. `real-time:1000:64` for 1000
+/// msg/s of 64 bytes.
+///
+/// Without rate or payload size, the flow takes the ones of the publisher.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Flow {
+    pub priority: Priority,
+    pub rate: Option<f64>,
+    pub payload: Option<usize>,
+}
+
+impl FromStr for Flow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split(':');
+        let priority = parse_priority(fields.next().unwrap_or_default())?;
+        let rate = match fields.next() {
+            None | Some("") => None,
+            Some(rate) => Some(parse_rate(rate).map_err(|e| format!("{e} in flow {s}"))?),
+        };
+        let payload = match fields.next() {
+            None | Some("") => None,
+            Some(payload) => match payload.parse::<Sizes>()?.0[..] {
+                [size] => Some(size),
+                _ => return Err(format!("Invalid payload size {payload} in flow {s}")),
+            },
+        };
+        if fields.next().is_some() {
+            return Err(format!(
+                "Invalid flow {s}, expect PRIORITY[:RATE[:PAYLOAD]], e.g. real-time:1000:64"
+            ));
+        }
+        Ok(Flow {
+            priority,
+            rate,
+            payload,
+        })
+    }
+}
+
+// The messages received on one priority
+struct Lane {
+    meter: Meter,
+    // Sum and maximum of the latencies since the last interval, with the
+    // number of messages giving one
+    latency_sum: AtomicU64,
+    latency_max: AtomicU64,
+    latency_count: AtomicU64,
+    throughput: Throughput,
+    latency: Summaries,
+}
+
+/// The throughput and the latency of the messages received on every
+/// priority, reported with a `priority` label.
+pub struct Priorities {
+    lanes: Vec<Lane>,
+}
+
+impl Priorities {
+    pub fn new(run: &Arc<Run>) -> Self {
+        let lanes = PRIORITIES
+            .iter()
+            .map(|_| Lane {
+                meter: Meter::new(),
+                latency_sum: AtomicU64::new(0),
+                latency_max: AtomicU64::new(0),
+                latency_count: AtomicU64::new(0),
+                throughput: Throughput::new(run.clone()),
+                latency: Summaries::new(run.clone()),
+            })
+            .collect();
+        Self { lanes }
+    }
+
+    /// Accounts for one message of `payload` bytes received on `priority`,
+    /// with its latency when it carries a [`Header`](crate::Header).
+    pub fn add(&self, priority: Priority, payload: usize, latency: Option<Duration>) {
+        let lane = &self.lanes[priority as usize];
+        lane.meter.add(payload);
+        if let Some(latency) = latency {
+            let latency = latency.as_micros() as u64;
+            lane.latency_sum.fetch_add(latency, Ordering::Relaxed);
+            lane.latency_max.fetch_max(latency, Ordering::Relaxed);
+            lane.latency_count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Emits the throughput of every priority over the last `elapsed`
+    /// microseconds, then the mean and the maximum latency of its messages.
+    ///
+    /// The summary of the latency is made of the means of the intervals,
+    /// keeping every sample of a saturating flow would not fit in memory.
+    pub fn record(&self, output: &Output, elapsed: f64) {
+        for ((_, name), lane) in PRIORITIES.iter().zip(&self.lanes) {
+            let count = lane.latency_count.swap(0, Ordering::Relaxed);
+            let sum = lane.latency_sum.swap(0, Ordering::Relaxed);
+            let max = lane.latency_max.swap(0, Ordering::Relaxed);
+            // Skip the intervals without messages or mixing two payload sizes
+            let (payload, c) = match lane.meter.take() {
+                Some((payload, c)) if c > 0 => (payload, c),
+                _ => continue,
+            };
+            let labels = [("priority", name.to_string())];
+            let value = (c as f64 * 1_000_000.0 / elapsed).floor();
+            lane.throughput
+                .record(output, "throughput", payload, value, &labels);
+            if count > 0 {
+                let mean = sum as f64 / count as f64;
+                lane.latency
+                    .record(output, "latency", payload, mean, "us", &labels);
+                output.emit_with("latency.max", payload, max as f64, "us", &labels);
+            }
+        }
+    }
+
+    /// Emits the summaries of the throughput and of the latency of every
+    /// priority which received messages.
+    pub fn emit(&self, output: &Output) {
+        for ((_, name), lane) in PRIORITIES.iter().zip(&self.lanes) {
+            let output = output.clone().label("priority", name);
+            lane.throughput.emit(&output, "throughput");
+            lane.latency.emit(&output, "latency", "us");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flow(s: &str) -> Result<(Priority, Option<f64>, Option<usize>), String> {
+        s.parse::<Flow>()
+            .map(|flow| (flow.priority, flow.rate, flow.payload))
+    }
+
+    #[test]
+    fn flows_take_an_optional_rate_and_payload() {
+        assert_eq!(
+            flow("real-time:1000:64"),
+            Ok((Priority::RealTime, Some(1000.0), Some(64)))
+        );
+        assert_eq!(flow("background"), Ok((Priority::Background, None, None)));
+        assert_eq!(flow("data:500"), Ok((Priority::Data, Some(500.0), None)));
+        assert_eq!(flow("data::1K"), Ok((Priority::Data, None, Some(1 << 10))));
+        assert_eq!(flow("data-low::"), Ok((Priority::DataLow, None, None)));
+    }
+
+    #[test]
+    fn malformed_flows_are_rejected() {
+        for s in [
+            "",
+            "bogus",
+            "Data",
+            "data:x",
+            "data:0",
+            "data:-1",
+            "data:1:x",
+            "data:1:8,64",
+            "data:1:8..64",
+            "data:1:2:3",
+        ] {
+            assert!(flow(s).is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn priorities_are_named_by_value() {
+        for (priority, name) in PRIORITIES {
+            assert_eq!(priority_name(priority), name);
+            assert_eq!(parse_priority(name), Ok(priority));
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+use crate::{
+    header::Header,
+    output::Output,
+    run::Run,
+    stats::{self, Summaries, Summary, Throughput},
+    sweep::Meter,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// Parses the number of publishers of a process, see [`Header::MAX_PRODUCERS`].
+pub fn parse_publishers(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(count) if (1..=Header::MAX_PRODUCERS).contains(&count) => Ok(count),
+        _ => Err(format!(
+            "Invalid number of publishers {s}, expect 1 to {}",
+            Header::MAX_PRODUCERS
+        )),
+    }
+}
+
+fn producer_id(index: usize) -> u32 {
+    Header::producer_id(index).unwrap_or_else(|e| panic!("{e}"))
+}
+
+/// The meters of the publishers or of the subscribers of a process, whose
+/// throughput is reported one by one and in aggregate.
+pub struct Meters {
+    label: &'static str,
+    meters: Vec<(u32, Arc<Meter>)>,
+    names: Vec<String>,
+    each: Vec<Arc<Throughput>>,
+    aggregate: Arc<Throughput>,
+    spread: Arc<Summaries>,
+}
+
+impl Meters {
+    fn new(run: &Arc<Run>, label: &'static str, ids: Vec<u32>, names: Vec<String>) -> Self {
+        assert!(!ids.is_empty(), "At least one {label} is required");
+        Self {
+            label,
+            names,
+            each: ids.iter().map(|_| run.throughput()).collect(),
+            meters: ids
+                .into_iter()
+                .map(|id| (id, Arc::new(Meter::new())))
+                .collect(),
+            aggregate: run.throughput(),
+            spread: run.summaries(),
+        }
+    }
+
+    /// The meters of `count` publishers, labelled with their producer id.
+    pub fn publishers(run: &Arc<Run>, count: usize) -> Self {
+        let ids: Vec<u32> = (0..count).map(producer_id).collect();
+        let names = ids.iter().map(u32::to_string).collect();
+        Self::new(run, "producer", ids, names)
+    }
+
+    /// The meters of publishers each sending a flow on its own priority,
+    /// labelled with the given priority names.
+    pub fn flows(run: &Arc<Run>, priorities: Vec<String>) -> Self {
+        let ids = (0..priorities.len()).map(producer_id).collect();
+        Self::new(run, "priority", ids, priorities)
+    }
+
+    /// The meters of `count` subscribers, labelled with their index.
+    pub fn subscribers(run: &Arc<Run>, count: usize) -> Self {
+        let ids: Vec<u32> = (0..count as u32).collect();
+        let names = ids.iter().map(u32::to_string).collect();
+        Self::new(run, "subscriber", ids, names)
+    }
+
+    /// The id and the meter of the `index`-th publisher or subscriber.
+    pub fn get(&self, index: usize) -> (u32, Arc<Meter>) {
+        let (id, meter) = &self.meters[index];
+        (*id, meter.clone())
+    }
+
+    /// Emits the throughput over the last `elapsed` microseconds of every
+    /// meter, with a label when there are several of them, then in aggregate
+    /// along with the spread between the slowest and the fastest one.
+    ///
+    /// Returns the payload size of the interval, `None` without messages or
+    /// when it mixed several payload sizes of a sweep.
+    pub fn record(&self, output: &Output, elapsed: f64) -> Option<usize> {
+        let mut values = vec![];
+        let mut payloads = vec![];
+        let each = self.meters.iter().zip(&self.names).zip(&self.each);
+        for (((_, meter), name), summaries) in each {
+            let (payload, c) = match meter.take() {
+                Some(taken) => taken,
+                None => {
+                    payloads.push(None);
+                    continue;
+                }
+            };
+            let value = (c as f64 * 1_000_000.0 / elapsed).floor();
+            if self.meters.len() > 1 && c > 0 {
+                let labels = [(self.label, name.clone())];
+                summaries.record(output, "throughput", payload, value, &labels);
+            }
+            // A meter without messages has no payload size yet
+            if c > 0 {
+                payloads.push(Some(payload));
+            }
+            values.push(value);
+        }
+
+        // Skip the intervals mixing several payload sizes of a sweep
+        let payload = match payloads[..] {
+            [Some(payload), ..] if payloads.iter().all(|p| *p == Some(payload)) => payload,
+            _ => return None,
+        };
+        let total: f64 = values.iter().sum();
+        self.aggregate
+            .record(output, "throughput", payload, total, &[]);
+        if self.meters.len() > 1 {
+            let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = values.iter().copied().fold(0.0, f64::max);
+            self.spread.record(
+                output,
+                "throughput.spread",
+                payload,
+                max - min,
+                "msg/s",
+                &[],
+            );
+        }
+        Some(payload)
+    }
+
+    /// Emits the summaries of every meter, of the aggregate and of the spread.
+    pub fn emit(&self, output: &Output) {
+        if self.meters.len() > 1 {
+            for (name, summaries) in self.names.iter().zip(&self.each) {
+                let output = output.clone().label(self.label, name);
+                summaries.emit(&output, "throughput");
+            }
+        }
+        self.aggregate.emit(output, "throughput");
+        if self.meters.len() > 1 {
+            self.spread.emit(output, "throughput.spread", "msg/s");
+        }
+    }
+
+    /// The payload size of the run, 0 when it swept several sizes.
+    pub fn payload(&self) -> usize {
+        self.aggregate.payload()
+    }
+}
+
+/// Counts the messages received from every producer, identified by the
+/// producer id of the payload [`Header`](crate::Header).
+///
+/// The producers seen once are kept for the rest of the run, so that one
+/// starved afterwards still counts in the fairness with a share of 0.
+#[derive(Default)]
+pub struct Producers {
+    counts: Mutex<HashMap<u32, usize>>,
+}
+
+impl Producers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&self, producer_id: u32) {
+        *self.counts.lock().unwrap().entry(producer_id).or_default() += 1;
+    }
+
+    /// Takes the messages counted per producer since the last call, 0 for
+    /// the producers seen before but silent since.
+    pub fn take(&self) -> Vec<(u32, usize)> {
+        let mut counts: Vec<_> = self
+            .counts
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .map(|(producer_id, c)| (*producer_id, std::mem::take(c)))
+            .collect();
+        counts.sort_unstable();
+        counts
+    }
+
+    /// Emits the throughput of every producer, in `msg/s` and `bit/s`, over the last `elapsed`
+    /// microseconds with a `producer` label, then the fairness index of
+    /// their shares which is also recorded in `summary`.
+    ///
+    /// Nothing is emitted without `payload`, e.g. for the intervals mixing
+    /// several payload sizes of a sweep, nor when no producer sent anything.
+    pub fn record(&self, output: &Output, payload: Option<usize>, elapsed: f64, summary: &Summary) {
+        let counts = self.take();
+        let payload = match payload {
+            Some(payload) if counts.iter().any(|(_, c)| *c > 0) => payload,
+            _ => return,
+        };
+        for (producer_id, c) in counts.iter() {
+            let value = (*c as f64 * 1_000_000.0 / elapsed).floor();
+            let labels = [("producer", producer_id.to_string())];
+            output.emit_with("throughput", payload, value, "msg/s", &labels);
+            let bits = stats::bits(payload, value);
+            output.emit_with("throughput", payload, bits, "bit/s", &labels);
+        }
+        let counts: Vec<usize> = counts.into_iter().map(|(_, c)| c).collect();
+        summary.record(output, "fairness", payload, fairness(&counts), "index", &[]);
+    }
+}
+
+/// Jain's fairness index of the shares: 1 when they are all equal, down to
+/// 1/n when a single one gets everything.
+pub fn fairness(shares: &[usize]) -> f64 {
+    let sum: f64 = shares.iter().map(|s| *s as f64).sum();
+    let squares: f64 = shares.iter().map(|s| (*s as f64).powi(2)).sum();
+    if squares == 0.0 {
+        return 1.0;
+    }
+    sum * sum / (shares.len() as f64 * squares)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fairness_of_equal_shares_is_one() {
+        assert_eq!(fairness(&[5, 5, 5, 5]), 1.0);
+        assert_eq!(fairness(&[]), 1.0);
+        assert_eq!(fairness(&[0, 0]), 1.0);
+    }
+
+    #[test]
+    fn fairness_of_a_single_share_is_one_over_n() {
+        assert_eq!(fairness(&[10, 0, 0, 0]), 0.25);
+        assert!((fairness(&[3, 1]) - 0.8).abs() < 1e-12);
+    }
+
+    #[test]
+    fn silent_producers_keep_a_null_share() {
+        let producers = Producers::new();
+        producers.add(1);
+        producers.add(2);
+        producers.add(2);
+        assert_eq!(producers.take(), vec![(1, 1), (2, 2)]);
+
+        producers.add(2);
+        let counts = producers.take();
+        assert_eq!(counts, vec![(1, 0), (2, 1)]);
+        let shares: Vec<usize> = counts.into_iter().map(|(_, c)| c).collect();
+        assert_eq!(fairness(&shares), 0.5);
+    }
+}
@@ -0,0 +1,79 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+use crate::output::Output;
+use clap::Args;
+use zenoh::prelude::{CongestionControl, Reliability};
+
+/// Option selecting the reliability of the messages, on the publishers of
+/// the transport-level tools and on the subscribers.
+///
+/// Meant to be embedded in a binary's own options with `#[clap(flatten)]`.
+#[derive(Debug, Clone, Args)]
+pub struct ReliabilityOpt {
+    /// reliability of the messages: reliable or best-effort
+    #[clap(long, default_value = "reliable", value_parser = parse_reliability)]
+    pub reliability: Reliability,
+}
+
+impl ReliabilityOpt {
+    /// Labels the records with the reliability unless it is the default one.
+    pub fn label(&self, output: Output) -> Output {
+        match self.reliability {
+            Reliability::Reliable => output,
+            Reliability::BestEffort => output.label("reliability", "best-effort"),
+        }
+    }
+}
+
+/// Option selecting what a publisher does when its transmission queues are
+/// full.
+///
+/// Meant to be embedded in a binary's own options with `#[clap(flatten)]`.
+#[derive(Debug, Clone, Args)]
+pub struct CongestionOpt {
+    /// what to do when the transmission queues are full: block or drop
+    #[clap(long, default_value = "block", value_parser = parse_congestion)]
+    pub congestion: CongestionControl,
+}
+
+impl CongestionOpt {
+    /// Labels the records with the congestion control unless it is the
+    /// default one.
+    pub fn label(&self, output: Output) -> Output {
+        match self.congestion {
+            CongestionControl::Block => output,
+            CongestionControl::Drop => output.label("congestion", "drop"),
+        }
+    }
+}
+
+fn parse_reliability(s: &str) -> Result<Reliability, String> {
+    match s {
+        "reliable" => Ok(Reliability::Reliable),
+        "best-effort" => Ok(Reliability::BestEffort),
+        _ => Err(format!(
+            "Unsupported reliability {s}, expect reliable or best-effort"
+        )),
+    }
+}
+
+fn parse_congestion(s: &str) -> Result<CongestionControl, String> {
+    match s {
+        "block" => Ok(CongestionControl::Block),
+        "drop" => Ok(CongestionControl::Drop),
+        _ => Err(format!(
+            "Unsupported congestion control {s}, expect block or drop"
+        )),
+    }
+}
@@ -0,0 +1,408 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+use crate::{output::Output, run::parse_secs};
+use clap::{error::ErrorKind, ArgMatches, Args, Command, FromArgMatches};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+// Below this delay, the pacer spins instead of sleeping: the timers of the
+// OS and of async-std are not precise enough for high rates
+const SPIN_THRESHOLD: Duration = Duration::from_millis(1);
+
+/// Options controlling the offered load of a publisher.
+///
+/// Meant to be embedded in a binary's own options with `#[clap(flatten)]`.
+/// Without `--rate` the publisher sends as fast as it can.
+#[derive(Debug, Clone)]
+pub struct RateOpt {
+    pub rate: Option<f64>,
+    pub pattern: Pattern,
+    pub on: Duration,
+    pub off: Duration,
+}
+
+// The arguments of `RateOpt`, checked together once parsed
+#[derive(Args)]
+struct RateArgs {
+    /// offered load (msg/s), publish as fast as possible if not given
+    #[clap(long, value_parser = parse_rate)]
+    rate: Option<f64>,
+
+    /// arrival pattern of the messages at the given rate: constant, poisson or onoff
+    #[clap(long, default_value_t)]
+    pattern: Pattern,
+
+    /// length of the bursts of the onoff pattern (seconds)
+    #[clap(long, default_value = "1", value_parser = parse_on)]
+    on: Duration,
+
+    /// length of the silences between the bursts of the onoff pattern (seconds)
+    #[clap(long, default_value = "1", value_parser = parse_secs)]
+    off: Duration,
+}
+
+/// Parses an offered load, a positive number of messages per second.
+pub(crate) fn parse_rate(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+        _ => Err(format!(
+            "Invalid rate {s}, expect a positive number of msg/s"
+        )),
+    }
+}
+
+// A burst of the onoff pattern must let some messages through
+fn parse_on(s: &str) -> Result<Duration, String> {
+    match parse_secs(s) {
+        Ok(on) if !on.is_zero() => Ok(on),
+        _ => Err(format!(
+            "Invalid duration {s}, expect a positive number of seconds"
+        )),
+    }
+}
+
+impl RateOpt {
+    /// Checks that a burst of the onoff pattern holds at least one message
+    /// at `rate`, the count of messages of a burst being rounded down.
+    pub fn check(&self, rate: f64) -> Result<(), String> {
+        if self.pattern == Pattern::OnOff && (self.on.as_secs_f64() * rate) < 1.0 {
+            return Err(format!(
+                "--on {}s holds no message at {rate} msg/s, expect at least {}s",
+                self.on.as_secs_f64(),
+                1.0 / rate
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn pacer(&self) -> Pacer {
+        Pacer::new(self.rate, self.pattern, self.on, self.off)
+    }
+
+    /// Labels the records with the offered load, if any.
+    pub fn label(&self, output: Output) -> Output {
+        match self.rate {
+            Some(rate) => output.label("rate", rate).label("pattern", self.pattern),
+            None => output,
+        }
+    }
+}
+
+impl FromArgMatches for RateOpt {
+    fn from_arg_matches(matches: &ArgMatches) -> Result<Self, clap::Error> {
+        let args = RateArgs::from_arg_matches(matches)?;
+        let opt = Self {
+            rate: args.rate,
+            pattern: args.pattern,
+            on: args.on,
+            off: args.off,
+        };
+        match opt.rate.map_or(Ok(()), |rate| opt.check(rate)) {
+            Ok(()) => Ok(opt),
+            Err(e) => Err(clap::Error::raw(ErrorKind::ValueValidation, e)),
+        }
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &ArgMatches) -> Result<(), clap::Error> {
+        *self = Self::from_arg_matches(matches)?;
+        Ok(())
+    }
+}
+
+impl Args for RateOpt {
+    fn augment_args(cmd: Command) -> Command {
+        RateArgs::augment_args(cmd)
+    }
+
+    fn augment_args_for_update(cmd: Command) -> Command {
+        RateArgs::augment_args_for_update(cmd)
+    }
+}
+
+/// How the messages are spread in time at a given rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Pattern {
+    /// evenly spaced messages
+    #[default]
+    Constant,
+    /// exponentially distributed inter-arrival times
+    Poisson,
+    /// evenly spaced messages during bursts separated by silences
+    OnOff,
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "constant" => Ok(Pattern::Constant),
+            "poisson" => Ok(Pattern::Poisson),
+            "onoff" => Ok(Pattern::OnOff),
+            _ => Err(format!(
+                "Unsupported pattern {s}, expect constant, poisson or onoff"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Constant => write!(f, "constant"),
+            Pattern::Poisson => write!(f, "poisson"),
+            Pattern::OnOff => write!(f, "onoff"),
+        }
+    }
+}
+
+/// An open-loop scheduler of the messages of a publisher.
+///
+/// The send time of every message is an absolute deadline computed from
+/// the start of the pacing, so neither the time spent sending nor the
+/// imprecision of the timers accumulate into a drift. A publisher falling
+/// behind schedule sends without waiting until it catches up.
+pub struct Pacer {
+    start: Instant,
+    rate: Option<f64>,
+    pattern: Pattern,
+    on: Duration,
+    off: Duration,
+    sent: u64,
+    // Offset of the next message from the start, in seconds of "on" time
+    offset: f64,
+    // Draws the inter-arrival times of the poisson pattern
+    rng: StdRng,
+}
+
+impl Pacer {
+    pub fn new(rate: Option<f64>, pattern: Pattern, on: Duration, off: Duration) -> Self {
+        if let Some(rate) = rate {
+            assert!(rate > 0.0, "The rate must be positive");
+        }
+        if pattern == Pattern::OnOff {
+            assert!(!on.is_zero(), "The on period must not be zero");
+        }
+        Self {
+            start: Instant::now(),
+            rate,
+            pattern,
+            on,
+            off,
+            sent: 0,
+            offset: 0.0,
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    /// The deadline of the next message, `None` when not rate limited.
+    fn next_deadline(&mut self) -> Option<Instant> {
+        let rate = self.rate?;
+        let offset = match self.pattern {
+            Pattern::Poisson => {
+                let offset = self.offset;
+                // 1 - U is in (0, 1], so the logarithm is finite
+                self.offset += -(1.0 - self.rng.gen::<f64>()).ln() / rate;
+                offset
+            }
+            // Computed from the message count to avoid accumulating rounding errors
+            Pattern::Constant | Pattern::OnOff => self.sent as f64 / rate,
+        };
+        self.sent += 1;
+
+        let offset = Duration::from_secs_f64(offset);
+        let offset = match self.pattern {
+            // In nanoseconds, a count of bursts overflowing u32 for short bursts
+            Pattern::OnOff => {
+                let on = self.on.as_nanos();
+                let offset = offset.as_nanos();
+                let bursts = offset / on;
+                let nanos = bursts
+                    .checked_mul(on + self.off.as_nanos())
+                    .and_then(|nanos| nanos.checked_add(offset % on))
+                    .and_then(|nanos| u64::try_from(nanos).ok());
+                Duration::from_nanos(nanos.unwrap_or(u64::MAX))
+            }
+            Pattern::Constant | Pattern::Poisson => offset,
+        };
+        Some(self.start + offset)
+    }
+
+    /// Waits until the next message is due.
+    pub async fn wait(&mut self) {
+        let deadline = match self.next_deadline() {
+            Some(deadline) => deadline,
+            None => return,
+        };
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            if left > SPIN_THRESHOLD {
+                async_std::task::sleep(left - SPIN_THRESHOLD).await;
+            } else {
+                async_std::task::yield_now().await;
+            }
+        }
+    }
+
+    /// Like [`Pacer::wait`] for the tools not running in an async context.
+    pub fn wait_blocking(&mut self) {
+        let deadline = match self.next_deadline() {
+            Some(deadline) => deadline,
+            None => return,
+        };
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            if left > SPIN_THRESHOLD {
+                std::thread::sleep(left - SPIN_THRESHOLD);
+            } else {
+                std::hint::spin_loop();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns_parse_as_displayed() {
+        for pattern in [Pattern::Constant, Pattern::Poisson, Pattern::OnOff] {
+            assert_eq!(pattern.to_string().parse(), Ok(pattern));
+        }
+        assert_eq!("onoff".parse(), Ok(Pattern::OnOff));
+        assert!("on-off".parse::<Pattern>().is_err());
+        assert!("".parse::<Pattern>().is_err());
+    }
+
+    #[test]
+    fn constant_deadlines_are_evenly_spaced() {
+        let mut pacer = Pacer::new(
+            Some(10.0),
+            Pattern::Constant,
+            Duration::ZERO,
+            Duration::ZERO,
+        );
+        let start = pacer.start;
+        let offsets: Vec<Duration> = (0..3)
+            .map(|_| pacer.next_deadline().unwrap() - start)
+            .collect();
+        assert_eq!(offsets, [0, 100, 200].map(Duration::from_millis).to_vec());
+    }
+
+    #[test]
+    fn onoff_deadlines_skip_the_silences() {
+        let on = Duration::from_millis(200);
+        let off = Duration::from_millis(800);
+        let mut pacer = Pacer::new(Some(10.0), Pattern::OnOff, on, off);
+        let start = pacer.start;
+        let offsets: Vec<Duration> = (0..4)
+            .map(|_| pacer.next_deadline().unwrap() - start)
+            .collect();
+        assert_eq!(
+            offsets,
+            [0, 100, 1000, 1100].map(Duration::from_millis).to_vec()
+        );
+    }
+
+    #[test]
+    fn onoff_deadlines_count_many_short_bursts() {
+        // Beyond u32::MAX bursts of 1ns
+        let on = Duration::from_nanos(1);
+        let off = Duration::from_nanos(1);
+        let mut pacer = Pacer::new(Some(1e9), Pattern::OnOff, on, off);
+        pacer.sent = 5_000_000_000;
+        let deadline = pacer.next_deadline().unwrap() - pacer.start;
+        assert_eq!(deadline, Duration::from_secs(10));
+    }
+
+    #[test]
+    fn poisson_deadlines_average_the_rate() {
+        let mut pacer = Pacer::new(
+            Some(1000.0),
+            Pattern::Poisson,
+            Duration::ZERO,
+            Duration::ZERO,
+        );
+        pacer.rng = StdRng::seed_from_u64(7);
+        let start = pacer.start;
+        let n = 100_000;
+        let deadlines: Vec<Duration> = (0..=n)
+            .map(|_| pacer.next_deadline().unwrap() - start)
+            .collect();
+        assert_eq!(deadlines[0], Duration::ZERO);
+        assert!(deadlines.windows(2).all(|w| w[0] <= w[1]));
+        // The mean inter-arrival time is 1ms, within 1% over 100k messages
+        let mean = deadlines[n].as_secs_f64() / n as f64;
+        assert!((mean - 0.001).abs() < 0.00001, "{mean}");
+    }
+
+    fn rate(args: &[&str]) -> Result<RateOpt, ErrorKind> {
+        use clap::Parser;
+        #[derive(Parser)]
+        struct Opt {
+            #[clap(flatten)]
+            rate: RateOpt,
+        }
+        Opt::try_parse_from(std::iter::once("test").chain(args.iter().copied()))
+            .map(|opt| opt.rate)
+            .map_err(|e| e.kind())
+    }
+
+    #[test]
+    fn bursts_hold_at_least_one_message() {
+        assert!(rate(&["--rate", "10", "--pattern", "onoff", "--on", "0.1"]).is_ok());
+        assert_eq!(
+            rate(&["--rate", "10", "--pattern", "onoff", "--on", "0.05"]).unwrap_err(),
+            ErrorKind::ValueValidation
+        );
+        // Only the onoff pattern has bursts
+        assert!(rate(&["--rate", "10", "--on", "0.05"]).is_ok());
+        assert!(rate(&["--pattern", "onoff", "--on", "0.05"]).is_ok());
+    }
+
+    #[test]
+    fn unlimited_pacers_have_no_deadline() {
+        let mut pacer = Pacer::new(None, Pattern::Poisson, Duration::ZERO, Duration::ZERO);
+        assert_eq!(pacer.next_deadline(), None);
+    }
+
+    #[test]
+    fn rates_are_positive() {
+        assert_eq!(parse_rate("1000"), Ok(1000.0));
+        assert_eq!(parse_rate("0.5"), Ok(0.5));
+        for rate in ["0", "-10", "inf", "NaN", "fast"] {
+            assert!(parse_rate(rate).is_err(), "{rate}");
+        }
+    }
+
+    #[test]
+    fn bursts_are_positive_and_silences_non_negative() {
+        assert_eq!(parse_on("0.5"), Ok(Duration::from_millis(500)));
+        assert!(parse_on("0").is_err());
+        assert!(parse_on("-1").is_err());
+        assert_eq!(parse_secs("0"), Ok(Duration::ZERO));
+        assert!(parse_secs("-1").is_err());
+    }
+}
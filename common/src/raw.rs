@@ -0,0 +1,243 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+use async_std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+};
+#[cfg(unix)]
+use async_std::{
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+};
+use std::{
+    fmt,
+    pin::Pin,
+    str::FromStr,
+    task::{Context, Poll},
+};
+
+const TCP: &str = "tcp/";
+const UNIXSOCK_STREAM: &str = "unixsock-stream/";
+
+/// The address of the raw stream tools (t_sink_tcp, t_source_tcp), in the
+/// syntax of the zenoh endpoints: `tcp/127.0.0.1:7447`, or a bare socket
+/// address, and `unixsock-stream//tmp/zenoh.sock` on unix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RawAddr {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl RawAddr {
+    /// The protocol of the address, as in the zenoh endpoints.
+    pub fn protocol(&self) -> &'static str {
+        match self {
+            RawAddr::Tcp(_) => "tcp",
+            #[cfg(unix)]
+            RawAddr::Unix(_) => "unixsock-stream",
+        }
+    }
+}
+
+impl FromStr for RawAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix(UNIXSOCK_STREAM) {
+            #[cfg(unix)]
+            return match path.is_empty() {
+                true => Err(format!("Missing socket path in {s:?}")),
+                false => Ok(RawAddr::Unix(path.into())),
+            };
+            #[cfg(not(unix))]
+            return Err(format!("Unix sockets are not supported: {path:?}"));
+        }
+        let addr = s.strip_prefix(TCP).unwrap_or(s);
+        addr.parse()
+            .map(RawAddr::Tcp)
+            .map_err(|e| format!("Invalid address {s:?}: {e}"))
+    }
+}
+
+impl fmt::Display for RawAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RawAddr::Tcp(addr) => write!(f, "{TCP}{addr}"),
+            #[cfg(unix)]
+            RawAddr::Unix(path) => write!(f, "{UNIXSOCK_STREAM}{}", path.display()),
+        }
+    }
+}
+
+/// A listener accepting the [`RawStream`]s of the clients, the socket file of
+/// a unix listener is removed when it is dropped.
+pub enum RawListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf, usize),
+}
+
+impl RawListener {
+    pub async fn bind(addr: &RawAddr) -> io::Result<Self> {
+        match addr {
+            RawAddr::Tcp(addr) => Ok(RawListener::Tcp(TcpListener::bind(addr).await?)),
+            #[cfg(unix)]
+            RawAddr::Unix(path) => {
+                let listener = UnixListener::bind(path).await?;
+                Ok(RawListener::Unix(listener, path.clone(), 0))
+            }
+        }
+    }
+
+    /// Accepts a client, along with a name telling it apart from the others:
+    /// its address over TCP, the path and the number of the connection over
+    /// a unix socket, whose clients are unnamed.
+    pub async fn accept(&mut self) -> io::Result<(RawStream, String)> {
+        match self {
+            RawListener::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                Ok((RawStream::Tcp(stream), addr.to_string()))
+            }
+            #[cfg(unix)]
+            RawListener::Unix(listener, path, clients) => {
+                let (stream, _) = listener.accept().await?;
+                *clients += 1;
+                let name = format!("{}#{clients}", path.display());
+                Ok((RawStream::Unix(stream), name))
+            }
+        }
+    }
+}
+
+impl Drop for RawListener {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let RawListener::Unix(_, path, _) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// A connected TCP or unix stream.
+#[derive(Clone)]
+pub enum RawStream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl RawStream {
+    pub async fn connect(addr: &RawAddr) -> io::Result<Self> {
+        match addr {
+            RawAddr::Tcp(addr) => Ok(RawStream::Tcp(TcpStream::connect(addr).await?)),
+            #[cfg(unix)]
+            RawAddr::Unix(path) => Ok(RawStream::Unix(UnixStream::connect(path).await?)),
+        }
+    }
+}
+
+impl Read for RawStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            RawStream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            RawStream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl Write for RawStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            RawStream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            RawStream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            RawStream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            RawStream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            RawStream::Tcp(stream) => Pin::new(stream).poll_close(cx),
+            #[cfg(unix)]
+            RawStream::Unix(stream) => Pin::new(stream).poll_close(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tcp_addresses_take_an_optional_prefix() {
+        let addr: SocketAddr = "127.0.0.1:7447".parse().unwrap();
+        assert_eq!("tcp/127.0.0.1:7447".parse(), Ok(RawAddr::Tcp(addr)));
+        assert_eq!("127.0.0.1:7447".parse(), Ok(RawAddr::Tcp(addr)));
+        let addr: SocketAddr = "[::1]:7447".parse().unwrap();
+        assert_eq!("tcp/[::1]:7447".parse(), Ok(RawAddr::Tcp(addr)));
+        assert_eq!(RawAddr::Tcp(addr).protocol(), "tcp");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_addresses_take_a_path() {
+        let addr: RawAddr = "unixsock-stream//tmp/zenoh.sock".parse().unwrap();
+        assert_eq!(addr, RawAddr::Unix("/tmp/zenoh.sock".into()));
+        assert_eq!(addr.protocol(), "unixsock-stream");
+        assert!("unixsock-stream/".parse::<RawAddr>().is_err());
+    }
+
+    #[test]
+    fn addresses_parse_as_displayed() {
+        let mut addrs = vec!["tcp/127.0.0.1:7447", "tcp/[::1]:7447"];
+        if cfg!(unix) {
+            addrs.push("unixsock-stream//tmp/zenoh.sock");
+        }
+        for s in addrs {
+            assert_eq!(s.parse::<RawAddr>().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn malformed_addresses_are_rejected() {
+        for s in [
+            "",
+            "tcp/",
+            "localhost:7447",
+            "tcp/127.0.0.1",
+            "tcp/127.0.0.1:port",
+            "udp/127.0.0.1:7447",
+            "tcp/tcp/127.0.0.1:7447",
+        ] {
+            assert!(s.parse::<RawAddr>().is_err(), "{s}");
+        }
+    }
+}
@@ -0,0 +1,268 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+use crate::{
+    output::Output,
+    stats::{Summaries, Summary, Throughput},
+};
+use clap::Args;
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Once,
+    },
+    time::{Duration, Instant},
+};
+
+static STOP: AtomicBool = AtomicBool::new(false);
+static HANDLER: Once = Once::new();
+
+// How often the waiting functions check whether the run is over
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Options bounding the length of a run.
+///
+/// Meant to be embedded in a binary's own options with `#[clap(flatten)]`.
+/// Without any of them the run only ends on SIGINT or SIGTERM.
+#[derive(Debug, Clone, Default, Args)]
+pub struct RunOpt {
+    /// stop after the given number of seconds
+    #[clap(long, value_parser = parse_secs)]
+    pub duration: Option<Duration>,
+
+    /// stop after the given number of messages (sent or received)
+    #[clap(long)]
+    pub count: Option<u64>,
+
+    /// leave out of the summary the first seconds (e.g. 5s) or messages (e.g. 1000) of the run
+    #[clap(long)]
+    pub warmup: Option<Warmup>,
+}
+
+impl RunOpt {
+    pub fn start(&self) -> Arc<Run> {
+        Run::new(self.duration, self.count, self.warmup)
+    }
+}
+
+/// Parses a non-negative number of seconds, e.g. 10 or 0.5, meant to be
+/// the `value_parser` of a duration option.
+pub fn parse_secs(s: &str) -> Result<Duration, String> {
+    s.parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| format!("Invalid duration {s}, expect a non-negative number of seconds"))
+}
+
+/// The beginning of a run whose measurements are not representative, e.g.
+/// because of the connection setup or the declarations propagation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Warmup {
+    Duration(Duration),
+    Messages(u64),
+}
+
+impl FromStr for Warmup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err =
+            || format!("Invalid warm-up {s}, expect seconds (e.g. 5s) or messages (e.g. 1000)");
+        match s.strip_suffix('s') {
+            Some(secs) => secs
+                .parse::<f64>()
+                .ok()
+                .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                .map(Warmup::Duration)
+                .ok_or_else(err),
+            None => s.parse().map(Warmup::Messages).map_err(|_| err()),
+        }
+    }
+}
+
+impl fmt::Display for Warmup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warmup::Duration(duration) => write!(f, "{}s", duration.as_secs_f64()),
+            Warmup::Messages(messages) => write!(f, "{messages}"),
+        }
+    }
+}
+
+/// The state of a bounded run, shared between the sending or receiving
+/// tasks and the one waiting for its end.
+pub struct Run {
+    start: Instant,
+    duration: Option<Duration>,
+    count: Option<u64>,
+    warmup: Option<Warmup>,
+    messages: AtomicU64,
+    stopped: AtomicBool,
+}
+
+impl Run {
+    /// Starts a run, the first one also installs the SIGINT/SIGTERM handler.
+    ///
+    /// A first signal stops the run gracefully, a second one exits at once.
+    /// The duration and count limits include the warm-up.
+    pub fn new(
+        duration: Option<Duration>,
+        count: Option<u64>,
+        warmup: Option<Warmup>,
+    ) -> Arc<Self> {
+        HANDLER.call_once(|| {
+            ctrlc::set_handler(|| {
+                if STOP.swap(true, Ordering::Relaxed) {
+                    std::process::exit(130);
+                }
+            })
+            .unwrap();
+        });
+        Arc::new(Self {
+            start: Instant::now(),
+            duration,
+            count,
+            warmup,
+            messages: AtomicU64::new(0),
+            stopped: AtomicBool::new(false),
+        })
+    }
+
+    /// Accounts for `n` more messages and tells whether the run goes on.
+    pub fn add(&self, n: u64) -> bool {
+        self.messages.fetch_add(n, Ordering::Relaxed);
+        self.is_running()
+    }
+
+    /// Accounts for one more message and tells whether the run goes on.
+    pub fn tick(&self) -> bool {
+        self.add(1)
+    }
+
+    pub fn is_running(&self) -> bool {
+        if STOP.load(Ordering::Relaxed) || self.stopped.load(Ordering::Relaxed) {
+            return false;
+        }
+        if let Some(duration) = self.duration {
+            if self.start.elapsed() >= duration {
+                return false;
+            }
+        }
+        match self.count {
+            Some(count) => self.messages() < count,
+            None => true,
+        }
+    }
+
+    /// Tells whether the run is still in its warm-up phase.
+    pub fn is_warming_up(&self) -> bool {
+        match self.warmup {
+            Some(Warmup::Duration(duration)) => self.start.elapsed() < duration,
+            Some(Warmup::Messages(messages)) => self.messages() < messages,
+            None => false,
+        }
+    }
+
+    /// A summary leaving out the samples collected during the warm-up.
+    pub fn summary(self: &Arc<Self>) -> Arc<Summary> {
+        Arc::new(Summary::with_run(self.clone()))
+    }
+
+    /// Like [`Run::summary`] with a summary per payload size.
+    pub fn summaries(self: &Arc<Self>) -> Arc<Summaries> {
+        Arc::new(Summaries::new(self.clone()))
+    }
+
+    /// Like [`Run::summaries`] for a throughput in `msg/s` and `bit/s`.
+    pub fn throughput(self: &Arc<Self>) -> Arc<Throughput> {
+        Arc::new(Throughput::new(self.clone()))
+    }
+
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+
+    pub fn messages(&self) -> u64 {
+        self.messages.load(Ordering::Relaxed)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Waits for the end of the run.
+    pub async fn wait(&self) {
+        while self.is_running() {
+            async_std::task::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Like [`Run::wait`] for the tools not running in an async context.
+    pub fn wait_blocking(&self) {
+        while self.is_running() {
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Emits the total number of messages and the length of the run.
+    pub fn emit_totals(&self, output: &Output, payload: usize) {
+        output.emit("run.messages", payload, self.messages() as f64, "msg");
+        output.emit("run.duration", payload, self.elapsed().as_secs_f64(), "s");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warmups_are_seconds_or_messages() {
+        assert_eq!("5s".parse(), Ok(Warmup::Duration(Duration::from_secs(5))));
+        assert_eq!(
+            "0.5s".parse(),
+            Ok(Warmup::Duration(Duration::from_millis(500)))
+        );
+        assert_eq!("1000".parse(), Ok(Warmup::Messages(1000)));
+        for warmup in ["", "s", "-1s", "1.5", "-1", "5m", "infs"] {
+            assert!(warmup.parse::<Warmup>().is_err(), "{warmup}");
+        }
+    }
+
+    #[test]
+    fn warmups_display_as_parsed() {
+        for warmup in ["5s", "0.5s", "1000"] {
+            assert_eq!(warmup.parse::<Warmup>().unwrap().to_string(), warmup);
+        }
+    }
+
+    #[test]
+    fn durations_are_non_negative_seconds() {
+        assert_eq!(parse_secs("10"), Ok(Duration::from_secs(10)));
+        assert_eq!(parse_secs("0"), Ok(Duration::ZERO));
+        assert!(parse_secs("-1").is_err());
+        assert!(parse_secs("10s").is_err());
+    }
+
+    #[test]
+    fn warmups_end_after_their_messages() {
+        let run = Run::new(None, Some(3), Some(Warmup::Messages(2)));
+        assert!(run.is_warming_up());
+        assert!(run.tick());
+        assert!(run.tick());
+        assert!(!run.is_warming_up());
+        assert!(!run.tick());
+        assert!(!run.is_running());
+    }
+}
@@ -0,0 +1,359 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+use crate::{header::Header, output::Output, run::Run, stats::Summaries};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::AddAssign,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+// How far behind the latest sequence number a missing message may still
+// arrive late, older gaps are forgotten and stay lost to bound the memory
+const REORDER_WINDOW: u64 = 1 << 20;
+
+/// The messages counted from the sequence numbers of the producers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counts {
+    /// messages sent by the producers, as told by their sequence numbers
+    pub expected: u64,
+    /// distinct messages received, late ones included
+    pub received: u64,
+    /// messages received more than once
+    pub duplicates: u64,
+    /// messages received after a message sent later
+    pub reordered: u64,
+    /// messages received without sequence number, too small to hold a
+    /// [`Header`], whose losses cannot be tracked
+    pub unstamped: u64,
+}
+
+impl Counts {
+    /// The messages expected but not received, late ones may make it negative
+    /// over a short interval so it saturates at 0.
+    pub fn lost(&self) -> u64 {
+        self.expected.saturating_sub(self.received)
+    }
+
+    /// The share of the expected messages which were lost (%), `None`
+    /// without any message expected, e.g. only unstamped ones.
+    pub fn loss(&self) -> Option<f64> {
+        (self.expected > 0).then(|| 100.0 * self.lost() as f64 / self.expected as f64)
+    }
+}
+
+impl AddAssign for Counts {
+    fn add_assign(&mut self, other: Self) {
+        self.expected += other.expected;
+        self.received += other.received;
+        self.duplicates += other.duplicates;
+        self.reordered += other.reordered;
+        self.unstamped += other.unstamped;
+    }
+}
+
+// The sequence numbers received from one producer
+#[derive(Default)]
+struct Stream {
+    // The sequence number expected next, `None` before the first message
+    next: Option<u64>,
+    // The sequence numbers not received yet, as [start, end) ranges by start
+    missing: BTreeMap<u64, u64>,
+    counts: Counts,
+}
+
+impl Stream {
+    fn add(&mut self, sequence: u64) {
+        // A subscriber joining late starts from the first message it receives
+        let next = self.next.unwrap_or(sequence);
+        if sequence >= next {
+            if sequence > next {
+                self.missing.insert(next, sequence);
+            }
+            self.counts.expected += sequence + 1 - next;
+            self.counts.received += 1;
+            self.next = Some(sequence + 1);
+            while let Some((_, end)) = self.missing.first_key_value() {
+                if end + REORDER_WINDOW >= sequence {
+                    break;
+                }
+                self.missing.pop_first();
+            }
+            return;
+        }
+
+        match self.missing.range(..=sequence).next_back() {
+            Some((&start, &end)) if sequence < end => {
+                self.missing.remove(&start);
+                if start < sequence {
+                    self.missing.insert(start, sequence);
+                }
+                if sequence + 1 < end {
+                    self.missing.insert(sequence + 1, end);
+                }
+                self.counts.received += 1;
+                self.counts.reordered += 1;
+            }
+            _ => self.counts.duplicates += 1,
+        }
+    }
+}
+
+/// Tracks the sequence numbers of the [`Header`] of the
+/// messages received by a subscriber, per producer, to detect the lost,
+/// duplicated and reordered messages.
+#[derive(Default)]
+pub struct Sequences {
+    streams: Mutex<HashMap<u32, Stream>>,
+    unstamped: AtomicU64,
+}
+
+impl Sequences {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&self, producer_id: u32, sequence: u64) {
+        self.streams
+            .lock()
+            .unwrap()
+            .entry(producer_id)
+            .or_default()
+            .add(sequence);
+    }
+
+    /// Forgets the sequence numbers received so far, for producers starting
+    /// over, e.g. with a new session; the counts not taken yet are kept.
+    pub fn restart(&self) {
+        for stream in self.streams.lock().unwrap().values_mut() {
+            stream.next = None;
+            stream.missing.clear();
+        }
+    }
+
+    /// Accounts for a message without sequence number, see [`Counts::unstamped`].
+    pub fn add_unstamped(&self) {
+        self.unstamped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Takes the counts of all the producers since the last call.
+    pub fn take(&self) -> Counts {
+        let mut counts = Counts {
+            unstamped: self.unstamped.swap(0, Ordering::Relaxed),
+            ..Counts::default()
+        };
+        for stream in self.streams.lock().unwrap().values_mut() {
+            counts += std::mem::take(&mut stream.counts);
+        }
+        counts
+    }
+}
+
+/// The sequence numbers tracked by the subscribers of a process, whose
+/// losses are reported every interval and over the whole run.
+pub struct Losses {
+    run: Arc<Run>,
+    sequences: Vec<Arc<Sequences>>,
+    loss: Arc<Summaries>,
+    total: Mutex<Counts>,
+    warned: AtomicBool,
+}
+
+impl Losses {
+    /// The trackers of `count` subscribers.
+    pub fn new(run: &Arc<Run>, count: usize) -> Self {
+        Self {
+            run: run.clone(),
+            sequences: (0..count).map(|_| Arc::new(Sequences::new())).collect(),
+            loss: run.summaries(),
+            total: Mutex::new(Counts::default()),
+            warned: AtomicBool::new(false),
+        }
+    }
+
+    /// The tracker of the `index`-th subscriber.
+    pub fn get(&self, index: usize) -> Arc<Sequences> {
+        self.sequences[index].clone()
+    }
+
+    /// Emits the loss (%), the duplicated and the reordered messages of
+    /// all the subscribers since the last call.
+    ///
+    /// Nothing is emitted without `payload`, e.g. for the intervals mixing
+    /// several payload sizes of a sweep, but the messages are still counted
+    /// in the totals of the run.
+    pub fn record(&self, output: &Output, payload: Option<usize>) {
+        let mut counts = Counts::default();
+        for sequences in self.sequences.iter() {
+            counts += sequences.take();
+        }
+        if counts.unstamped > 0 && !self.warned.swap(true, Ordering::Relaxed) {
+            eprintln!(
+                "WARNING: received messages of less than {} bytes, too small to carry a sequence number: their losses are not tracked, see the `unstamped` records",
+                Header::SIZE
+            );
+        }
+        if counts.expected == 0 && counts.unstamped == 0 {
+            return;
+        }
+        if !self.run.is_warming_up() {
+            *self.total.lock().unwrap() += counts;
+        }
+        let payload = match payload {
+            Some(payload) => payload,
+            None => return,
+        };
+        if let Some(loss) = counts.loss() {
+            self.loss.record(output, "loss", payload, loss, "%", &[]);
+        }
+        let phase = if self.run.is_warming_up() {
+            vec![("phase", "warmup".to_string())]
+        } else {
+            vec![]
+        };
+        output.emit_with(
+            "duplicates",
+            payload,
+            counts.duplicates as f64,
+            "msg",
+            &phase,
+        );
+        output.emit_with("reordered", payload, counts.reordered as f64, "msg", &phase);
+        if counts.unstamped > 0 {
+            output.emit_with("unstamped", payload, counts.unstamped as f64, "msg", &phase);
+        }
+    }
+
+    /// Emits the summaries of the loss, then the totals of the run after
+    /// the warm-up: the loss (%), the lost, duplicated and reordered messages,
+    /// and the messages without sequence number if any.
+    pub fn emit(&self, output: &Output) {
+        self.loss.emit(output, "loss", "%");
+        let total = *self.total.lock().unwrap();
+        let payload = self.loss.payload();
+        if total.unstamped > 0 {
+            output.emit("sequence.unstamped", payload, total.unstamped as f64, "msg");
+        }
+        let loss = match total.loss() {
+            Some(loss) => loss,
+            None => return,
+        };
+        output.emit("sequence.loss", payload, loss, "%");
+        output.emit("sequence.lost", payload, total.lost() as f64, "msg");
+        output.emit(
+            "sequence.duplicates",
+            payload,
+            total.duplicates as f64,
+            "msg",
+        );
+        output.emit("sequence.reordered", payload, total.reordered as f64, "msg");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Records;
+
+    fn counts(sequences: &[u64]) -> Counts {
+        let s = Sequences::new();
+        for &sequence in sequences {
+            s.add(0, sequence);
+        }
+        s.take()
+    }
+
+    #[test]
+    fn gaps_are_lost() {
+        let c = counts(&[0, 1, 4, 5]);
+        assert_eq!((c.expected, c.received, c.lost()), (6, 4, 2));
+    }
+
+    #[test]
+    fn late_messages_fill_their_gap() {
+        let c = counts(&[0, 2, 1, 3]);
+        assert_eq!((c.expected, c.received, c.lost()), (4, 4, 0));
+        assert_eq!((c.reordered, c.duplicates), (1, 0));
+    }
+
+    #[test]
+    fn repeated_messages_are_duplicates() {
+        let c = counts(&[0, 1, 1, 2, 0]);
+        assert_eq!((c.expected, c.received, c.lost()), (3, 3, 0));
+        assert_eq!((c.reordered, c.duplicates), (0, 2));
+    }
+
+    #[test]
+    fn producers_are_tracked_apart() {
+        let s = Sequences::new();
+        s.add(1, 10);
+        s.add(2, 0);
+        s.add(1, 12);
+        s.add(2, 1);
+        let c = s.take();
+        assert_eq!((c.expected, c.received, c.lost()), (5, 4, 1));
+    }
+
+    #[test]
+    fn unstamped_messages_are_counted_until_taken() {
+        let s = Sequences::new();
+        s.add_unstamped();
+        s.add_unstamped();
+        assert_eq!(s.take().unstamped, 2);
+        assert_eq!(s.take(), Counts::default());
+    }
+
+    #[test]
+    fn restarted_producers_start_over() {
+        let s = Sequences::new();
+        s.add(0, 100);
+        s.add(0, 102);
+        s.restart();
+        s.add(0, 0);
+        s.add(0, 1);
+        let c = s.take();
+        assert_eq!((c.expected, c.received, c.lost()), (5, 4, 1));
+        assert_eq!((c.reordered, c.duplicates), (0, 0));
+    }
+
+    #[test]
+    fn unstamped_intervals_have_no_loss() {
+        let (records, output) = Records::output();
+        let losses = Losses::new(&Run::new(None, None, None), 1);
+        losses.get(0).add_unstamped();
+        losses.record(&output, Some(8));
+        losses.emit(&output);
+        assert_eq!(records.values("unstamped"), vec![1.0]);
+        assert_eq!(records.values("sequence.unstamped"), vec![1.0]);
+        assert!(records.values("loss").is_empty());
+        assert!(records.values("sequence.loss").is_empty());
+        assert!(records.0.lock().unwrap().iter().all(|r| !r.value.is_nan()));
+    }
+
+    #[test]
+    fn losses_are_shares_of_the_expected_messages() {
+        let (records, output) = Records::output();
+        let losses = Losses::new(&Run::new(None, None, None), 1);
+        for sequence in [0, 1, 3] {
+            losses.get(0).add(0, sequence);
+        }
+        losses.record(&output, Some(8));
+        losses.emit(&output);
+        assert_eq!(records.values("loss"), vec![25.0]);
+        assert_eq!(records.values("sequence.loss"), vec![25.0]);
+        assert_eq!(records.values("sequence.lost"), vec![1.0]);
+    }
+}
@@ -0,0 +1,111 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+use crate::output::Output;
+use async_std::task;
+use clap::Args;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use zenoh::{
+    config::{Config, ValidatedMap},
+    net::protocol::io::{SharedMemoryManager, ZBuf},
+    prelude::Value,
+    Session,
+};
+
+// The segments allocated by the process, to give each of them its own name
+static SEGMENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Option exchanging the payloads through shared memory between the sessions
+/// of the same host, only available with the `shared-memory` feature.
+///
+/// Meant to be embedded in a binary's own options with `#[clap(flatten)]`.
+#[derive(Debug, Clone, Args)]
+pub struct ShmOpt {
+    /// publish the payloads from shared memory, and read them without copy
+    /// on the subscribers of the same host
+    #[clap(long)]
+    pub shm: bool,
+
+    /// size of the shared memory segment of every publisher (bytes)
+    #[clap(long, default_value_t = 64 * 1024 * 1024)]
+    pub shm_size: usize,
+}
+
+impl ShmOpt {
+    /// Enables shared memory on the transports of `config` with --shm.
+    pub fn apply(&self, mut config: Config) -> Config {
+        if self.shm {
+            config
+                .insert_json5("transport/shared_memory/enabled", "true")
+                .unwrap();
+        }
+        config
+    }
+
+    /// Allocates a segment for a publisher of `session` with --shm.
+    pub async fn segment(&self, session: &Session) -> Option<Segment> {
+        if !self.shm {
+            return None;
+        }
+        let n = SEGMENTS.fetch_add(1, Ordering::Relaxed);
+        let id = format!("{}.{}", session.id().await, n);
+        Some(Segment {
+            manager: SharedMemoryManager::make(id, self.shm_size).unwrap(),
+            size: self.shm_size,
+        })
+    }
+
+    /// Labels the records with `shm=true` with --shm.
+    pub fn label(&self, output: Output) -> Output {
+        match self.shm {
+            true => output.label("shm", true),
+            false => output,
+        }
+    }
+}
+
+/// A shared memory segment the payloads of a publisher are written to.
+pub struct Segment {
+    manager: SharedMemoryManager,
+    size: usize,
+}
+
+impl Segment {
+    /// Copies the payload made of `slices` into a buffer of the segment,
+    /// waiting for zenoh to release the buffers of the previous payloads
+    /// when it is full.
+    pub async fn value(&mut self, slices: &[&[u8]]) -> Value {
+        let len = slices.iter().map(|slice| slice.len()).sum();
+        assert!(
+            len <= self.size,
+            "The payload does not fit in the shared memory segment, raise --shm-size"
+        );
+        loop {
+            if let Some(mut sbuf) = self.manager.alloc(len) {
+                // SAFETY: the buffer was just allocated from the segment of
+                // this publisher and is not shared yet, no one else can read
+                // or write it until it is sent within the returned value.
+                let buf = unsafe { sbuf.as_mut_slice() };
+                let mut at = 0;
+                for slice in slices {
+                    buf[at..at + slice.len()].copy_from_slice(slice);
+                    at += slice.len();
+                }
+                return ZBuf::from(sbuf).into();
+            }
+            self.manager.garbage_collect();
+            self.manager.defragment();
+            task::yield_now().await;
+        }
+    }
+}
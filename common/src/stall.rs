@@ -0,0 +1,149 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+use crate::{
+    output::Output,
+    run::Run,
+    stats::{Histogram, Summaries},
+    sweep::Meter,
+};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// The time the publishers of a process spend blocked in their calls to
+/// zenoh, e.g. in `put` or `handle_message` with `CongestionControl::Block`,
+/// telling whether a throughput ceiling is on the publishers or on the network.
+pub struct Stalls {
+    publishers: usize,
+    meter: Meter,
+    // Time blocked since the last report (ns)
+    blocked: AtomicU64,
+    // Longest call since the last report (ns)
+    max: AtomicU64,
+    fraction: Arc<Summaries>,
+    latency: Histogram,
+}
+
+impl Stalls {
+    /// The stalls of `publishers` concurrent publishers.
+    pub fn new(run: &Arc<Run>, publishers: usize) -> Self {
+        Self {
+            publishers,
+            meter: Meter::new(),
+            blocked: AtomicU64::new(0),
+            max: AtomicU64::new(0),
+            fraction: run.summaries(),
+            latency: Histogram::new(run.clone()),
+        }
+    }
+
+    /// Accounts for a call publishing `payload` bytes which took `elapsed`.
+    pub fn add(&self, payload: usize, elapsed: Duration) {
+        let nanos = elapsed.as_nanos() as u64;
+        self.meter.add(payload);
+        self.blocked.fetch_add(nanos, Ordering::Relaxed);
+        self.max.fetch_max(nanos, Ordering::Relaxed);
+        self.latency.add(elapsed.as_micros() as usize);
+    }
+
+    /// Emits the share of the last `elapsed` microseconds the publishers
+    /// spent in their calls as `blocked` (%), then the longest call as
+    /// `stall.max` (us).
+    ///
+    /// Nothing is emitted for the intervals without calls or mixing several
+    /// payload sizes of a sweep.
+    pub fn record(&self, output: &Output, elapsed: f64) {
+        let blocked = self.blocked.swap(0, Ordering::Relaxed);
+        let max = self.max.swap(0, Ordering::Relaxed);
+        let payload = match self.meter.take() {
+            Some((payload, c)) if c > 0 => payload,
+            _ => return,
+        };
+        let wall = 1_000.0 * elapsed * self.publishers as f64;
+        let fraction = 100.0 * blocked as f64 / wall;
+        self.fraction
+            .record(output, "blocked", payload, fraction, "%", &[]);
+        output.emit("stall.max", payload, max as f64 / 1_000.0, "us");
+    }
+
+    /// Emits the summaries of the blocked share, then the histogram of the
+    /// duration of the calls after the warm-up, in buckets of microseconds.
+    pub fn emit(&self, output: &Output) {
+        self.fraction.emit(output, "blocked", "%");
+        self.latency
+            .emit(output, "stall", self.fraction.payload(), "call");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::Records;
+
+    #[test]
+    fn stalls_give_the_blocked_share_and_the_longest_call() {
+        let (records, output) = Records::output();
+        let stalls = Stalls::new(&Run::new(None, None, None), 2);
+        for millis in [100, 300, 50] {
+            stalls.add(64, Duration::from_millis(millis));
+        }
+        // 450ms blocked out of 2 publishers for 1s
+        stalls.record(&output, 1_000_000.0);
+        assert_eq!(records.values("blocked"), vec![22.5]);
+        assert_eq!(records.values("stall.max"), vec![300_000.0]);
+
+        // The longest call is reset at every report
+        stalls.add(64, Duration::from_micros(20));
+        stalls.record(&output, 1_000_000.0);
+        assert_eq!(records.values("stall.max"), vec![300_000.0, 20.0]);
+    }
+
+    #[test]
+    fn intervals_without_calls_are_skipped() {
+        let (records, output) = Records::output();
+        let stalls = Stalls::new(&Run::new(None, None, None), 1);
+        stalls.record(&output, 1_000_000.0);
+        stalls.add(64, Duration::from_millis(10));
+        stalls.record(&output, 1_000_000.0);
+        // The next size of a sweep starts within the interval
+        stalls.add(64, Duration::from_millis(10));
+        stalls.add(128, Duration::from_millis(10));
+        stalls.record(&output, 1_000_000.0);
+        assert_eq!(records.values("blocked"), vec![1.0]);
+        assert_eq!(records.values("stall.max"), vec![10_000.0]);
+    }
+
+    #[test]
+    fn calls_are_bucketed_in_microseconds() {
+        let (records, output) = Records::output();
+        let stalls = Stalls::new(&Run::new(None, None, None), 1);
+        for micros in [0, 3, 2, 1_000] {
+            stalls.add(64, Duration::from_micros(micros));
+        }
+        stalls.add(64, Duration::from_nanos(999));
+        stalls.emit(&output);
+        assert_eq!(
+            records.by("bucket"),
+            vec![
+                ("0".to_string(), 2.0),
+                ("2-3".to_string(), 2.0),
+                ("512-1023".to_string(), 1.0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,356 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+use crate::{output::Output, run::Run};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+/// The percentiles reported by [`Summary::emit`].
+pub const PERCENTILES: [(&str, f64); 4] =
+    [("p50", 50.0), ("p90", 90.0), ("p99", 99.0), ("p99.9", 99.9)];
+
+/// Collects the samples of a metric to summarize them at the end of a run.
+#[derive(Default)]
+pub struct Summary {
+    samples: Mutex<Vec<f64>>,
+    run: Option<Arc<Run>>,
+}
+
+impl Summary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A summary aware of the warm-up phase of `run`, see [`Summary::record`].
+    pub fn with_run(run: Arc<Run>) -> Self {
+        Self {
+            samples: Mutex::default(),
+            run: Some(run),
+        }
+    }
+
+    /// Emits a measurement and adds it to the samples.
+    ///
+    /// During the warm-up of the run, the record is tagged `phase=warmup`
+    /// and the sample is left out.
+    pub fn record(
+        &self,
+        output: &Output,
+        metric: &str,
+        payload: usize,
+        value: f64,
+        unit: &str,
+        labels: &[(&str, String)],
+    ) {
+        if self.run.as_ref().is_some_and(|run| run.is_warming_up()) {
+            let mut labels = labels.to_vec();
+            labels.push(("phase", "warmup".to_string()));
+            output.emit_with(metric, payload, value, unit, &labels);
+        } else {
+            output.emit_with(metric, payload, value, unit, labels);
+            self.add(value);
+        }
+    }
+
+    pub fn add(&self, value: f64) {
+        self.samples.lock().unwrap().push(value);
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Emits the count, mean, min, max and percentiles of the samples as
+    /// `<metric>.summary` records, each tagged with a `stat` label.
+    pub fn emit(&self, output: &Output, metric: &str, payload: usize, unit: &str) {
+        let mut samples = self.samples.lock().unwrap().clone();
+        let metric = format!("{metric}.summary");
+        output.emit_with(
+            &metric,
+            payload,
+            samples.len() as f64,
+            "samples",
+            &[("stat", "count".to_string())],
+        );
+        if samples.is_empty() {
+            return;
+        }
+        samples.sort_by(|a, b| a.total_cmp(b));
+
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        let mut stats = vec![
+            ("mean", mean),
+            ("min", samples[0]),
+            ("max", samples[samples.len() - 1]),
+        ];
+        stats.extend(
+            PERCENTILES
+                .iter()
+                .map(|(n, p)| (*n, percentile(&samples, *p))),
+        );
+        for (stat, value) in stats {
+            output.emit_with(&metric, payload, value, unit, &[("stat", stat.to_string())]);
+        }
+    }
+}
+
+/// The summaries of a metric per payload size, for the runs sweeping
+/// several sizes.
+pub struct Summaries {
+    run: Arc<Run>,
+    summaries: Mutex<BTreeMap<usize, Arc<Summary>>>,
+}
+
+impl Summaries {
+    pub fn new(run: Arc<Run>) -> Self {
+        Self {
+            run,
+            summaries: Mutex::default(),
+        }
+    }
+
+    /// The summary of the given payload size.
+    pub fn get(&self, payload: usize) -> Arc<Summary> {
+        self.summaries
+            .lock()
+            .unwrap()
+            .entry(payload)
+            .or_insert_with(|| self.run.summary())
+            .clone()
+    }
+
+    /// Like [`Summary::record`] in the summary of the given payload size.
+    pub fn record(
+        &self,
+        output: &Output,
+        metric: &str,
+        payload: usize,
+        value: f64,
+        unit: &str,
+        labels: &[(&str, String)],
+    ) {
+        self.get(payload)
+            .record(output, metric, payload, value, unit, labels);
+    }
+
+    /// Emits the summary of every payload size, see [`Summary::emit`].
+    pub fn emit(&self, output: &Output, metric: &str, unit: &str) {
+        for (payload, summary) in self.summaries.lock().unwrap().iter() {
+            summary.emit(output, metric, *payload, unit);
+        }
+    }
+
+    /// The payload size of the run, 0 when it swept several sizes.
+    pub fn payload(&self) -> usize {
+        let summaries = self.summaries.lock().unwrap();
+        match summaries.keys().collect::<Vec<_>>()[..] {
+            [payload] => *payload,
+            _ => 0,
+        }
+    }
+}
+
+/// The summaries of a throughput, both in messages and in payload bits per
+/// second, per payload size.
+pub struct Throughput {
+    messages: Summaries,
+    bits: Summaries,
+}
+
+impl Throughput {
+    pub fn new(run: Arc<Run>) -> Self {
+        Self {
+            messages: Summaries::new(run.clone()),
+            bits: Summaries::new(run),
+        }
+    }
+
+    /// Emits a message rate in `msg/s` along with the matching payload bit
+    /// rate in `bit/s`, see [`Summary::record`].
+    pub fn record(
+        &self,
+        output: &Output,
+        metric: &str,
+        payload: usize,
+        rate: f64,
+        labels: &[(&str, String)],
+    ) {
+        self.messages
+            .record(output, metric, payload, rate, "msg/s", labels);
+        self.bits.record(
+            output,
+            metric,
+            payload,
+            bits(payload, rate),
+            "bit/s",
+            labels,
+        );
+    }
+
+    /// Emits the summaries in `msg/s` then in `bit/s` of every payload size.
+    pub fn emit(&self, output: &Output, metric: &str) {
+        self.messages.emit(output, metric, "msg/s");
+        self.bits.emit(output, metric, "bit/s");
+    }
+
+    /// The payload size of the run, 0 when it swept several sizes.
+    pub fn payload(&self) -> usize {
+        self.messages.payload()
+    }
+}
+
+/// Counts the samples of a metric in power-of-two buckets: 0, 1, 2-3, 4-7...
+/// e.g. the number of messages packed in every frame.
+pub struct Histogram {
+    run: Arc<Run>,
+    buckets: Mutex<Vec<u64>>,
+}
+
+impl Histogram {
+    pub fn new(run: Arc<Run>) -> Self {
+        Self {
+            run,
+            buckets: Mutex::default(),
+        }
+    }
+
+    /// Counts a sample, unless the run is warming up.
+    pub fn add(&self, value: usize) {
+        if self.run.is_warming_up() {
+            return;
+        }
+        let bucket = (usize::BITS - value.leading_zeros()) as usize;
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() <= bucket {
+            buckets.resize(bucket + 1, 0);
+        }
+        buckets[bucket] += 1;
+    }
+
+    /// Emits the count of every non-empty bucket as `<metric>.histogram`
+    /// records, each tagged with a `bucket` label, e.g. `bucket=4-7`.
+    pub fn emit(&self, output: &Output, metric: &str, payload: usize, unit: &str) {
+        let metric = format!("{metric}.histogram");
+        for (bucket, count) in self.buckets.lock().unwrap().iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+            let label = match bucket {
+                0 => "0".to_string(),
+                1 => "1".to_string(),
+                _ => format!(
+                    "{}-{}",
+                    1usize << (bucket - 1),
+                    usize::MAX >> (usize::BITS as usize - bucket)
+                ),
+            };
+            output.emit_with(&metric, payload, *count as f64, unit, &[("bucket", label)]);
+        }
+    }
+}
+
+/// The payload bit rate of `rate` messages per second of `payload` bytes.
+pub fn bits(payload: usize, rate: f64) -> f64 {
+    8.0 * payload as f64 * rate
+}
+
+/// The nearest-rank percentile `p` (0-100) of already sorted samples.
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return f64::NAN;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{output::Records, run::Warmup};
+
+    #[test]
+    fn percentiles_are_nearest_ranks() {
+        let samples: Vec<f64> = (1..=10).map(f64::from).collect();
+        assert_eq!(percentile(&samples, 0.0), 1.0);
+        assert_eq!(percentile(&samples, 50.0), 5.0);
+        assert_eq!(percentile(&samples, 90.0), 9.0);
+        assert_eq!(percentile(&samples, 99.9), 10.0);
+        assert_eq!(percentile(&samples, 100.0), 10.0);
+        assert_eq!(percentile(&[7.0], 50.0), 7.0);
+        assert!(percentile(&[], 50.0).is_nan());
+    }
+
+    #[test]
+    fn summaries_give_the_count_mean_extremes_and_percentiles() {
+        let (records, output) = Records::output();
+        let summary = Summary::new();
+        for value in [3.0, 1.0, 4.0, 2.0] {
+            summary.add(value);
+        }
+        summary.emit(&output, "latency", 64, "us");
+        let stats = records.by("stat");
+        let stat = |name: &str| stats.iter().find(|(n, _)| n == name).unwrap().1;
+        assert_eq!(stat("count"), 4.0);
+        assert_eq!(stat("mean"), 2.5);
+        assert_eq!((stat("min"), stat("max")), (1.0, 4.0));
+        assert_eq!((stat("p50"), stat("p90"), stat("p99.9")), (2.0, 4.0, 4.0));
+    }
+
+    #[test]
+    fn empty_summaries_only_give_the_count() {
+        let (records, output) = Records::output();
+        Summary::new().emit(&output, "latency", 64, "us");
+        assert_eq!(records.by("stat"), vec![("count".to_string(), 0.0)]);
+    }
+
+    #[test]
+    fn summaries_leave_the_warmup_out() {
+        let (records, output) = Records::output();
+        let run = Run::new(None, None, Some(Warmup::Messages(1)));
+        let summary = run.summary();
+        summary.record(&output, "latency", 64, 100.0, "us", &[]);
+        run.tick();
+        summary.record(&output, "latency", 64, 10.0, "us", &[]);
+        assert_eq!(summary.len(), 1);
+        assert_eq!(records.by("phase"), vec![("warmup".to_string(), 100.0)]);
+        assert_eq!(records.values("latency"), vec![100.0, 10.0]);
+    }
+
+    #[test]
+    fn histograms_count_in_power_of_two_buckets() {
+        let (records, output) = Records::output();
+        let histogram = Histogram::new(Run::new(None, None, None));
+        for value in [0, 1, 2, 3, 4, 7, 8, usize::MAX] {
+            histogram.add(value);
+        }
+        histogram.emit(&output, "frame.messages", 0, "frames");
+        let max = format!("{}-{}", 1usize << (usize::BITS - 1), usize::MAX);
+        assert_eq!(
+            records.by("bucket"),
+            vec![
+                ("0".to_string(), 1.0),
+                ("1".to_string(), 1.0),
+                ("2-3".to_string(), 2.0),
+                ("4-7".to_string(), 2.0),
+                ("8-15".to_string(), 1.0),
+                (max, 1.0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,296 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+use crate::run::parse_secs;
+use clap::{error::ErrorKind, ArgMatches, Args, Command, FromArgMatches};
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+/// Options of a publisher sweeping one or several payload sizes.
+///
+/// Meant to be embedded in a binary's own options with `#[clap(flatten)]`,
+/// `--phase` being required as soon as `--payload` gives several sizes.
+/// `--payload` is required as well, unless a tool makes it optional with
+/// `mut_arg("payload", |arg| arg.required(false))`, e.g. when every flow of
+/// t_pub_thr sets its own payload size.
+#[derive(Debug, Clone)]
+pub struct SweepOpt {
+    pub payload: Option<Sizes>,
+    pub phase: Option<Duration>,
+}
+
+// The arguments of `SweepOpt`, checked together once parsed
+#[derive(Args)]
+struct SweepArgs {
+    /// payload size (bytes), or the sizes to publish one after the other:
+    /// a list (e.g. 8,64,1K,1M) or a range with a step (e.g. 8..1M:x2 or 1K..8K:1K)
+    #[clap(short, long, required = true)]
+    payload: Option<Sizes>,
+
+    /// how long each payload size is published when sweeping several of them (seconds)
+    #[clap(long, value_parser = parse_secs)]
+    phase: Option<Duration>,
+}
+
+impl SweepOpt {
+    fn new(payload: Option<Sizes>, phase: Option<Duration>) -> Result<Self, clap::Error> {
+        let error = |kind, message: &str| Err(clap::Error::raw(kind, message));
+        match phase {
+            Some(phase) if phase.is_zero() => {
+                error(ErrorKind::ValueValidation, "--phase must be above 0")
+            }
+            None if payload.as_ref().map_or(0, |sizes| sizes.0.len()) > 1 => error(
+                ErrorKind::MissingRequiredArgument,
+                "--phase is required to sweep several payload sizes",
+            ),
+            _ => Ok(Self { payload, phase }),
+        }
+    }
+
+    /// The payload sizes, none when `--payload` was made optional and not given.
+    pub fn sizes(&self) -> &[usize] {
+        self.payload.as_ref().map_or(&[], |sizes| &sizes.0)
+    }
+
+    /// The duration of the phase of each payload size, `None` for a single
+    /// size published until the end of the run.
+    pub fn phase(&self) -> Option<Duration> {
+        self.phase
+    }
+
+    /// The payload size of the run, 0 when it sweeps several sizes.
+    pub fn payload(&self) -> usize {
+        match self.sizes() {
+            [size] => *size,
+            _ => 0,
+        }
+    }
+}
+
+impl FromArgMatches for SweepOpt {
+    fn from_arg_matches(matches: &ArgMatches) -> Result<Self, clap::Error> {
+        let args = SweepArgs::from_arg_matches(matches)?;
+        Self::new(args.payload, args.phase)
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &ArgMatches) -> Result<(), clap::Error> {
+        *self = Self::from_arg_matches(matches)?;
+        Ok(())
+    }
+}
+
+impl Args for SweepOpt {
+    fn augment_args(cmd: Command) -> Command {
+        SweepArgs::augment_args(cmd)
+    }
+
+    fn augment_args_for_update(cmd: Command) -> Command {
+        SweepArgs::augment_args_for_update(cmd)
+    }
+}
+
+/// A non-empty list of payload sizes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sizes(pub Vec<usize>);
+
+impl Sizes {
+    // A size in bytes with an optional K, M or G binary suffix, e.g. 64 or 1K
+    fn size(s: &str) -> Result<usize, String> {
+        let (digits, unit) = match s.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+            Some((i, _)) => s.split_at(i),
+            None => (s, ""),
+        };
+        let unit = match unit {
+            "" => 1,
+            "K" | "k" => 1 << 10,
+            "M" | "m" => 1 << 20,
+            "G" | "g" => 1 << 30,
+            _ => return Err(format!("Invalid size {s}, expect e.g. 64, 1K or 1M")),
+        };
+        let n = digits
+            .parse::<usize>()
+            .map_err(|_| format!("Invalid size {s}, expect e.g. 64, 1K or 1M"))?;
+        n.checked_mul(unit)
+            .ok_or_else(|| format!("Invalid size {s}, too large"))
+    }
+
+    // A range with a multiplicative (e.g. 8..1M:x2) or additive (e.g. 1K..8K:1K) step
+    fn range(from: &str, rest: &str) -> Result<Vec<usize>, String> {
+        let (to, step) = rest
+            .split_once(':')
+            .ok_or_else(|| format!("Missing step in range {from}..{rest}, e.g. 8..1M:x2"))?;
+        let (from, to) = (Self::size(from)?, Self::size(to)?);
+        // `None` once the next size overflows, which ends the range
+        let next: Box<dyn Fn(usize) -> Option<usize>> = match step.strip_prefix('x') {
+            Some(factor) => match factor.parse::<usize>() {
+                Ok(factor) if factor > 1 && from > 0 => {
+                    Box::new(move |size| size.checked_mul(factor))
+                }
+                _ => return Err(format!("Invalid step {step}, expect a factor above 1")),
+            },
+            None => match Self::size(step)? {
+                0 => return Err("Invalid step 0".to_string()),
+                step => Box::new(move |size| size.checked_add(step)),
+            },
+        };
+        let mut sizes = vec![];
+        let mut size = Some(from);
+        while let Some(current) = size.filter(|size| *size <= to) {
+            sizes.push(current);
+            size = next(current);
+        }
+        Ok(sizes)
+    }
+}
+
+impl FromStr for Sizes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let sizes = match s.split_once("..") {
+            Some((from, rest)) => Self::range(from, rest)?,
+            None => s.split(',').map(Self::size).collect::<Result<_, _>>()?,
+        };
+        if sizes.is_empty() {
+            return Err(format!("No payload size in {s}"));
+        }
+        Ok(Sizes(sizes))
+    }
+}
+
+/// Counts the messages sent or received along with their payload size, so
+/// that the throughput measured every second follows a sweep.
+#[derive(Default)]
+pub struct Meter {
+    messages: AtomicUsize,
+    payload: AtomicUsize,
+    measured: AtomicUsize,
+}
+
+impl Meter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accounts for one message of `payload` bytes.
+    pub fn add(&self, payload: usize) {
+        if self.payload.load(Ordering::Relaxed) != payload {
+            self.payload.store(payload, Ordering::Relaxed);
+        }
+        self.messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Accounts for one message whose size does not matter, e.g. a declaration.
+    pub fn add_unsized(&self) {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Takes the payload size and the number of messages counted since the
+    /// last call, `None` if the size changed meanwhile as the count then
+    /// mixes two sizes.
+    pub fn take(&self) -> Option<(usize, usize)> {
+        let messages = self.messages.swap(0, Ordering::Relaxed);
+        let payload = self.payload.load(Ordering::Relaxed);
+        let measured = self.measured.swap(payload, Ordering::Relaxed);
+        (measured == payload || measured == 0).then_some((payload, messages))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sizes(s: &str) -> Result<Vec<usize>, String> {
+        s.parse::<Sizes>().map(|sizes| sizes.0)
+    }
+
+    #[test]
+    fn sizes_take_binary_suffixes() {
+        assert_eq!(sizes("64"), Ok(vec![64]));
+        assert_eq!(sizes("8,1K,2m,1G"), Ok(vec![8, 1 << 10, 2 << 20, 1 << 30]));
+        assert!(sizes("1T").is_err());
+        assert!(sizes("K").is_err());
+        assert!(sizes("-1").is_err());
+    }
+
+    #[test]
+    fn ranges_step_by_a_factor_or_an_increment() {
+        assert_eq!(sizes("8..64:x2"), Ok(vec![8, 16, 32, 64]));
+        assert_eq!(sizes("8..100:x2"), Ok(vec![8, 16, 32, 64]));
+        assert_eq!(sizes("1K..4K:1K"), Ok(vec![1024, 2048, 3072, 4096]));
+        assert!(sizes("8..64").is_err());
+        assert!(sizes("8..64:x1").is_err());
+        assert!(sizes("0..64:x2").is_err());
+        assert!(sizes("8..64:0").is_err());
+        assert!(sizes("64..8:x2").is_err());
+    }
+
+    #[derive(Debug, clap::Parser)]
+    struct Opt {
+        #[clap(flatten)]
+        sweep: SweepOpt,
+    }
+
+    fn opt(args: &[&str]) -> Result<SweepOpt, ErrorKind> {
+        use clap::Parser;
+        Opt::try_parse_from(std::iter::once("test").chain(args.iter().copied()))
+            .map(|opt| opt.sweep)
+            .map_err(|e| e.kind())
+    }
+
+    #[test]
+    fn sweeps_require_a_phase() {
+        let sweep = opt(&["-p", "64"]).unwrap();
+        assert_eq!((sweep.sizes(), sweep.phase()), (&[64][..], None));
+        let sweep = opt(&["-p", "8,64", "--phase", "0.5"]).unwrap();
+        assert_eq!(sweep.phase(), Some(Duration::from_millis(500)));
+        assert_eq!(
+            opt(&["-p", "8,64"]).unwrap_err(),
+            ErrorKind::MissingRequiredArgument
+        );
+        assert_eq!(
+            opt(&["-p", "8,64", "--phase", "0"]).unwrap_err(),
+            ErrorKind::ValueValidation
+        );
+        assert!(opt(&["-p", "8,64", "--phase", "-1"]).is_err());
+    }
+
+    #[test]
+    fn payloads_can_be_made_optional() {
+        use clap::{CommandFactory, FromArgMatches};
+        assert_eq!(opt(&[]).unwrap_err(), ErrorKind::MissingRequiredArgument);
+        let matches = Opt::command()
+            .mut_arg("payload", |arg| arg.required(false))
+            .try_get_matches_from(["test"])
+            .unwrap();
+        let sweep = Opt::from_arg_matches(&matches).unwrap().sweep;
+        assert_eq!((sweep.sizes(), sweep.payload()), (&[][..], 0));
+    }
+
+    #[test]
+    fn overflowing_sizes_are_rejected() {
+        assert!(sizes(&format!("{}G", usize::MAX)).is_err());
+        assert!(sizes(&format!("{}", usize::MAX)).is_ok());
+    }
+
+    #[test]
+    fn overflowing_steps_end_the_range() {
+        let max = usize::MAX;
+        let from = max / 2 + 1;
+        assert_eq!(sizes(&format!("{from}..{max}:x2")), Ok(vec![from]));
+        assert_eq!(sizes(&format!("{max}..{max}:1")), Ok(vec![max]));
+    }
+}
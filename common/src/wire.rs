@@ -0,0 +1,73 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+use crate::{output::Output, run::Run, stats::Summaries};
+use std::sync::{Arc, Mutex};
+use zenoh::net::transport::TransportUnicast;
+
+/// The bytes received on the wire by the unicast transports of a process,
+/// protocol overhead included, read from the statistics of the transports,
+/// only available with the `stats` feature.
+pub struct Wire {
+    // Every transport along with its bytes received at the last report
+    transports: Mutex<Vec<(TransportUnicast, usize)>>,
+    rate: Arc<Summaries>,
+}
+
+impl Wire {
+    pub fn new(run: &Arc<Run>) -> Self {
+        Self {
+            transports: Mutex::new(vec![]),
+            rate: run.summaries(),
+        }
+    }
+
+    /// Accounts for the bytes received by `transport` from now on.
+    pub fn add(&self, transport: TransportUnicast) {
+        let received = transport.get_stats().map_or(0, |stats| stats.rx_bytes);
+        self.transports.lock().unwrap().push((transport, received));
+    }
+
+    /// Emits the bytes received over the last `elapsed` microseconds as
+    /// `wire` (bit/s).
+    ///
+    /// The transports closed since the last report are forgotten along with
+    /// the bytes they received in the meantime, and nothing is emitted for
+    /// an interval mixing several payload sizes of a sweep.
+    pub fn record(&self, output: &Output, payload: Option<usize>, elapsed: f64) {
+        let mut bytes = 0;
+        self.transports
+            .lock()
+            .unwrap()
+            .retain_mut(|(transport, last)| match transport.get_stats() {
+                Ok(stats) => {
+                    bytes += stats.rx_bytes.saturating_sub(*last);
+                    *last = stats.rx_bytes;
+                    true
+                }
+                Err(_) => false,
+            });
+        let payload = match payload {
+            Some(payload) if bytes > 0 => payload,
+            _ => return,
+        };
+        let value = (8.0 * bytes as f64 * 1_000_000.0 / elapsed).floor();
+        self.rate
+            .record(output, "wire", payload, value, "bit/s", &[]);
+    }
+
+    /// Emits the summaries of the wire rate.
+    pub fn emit(&self, output: &Output) {
+        self.rate.emit(output, "wire", "bit/s");
+    }
+}
@@ -1,28 +1,16 @@
 mod opts;
 
-use anyhow::{anyhow, ensure, Context, Result};
+use anyhow::{anyhow, ensure, Result};
 use clap::Parser;
 use kafka_test::{AsyncStdFutureProducer, AsyncStdStreamConsumer, DEFAULT_GROUP_ID};
 use log::{error, info, trace};
-use once_cell::sync::Lazy;
 use opts::Opts;
 use rdkafka::{
     consumer::Consumer, error::KafkaError, producer::FutureRecord, types::RDKafkaErrorCode,
     ClientConfig, Message,
 };
-use std::{
-    process,
-    time::{Duration, SystemTime},
-};
-
-static SINCE: Lazy<SystemTime> = Lazy::new(SystemTime::now);
-const MIN_PAYLOAD_SIZE: usize = 16;
-
-struct PayloadInfo {
-    pub ping_id: u32,
-    pub msg_idx: u32,
-    pub rtt: Duration,
-}
+use std::{process, time::Duration};
+use zenoh_perf_common::{Header, Output, Run, Summary};
 
 #[async_std::main]
 async fn main() -> Result<()> {
@@ -57,61 +45,16 @@ async fn run_latency_benchmark(opts: Opts) -> Result<()> {
     Ok(())
 }
 
-fn generate_payload(size: usize, ping_id: u32, msg_idx: u32) -> Vec<u8> {
-    assert!(
-        size >= MIN_PAYLOAD_SIZE,
-        "The minimum payload size is {} bytes",
-        MIN_PAYLOAD_SIZE
-    );
-    let since = *SINCE; // the SINCE is inited earlier than the next SystemTime::now()
-    let dur = SystemTime::now().duration_since(since).unwrap();
-    let micros = dur.as_micros() as u64;
-
-    let ping_id_bytes = ping_id.to_le_bytes();
-    let msg_idx_bytes = msg_idx.to_le_bytes();
-    let time_bytes = micros.to_le_bytes();
-
-    let mut payload = vec![0u8; size];
-    payload[0..4].copy_from_slice(&ping_id_bytes);
-    payload[4..8].copy_from_slice(&msg_idx_bytes);
-    payload[8..16].copy_from_slice(&time_bytes);
-    payload
-}
-
-fn parse_payload(payload: &[u8], expect_payload_size: usize) -> Result<PayloadInfo> {
-    let payload_size = payload.len();
-    ensure!(
-        payload_size >= MIN_PAYLOAD_SIZE,
-        "The payload size ({} bytes) is less than the required minimum {} bytes",
-        payload_size,
-        MIN_PAYLOAD_SIZE
-    );
-
-    let ping_id_bytes = &payload[0..4];
-    let msg_idx_bytes = &payload[4..8];
-    let time_bytes = &payload[8..16];
-
-    let micros = u64::from_le_bytes(time_bytes.try_into().unwrap());
-    let since = *SINCE + Duration::from_micros(micros);
-    let rtt = SystemTime::now()
-        .duration_since(since)
-        .with_context(|| "the timestamp goes backward")?;
-
-    let ping_id = u32::from_le_bytes(ping_id_bytes.try_into().unwrap());
-    let msg_idx = u32::from_le_bytes(msg_idx_bytes.try_into().unwrap());
-
+fn parse_payload(payload: &[u8], expect_payload_size: usize) -> Result<Header> {
+    let header = Header::decode(payload)?;
+    header.verify(payload)?;
     ensure!(
         payload.len() == expect_payload_size,
         "Expect payload size to be {} bytes, but get {} bytes",
         expect_payload_size,
-        payload_size
+        payload.len()
     );
-
-    Ok(PayloadInfo {
-        msg_idx,
-        rtt,
-        ping_id,
-    })
+    Ok(header)
 }
 
 fn create_producer(opts: &Opts, mut config: ClientConfig) -> Result<AsyncStdFutureProducer> {
@@ -148,14 +91,33 @@ fn create_consumer(
 async fn run_ping_pong(opts: &Opts, client_config: &ClientConfig, ping_id: u32) -> Result<()> {
     let producer: AsyncStdFutureProducer = create_producer(opts, client_config.clone())?;
     let mut consumer = create_consumer(opts, client_config.clone(), &opts.pong_topic)?;
+    let output = Output::new(opts.output, "kafka_ping", "kafka").label("interval", opts.interval);
+
+    let run = Run::new(opts.duration, opts.count, opts.warmup);
+    let summary = run.summary();
 
-    for count in 0.. {
+    let mut count: u64 = 0;
+    while run.is_running() {
         send(opts, &producer, ping_id, count).await?;
-        if !recv(opts, client_config, ping_id, &mut consumer).await? {
+        if !recv(
+            opts,
+            client_config,
+            ping_id,
+            &mut consumer,
+            &output,
+            &summary,
+        )
+        .await?
+        {
             panic!("Failed to receive pong message.");
         }
+        run.tick();
+        count += 1;
         async_std::task::sleep(Duration::from_secs_f64(opts.interval)).await;
     }
+
+    summary.emit(&output, "latency", opts.payload_size, "us");
+    run.emit_totals(&output, opts.payload_size);
     Ok(())
 }
 
@@ -163,10 +125,10 @@ async fn send(
     opts: &Opts,
     producer: &AsyncStdFutureProducer,
     ping_id: u32,
-    msg_idx: u32,
+    msg_idx: u64,
 ) -> Result<()> {
     let record_key = ping_id.to_le_bytes();
-    let payload = generate_payload(opts.payload_size, ping_id, msg_idx);
+    let payload = Header::payload(ping_id, msg_idx, opts.payload_size, false);
     let record = FutureRecord::to(&opts.ping_topic)
         .payload(&payload)
         .key(&record_key);
@@ -188,6 +150,8 @@ async fn recv(
     client_config: &ClientConfig,
     ping_id: u32,
     consumer: &mut AsyncStdStreamConsumer,
+    output: &Output,
+    summary: &Summary,
 ) -> Result<bool> {
     use KafkaError as E;
     use RDKafkaErrorCode as C;
@@ -207,8 +171,8 @@ async fn recv(
                 }
             };
 
-            let info = match parse_payload(payload, opts.payload_size) {
-                Ok(info) => info,
+            let header = match parse_payload(payload, opts.payload_size) {
+                Ok(header) => header,
                 Err(err) => {
                     error!("Unable to parse payload: {:#}", err);
                     return Ok(false);
@@ -217,16 +181,24 @@ async fn recv(
 
             trace!(
                 "Received a pong with ping_id {} and msg_idx {}",
-                info.ping_id,
-                info.msg_idx
+                header.producer_id,
+                header.sequence
             );
             ensure!(
-                info.ping_id == ping_id,
+                header.producer_id == ping_id,
                 "Ignore the payload from a foreign ping ID {}",
-                info.ping_id
+                header.producer_id
             );
 
-            println!("{},{}", opts.interval, info.rtt.as_micros() / 2);
+            let value = (header.elapsed().as_micros() / 2) as f64;
+            summary.record(
+                &output,
+                "latency",
+                opts.payload_size,
+                value,
+                "us",
+                &[("seq", header.sequence.to_string())],
+            );
 
             Ok(true)
         }
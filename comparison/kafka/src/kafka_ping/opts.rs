@@ -2,6 +2,7 @@ use anyhow::Result;
 use clap::Parser;
 use kafka_test::{KeyVal, DEFAULT_PING_TOPIC, DEFAULT_PONG_TOPIC};
 use std::time::Duration;
+use zenoh_perf_common::{parse_payload, OutputFormat, Warmup};
 
 #[derive(Parser)]
 pub struct Opts {
@@ -11,16 +12,35 @@ pub struct Opts {
     pub pong_topic: String,
     #[clap(long, parse(try_from_str = parse_timeout))]
     pub timeout: Option<Duration>,
+    #[clap(
+        long,
+        parse(try_from_str = parse_timeout),
+        help = "stop after the given duration, e.g. 30s"
+    )]
+    pub duration: Option<Duration>,
+    #[clap(long, help = "stop after the given number of messages")]
+    pub count: Option<u64>,
+    #[clap(
+        long,
+        help = "leave out of the summary the first seconds (e.g. 5s) or messages (e.g. 1000)"
+    )]
+    pub warmup: Option<Warmup>,
     #[clap(short = 'b', long, default_value = "127.0.0.1")]
     pub brokers: String,
     #[clap(short, long, help = "ping interval in seconds")]
     pub interval: f64,
-    #[clap(short, long)]
+    #[clap(short, long, parse(try_from_str = parse_payload))]
     pub payload_size: usize,
     #[clap(short = 'P', long)]
     pub producer_configs: Option<Vec<KeyVal>>,
     #[clap(short = 'C', long)]
     pub consumer_configs: Option<Vec<KeyVal>>,
+    #[clap(
+        long,
+        default_value_t,
+        help = "output format of the results: csv, jsonl or prom"
+    )]
+    pub output: OutputFormat,
 }
 
 fn parse_timeout(text: &str) -> Result<Duration> {
@@ -10,6 +10,9 @@ use rdkafka::{
     ClientConfig, Message as _,
 };
 use std::{process, time::Duration};
+use zenoh_perf_common::Run;
+
+const RECV_TIMEOUT: Duration = Duration::from_millis(100);
 
 #[async_std::main]
 async fn main() -> Result<()> {
@@ -57,9 +60,16 @@ async fn run_pong(opts: Opts) -> Result<()> {
     use KafkaError as E;
     use RDKafkaErrorCode as C;
 
-    loop {
+    // Echo until SIGINT or SIGTERM
+    let run = Run::new(None, None, None);
+    while run.is_running() {
         let msg = loop {
-            let result = consumer.recv().await;
+            // Use a timeout to notice the end of the run even without traffic
+            let result = match async_std::future::timeout(RECV_TIMEOUT, consumer.recv()).await {
+                Ok(result) => result,
+                Err(_) if run.is_running() => continue,
+                Err(_) => return Ok(()),
+            };
             match result {
                 Ok(msg) => break msg.detach(),
                 Err(E::MessageConsumption(C::UnknownTopicOrPartition)) => {
@@ -96,6 +106,8 @@ async fn run_pong(opts: Opts) -> Result<()> {
             .await
             .map_err(|(err, _msg)| err)?;
     }
+
+    Ok(())
 }
 
 fn create_consumer(
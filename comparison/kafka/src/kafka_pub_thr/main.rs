@@ -7,6 +7,7 @@ use log::{info, trace};
 use opts::Opts;
 use rdkafka::{producer::FutureRecord, ClientConfig};
 use std::{process, time::Duration};
+use zenoh_perf_common::{Header, Output, Run};
 
 #[async_std::main]
 async fn main() -> Result<()> {
@@ -42,9 +43,14 @@ async fn run_producer(opts: Opts) -> Result<()> {
     }
 
     let producer: AsyncStdFutureProducer = client_config.create()?;
-    let payload: Vec<u8> = (0..opts.payload_size).map(|i| (i % 10) as u8).collect();
+    let mut payload = Header::payload(producer_id, 0, opts.payload_size, false);
 
-    for msg_idx in 0.. {
+    let run = Run::new(opts.duration, opts.count, None);
+    let output = Output::new(opts.output, "kafka_pub_thr", "kafka");
+
+    let mut msg_idx: u64 = 0;
+    while run.is_running() {
+        Header::stamp(&mut payload, producer_id, msg_idx, false);
         let key = producer_id.to_le_bytes();
         let record = FutureRecord::to(&opts.topic).payload(&payload).key(&key);
 
@@ -53,7 +59,10 @@ async fn run_producer(opts: Opts) -> Result<()> {
             .send(record, Duration::ZERO)
             .await
             .map_err(|(err, _msg)| err)?;
+        run.tick();
+        msg_idx += 1;
     }
 
+    run.emit_totals(&output, opts.payload_size);
     Ok(())
 }
@@ -14,6 +14,9 @@ use std::{
     sync::atomic::{AtomicUsize, Ordering},
     time::{Duration, Instant},
 };
+use zenoh_perf_common::{Header, Output, Run, Throughput};
+
+const RECV_TIMEOUT: Duration = Duration::from_millis(100);
 
 #[async_std::main]
 async fn main() -> Result<()> {
@@ -57,10 +60,23 @@ async fn run_consumer(opts: Opts) -> Result<()> {
     let mut consumer = create_consumer()?;
     let counter = Arc::new(AtomicUsize::new(0));
 
-    async_std::task::spawn(measure(counter.clone(), opts.payload_size));
-
-    loop {
-        let result = consumer.recv().await;
+    let run = Run::new(opts.duration, opts.count, opts.warmup);
+    let throughput = run.throughput();
+    let output = Output::new(opts.output, "kafka_sub_thr", "kafka");
+    async_std::task::spawn(measure(
+        counter.clone(),
+        output.clone(),
+        opts.payload_size,
+        run.clone(),
+        throughput.clone(),
+    ));
+
+    while run.is_running() {
+        // Use a timeout to notice the end of the run even without traffic
+        let result = match async_std::future::timeout(RECV_TIMEOUT, consumer.recv()).await {
+            Ok(result) => result,
+            Err(_) => continue,
+        };
         let msg = match result {
             Ok(msg) => msg,
             Err(E::MessageConsumption(C::UnknownTopicOrPartition)) => {
@@ -80,7 +96,7 @@ async fn run_consumer(opts: Opts) -> Result<()> {
         };
 
         // decode the producer ID and message index in the payload
-        let info = match parse_payload(payload, opts.payload_size) {
+        let header = match parse_payload(payload, opts.payload_size) {
             Ok(info) => info,
             Err(err) => {
                 error!("Unable to parse payload: {:?}", err);
@@ -90,54 +106,47 @@ async fn run_consumer(opts: Opts) -> Result<()> {
         trace!(
             "Consumer {} receives a payload with index {} and size {} from producer {}",
             consumer_id,
-            info.msg_index,
+            header.sequence,
             payload.len(),
-            info.producer_id
+            header.producer_id
         );
         counter.fetch_add(1, Ordering::Relaxed);
+        run.tick();
     }
+
+    throughput.emit(&output, "throughput");
+    run.emit_totals(&output, opts.payload_size);
+    Ok(())
 }
 
-pub fn parse_payload(payload: &[u8], expect_size: usize) -> Result<PayloadInfo> {
-    let payload_size = payload.len();
+pub fn parse_payload(payload: &[u8], expect_size: usize) -> Result<Header> {
+    let header = Header::decode(payload)?;
+    header.verify(payload)?;
     ensure!(
-        payload_size >= 8,
-        "insufficient payload size {}",
-        payload_size
-    );
-    ensure!(
-        payload_size == expect_size,
+        payload.len() == expect_size,
         "payload size does not match, expect {} bytes, but received {} bytes",
         expect_size,
-        payload_size
+        payload.len()
     );
-
-    let producer_id = u32::from_le_bytes(payload[0..4].try_into().unwrap());
-    let msg_index = u32::from_le_bytes(payload[4..8].try_into().unwrap());
-
-    Ok(PayloadInfo {
-        producer_id,
-        msg_index,
-        payload_size,
-    })
-}
-
-#[derive(Debug, Clone)]
-pub struct PayloadInfo {
-    pub producer_id: u32,
-    pub msg_index: u32,
-    pub payload_size: usize,
+    Ok(header)
 }
 
-async fn measure(messages: Arc<AtomicUsize>, payload: usize) {
+async fn measure(
+    messages: Arc<AtomicUsize>,
+    output: Output,
+    payload: usize,
+    run: Arc<Run>,
+    throughput: Arc<Throughput>,
+) {
     let mut timer = Instant::now();
-    loop {
+    while run.is_running() {
         task::sleep(Duration::from_secs(1)).await;
 
         if messages.load(Ordering::Relaxed) > 0 {
             let elapsed = timer.elapsed().as_micros() as f64;
             let c = messages.swap(0, Ordering::Relaxed);
-            println!("{},{:.3}", payload, c as f64 * 1_000_000.0 / elapsed);
+            let value = c as f64 * 1_000_000.0 / elapsed;
+            throughput.record(&output, "throughput", payload, value, &[]);
             timer = Instant::now()
         }
     }
@@ -2,6 +2,7 @@ use anyhow::Result;
 use clap::Parser;
 use kafka_test::DEFAULT_THROUGHPUT_TOPIC;
 use std::time::Duration;
+use zenoh_perf_common::{OutputFormat, Warmup};
 
 #[derive(Parser)]
 pub struct Opts {
@@ -9,12 +10,30 @@ pub struct Opts {
     pub topic: String,
     #[clap(long, parse(try_from_str = parse_duration))]
     pub timeout: Option<Duration>,
+    #[clap(
+        long,
+        parse(try_from_str = parse_duration),
+        help = "stop after the given duration, e.g. 30s"
+    )]
+    pub duration: Option<Duration>,
+    #[clap(long, help = "stop after the given number of messages")]
+    pub count: Option<u64>,
     #[clap(short = 'b', long, default_value = "127.0.0.1")]
     pub brokers: String,
     #[clap(short = 'p', long)]
     pub payload_size: usize,
-    #[clap(long, default_value = "0")]
-    pub warmup_msgs: usize,
+    #[clap(
+        long,
+        alias = "warmup-msgs",
+        help = "leave out of the summary the first seconds (e.g. 5s) or messages (e.g. 1000)"
+    )]
+    pub warmup: Option<Warmup>,
+    #[clap(
+        long,
+        default_value_t,
+        help = "output format of the results: csv, jsonl or prom"
+    )]
+    pub output: OutputFormat,
 }
 
 fn parse_duration(text: &str) -> Result<Duration> {
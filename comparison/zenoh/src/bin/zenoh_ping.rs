@@ -12,36 +12,35 @@
 //   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
 //
 use clap::Parser;
-use std::path::PathBuf;
 use std::time::{Duration, Instant};
-use zenoh::config::Config;
-use zenoh::prelude::{sync::*, CongestionControl};
-use zenoh_config::{EndPoint, WhatAmI};
+use zenoh::prelude::sync::*;
+use zenoh_perf_common::{
+    parse_payload, CommonOpt, CongestionOpt, Header, Output, OutputFormat, RunOpt,
+};
 
 #[derive(Debug, Parser)]
 #[clap(name = "zenoh_ping")]
 struct Opt {
-    #[clap(short, long, value_delimiter = ',')]
-    listen: Option<Vec<EndPoint>>,
+    #[clap(flatten)]
+    common: CommonOpt,
 
-    #[clap(short, long, value_delimiter = ',')]
-    connect: Option<Vec<EndPoint>>,
+    #[clap(flatten)]
+    run: RunOpt,
 
-    /// peer, router, or client
-    #[clap(short, long)]
-    mode: WhatAmI,
+    #[clap(flatten)]
+    congestion: CongestionOpt,
 
-    /// payload size (bytes)
-    #[clap(short, long)]
+    /// payload size (bytes), at least the 36 bytes of the header
+    #[clap(short, long, value_parser = parse_payload)]
     payload: usize,
 
     /// interval of sending message (sec)
     #[clap(short, long)]
     interval: f64,
 
-    /// configuration file (json5 or yaml)
-    #[clap(long = "conf", value_parser)]
-    config: Option<PathBuf>,
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
 
 fn main() {
@@ -50,30 +49,10 @@ fn main() {
 
     // Parse the args
     let opt = Opt::parse();
+    let config = opt.common.config();
 
-    let mut config: Config = if let Some(path) = &opt.config {
-        Config::from_file(path).unwrap()
-    } else {
-        Config::default()
-    };
-    config.set_mode(Some(opt.mode)).unwrap();
-    match opt.mode {
-        WhatAmI::Peer => {
-            if let Some(endpoints) = opt.listen.clone() {
-                config.listen.endpoints.extend(endpoints)
-            }
-            if let Some(endpoints) = opt.connect.clone() {
-                config.connect.endpoints.extend(endpoints)
-            }
-        }
-        WhatAmI::Client => {
-            if let Some(endpoints) = opt.connect.clone() {
-                config.connect.endpoints.extend(endpoints)
-            }
-        }
-        _ => panic!("Unsupported mode: {}", opt.mode),
-    };
-    config.scouting.multicast.set_enabled(Some(false)).unwrap();
+    let output = Output::new(opt.output, "zenoh_ping", "session").label("interval", opt.interval);
+    let output = opt.congestion.label(output);
 
     let session = zenoh::open(config).res().unwrap();
 
@@ -86,23 +65,37 @@ fn main() {
     let sub = session.declare_subscriber(key_expr_pong).res().unwrap();
     let publisher = session
         .declare_publisher(key_expr_ping)
-        .congestion_control(CongestionControl::Block)
+        .congestion_control(opt.congestion.congestion)
         .res()
         .unwrap();
 
-    let data: Value = (0usize..opt.payload)
-        .map(|i| (i % 10) as u8)
-        .collect::<Vec<u8>>()
-        .into();
-
     let sleep_interval = Duration::from_secs_f64(opt.interval);
+    let producer_id = std::process::id();
 
-    loop {
-        let data = data.clone();
+    let run = opt.run.start();
+    let summary = run.summary();
+    let mut count: u64 = 0;
+    while run.is_running() {
+        let data: Value = Header::payload(producer_id, count, opt.payload, false).into();
         std::thread::sleep(sleep_interval);
         let instant = Instant::now();
         publisher.put(data).res().unwrap();
-        let _ = sub.recv();
-        println!("{},{}", opt.interval, instant.elapsed().as_micros() / 2);
+        let sample = sub.recv().unwrap();
+        let header = Header::decode_value(&sample.value).unwrap();
+        header.verify_value(&sample.value).unwrap();
+        let value = (instant.elapsed().as_micros() / 2) as f64;
+        summary.record(
+            &output,
+            "latency",
+            opt.payload,
+            value,
+            "us",
+            &[("seq", header.sequence.to_string())],
+        );
+        run.tick();
+        count += 1;
     }
+
+    summary.emit(&output, "latency", opt.payload, "us");
+    run.emit_totals(&output, opt.payload);
 }
@@ -13,27 +13,17 @@
 //
 
 use clap::Parser;
-use std::path::PathBuf;
-use zenoh::config::Config;
-use zenoh::prelude::{sync::*, CongestionControl};
-use zenoh_config::{EndPoint, WhatAmI};
+use zenoh::prelude::sync::*;
+use zenoh_perf_common::{CommonOpt, CongestionOpt, Run};
 
 #[derive(Debug, Parser)]
 #[clap(name = "zenoh_pong")]
 struct Opt {
-    #[clap(short, long, value_delimiter = ',')]
-    listen: Option<Vec<EndPoint>>,
+    #[clap(flatten)]
+    common: CommonOpt,
 
-    #[clap(short, long, value_delimiter = ',')]
-    connect: Option<Vec<EndPoint>>,
-
-    /// peer, router, or client
-    #[clap(short, long)]
-    mode: WhatAmI,
-
-    /// configuration file (json5 or yaml)
-    #[clap(long = "conf", value_parser)]
-    config: Option<PathBuf>,
+    #[clap(flatten)]
+    congestion: CongestionOpt,
 }
 
 fn main() {
@@ -42,30 +32,7 @@ fn main() {
 
     // Parse the args
     let opt = Opt::parse();
-
-    let mut config: Config = if let Some(path) = &opt.config {
-        Config::from_file(path).unwrap()
-    } else {
-        Config::default()
-    };
-    config.set_mode(Some(opt.mode)).unwrap();
-    match opt.mode {
-        WhatAmI::Peer => {
-            if let Some(endpoints) = opt.listen {
-                config.listen.endpoints.extend(endpoints)
-            }
-            if let Some(endpoints) = opt.connect {
-                config.connect.endpoints.extend(endpoints)
-            }
-        }
-        WhatAmI::Client => {
-            if let Some(endpoints) = opt.connect {
-                config.connect.endpoints.extend(endpoints)
-            }
-        }
-        _ => panic!("Unsupported mode: {}", opt.mode),
-    };
-    config.scouting.multicast.set_enabled(Some(false)).unwrap();
+    let config = opt.common.config();
 
     let session = zenoh::open(config).res().unwrap().into_arc();
 
@@ -77,7 +44,7 @@ fn main() {
 
     let publisher = session
         .declare_publisher(key_expr_pong)
-        .congestion_control(CongestionControl::Block)
+        .congestion_control(opt.congestion.congestion)
         .res()
         .unwrap();
 
@@ -86,5 +53,6 @@ fn main() {
         .callback(move |sample| publisher.put(sample.value).res().unwrap())
         .res()
         .unwrap();
-    std::thread::park();
+    // Echo until SIGINT or SIGTERM
+    Run::new(None, None, None).wait_blocking();
 }
@@ -13,27 +13,32 @@
 //
 use clap::Parser;
 use std::{
-    path::PathBuf,
     sync::atomic::{AtomicUsize, Ordering},
-    time::Duration,
+    time::{Duration, Instant},
 };
 use std::{sync::Arc, thread};
-use zenoh::prelude::{sync::*, CongestionControl};
-use zenoh::{config::Config, prelude::Value};
-use zenoh_config::{EndPoint, WhatAmI};
+use zenoh::prelude::{sync::*, Value};
+use zenoh_perf_common::{
+    CommonOpt, CongestionOpt, LinkOpt, Output, OutputFormat, RateOpt, RunOpt, Stalls,
+};
 
 #[derive(Debug, Parser)]
 #[clap(name = "zenoh_pub_thr")]
 struct Opt {
-    #[clap(short, long, value_delimiter = ',')]
-    listen: Option<Vec<EndPoint>>,
+    #[clap(flatten)]
+    common: CommonOpt,
 
-    #[clap(short, long, value_delimiter = ',')]
-    connect: Option<Vec<EndPoint>>,
+    #[clap(flatten)]
+    run: RunOpt,
 
-    /// peer, router, or client
-    #[clap(short, long)]
-    mode: WhatAmI,
+    #[clap(flatten)]
+    rate: RateOpt,
+
+    #[clap(flatten)]
+    congestion: CongestionOpt,
+
+    #[clap(flatten)]
+    link: LinkOpt,
 
     /// payload size (bytes)
     #[clap(short, long)]
@@ -43,9 +48,14 @@ struct Opt {
     #[clap(short = 't', long)]
     print: bool,
 
-    /// configuration file (json5 or yaml)
-    #[clap(long = "conf", value_parser)]
-    config: Option<PathBuf>,
+    /// time the calls publishing the messages and report the share of the time
+    /// the publishers are blocked, e.g. by the congestion control
+    #[clap(long)]
+    stalls: bool,
+
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
 
 const KEY_EXPR: &str = "test/thr";
@@ -56,41 +66,22 @@ fn main() {
 
     // Parse the args
     let Opt {
-        listen,
-        connect,
-        mode,
+        common,
+        run,
+        rate,
+        congestion,
+        link,
         payload,
         print,
-        config,
+        stalls,
+        output,
     } = Opt::parse();
     let config = {
-        let mut config: Config = if let Some(path) = config {
-            Config::from_file(path).unwrap()
-        } else {
-            Config::default()
-        };
-        config.set_mode(Some(mode)).unwrap();
+        let mut config = link.apply(common.config());
         config
             .timestamping
             .set_enabled(Some(zenoh::config::ModeDependentValue::Unique(false)))
             .unwrap();
-        config.scouting.multicast.set_enabled(Some(false)).unwrap();
-        match mode {
-            WhatAmI::Peer => {
-                if let Some(endpoint) = listen {
-                    config.listen.endpoints.extend(endpoint);
-                }
-                if let Some(endpoint) = connect {
-                    config.connect.endpoints.extend(endpoint);
-                }
-            }
-            WhatAmI::Client => {
-                if let Some(endpoint) = connect {
-                    config.connect.endpoints.extend(endpoint);
-                }
-            }
-            _ => panic!("Unsupported mode: {mode}"),
-        };
         config
     };
 
@@ -102,27 +93,57 @@ fn main() {
     let session = zenoh::open(config).res().unwrap();
     let publisher = session
         .declare_publisher(KEY_EXPR)
-        .congestion_control(CongestionControl::Block)
+        .congestion_control(congestion.congestion)
         .res()
         .unwrap();
 
+    let run = run.start();
+    let output = rate.label(Output::new(output, "zenoh_pub_thr", "session"));
+    let output = link.label(congestion.label(output));
+    let throughput = run.throughput();
+    let count = Arc::new(AtomicUsize::new(0));
     if print {
-        let count = Arc::new(AtomicUsize::new(0));
         let c_count = count.clone();
+        let c_output = output.clone();
+        let c_throughput = throughput.clone();
         thread::spawn(move || loop {
             thread::sleep(Duration::from_secs(1));
-            let c = count.swap(0, Ordering::Relaxed);
+            let c = c_count.swap(0, Ordering::Relaxed);
             if c > 0 {
-                println!("{c} msg/s");
+                c_throughput.record(&c_output, "throughput", payload, c as f64, &[]);
             }
         });
-        loop {
-            publisher.put(data.clone()).res().unwrap();
-            c_count.fetch_add(1, Ordering::Relaxed);
-        }
-    } else {
-        loop {
-            publisher.put(data.clone()).res().unwrap();
+    }
+
+    let stalls = stalls.then(|| Arc::new(Stalls::new(&run, 1)));
+    if let Some(stalls) = &stalls {
+        let c_stalls = stalls.clone();
+        let c_output = output.clone();
+        thread::spawn(move || loop {
+            let now = Instant::now();
+            thread::sleep(Duration::from_secs(1));
+            let elapsed = now.elapsed().as_micros() as f64;
+            c_stalls.record(&c_output, elapsed);
+        });
+    }
+
+    let mut pacer = rate.pacer();
+    while run.is_running() {
+        pacer.wait_blocking();
+        let start = Instant::now();
+        publisher.put(data.clone()).res().unwrap();
+        if let Some(stalls) = &stalls {
+            stalls.add(payload, start.elapsed());
         }
+        count.fetch_add(1, Ordering::Relaxed);
+        run.tick();
+    }
+
+    if print {
+        throughput.emit(&output, "throughput");
+    }
+    if let Some(stalls) = &stalls {
+        stalls.emit(&output);
     }
+    run.emit_totals(&output, payload);
 }
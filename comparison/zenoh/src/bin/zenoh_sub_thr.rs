@@ -14,34 +14,31 @@
 use async_std::{sync::Arc, task};
 use clap::Parser;
 use std::{
-    path::PathBuf,
     sync::atomic::{AtomicUsize, Ordering},
     time::{Duration, Instant},
 };
-use zenoh::config::Config;
 use zenoh::prelude::r#async::*;
-use zenoh_config::{EndPoint, WhatAmI};
+use zenoh_perf_common::{CommonOpt, Output, OutputFormat, ReliabilityOpt, Run, RunOpt, Throughput};
 
 #[derive(Debug, Parser)]
 #[clap(name = "zenoh_sub_thr")]
 struct Opt {
-    #[clap(short, long, value_delimiter = ',')]
-    listen: Option<Vec<EndPoint>>,
+    #[clap(flatten)]
+    common: CommonOpt,
 
-    #[clap(short, long, value_delimiter = ',')]
-    connect: Option<Vec<EndPoint>>,
+    #[clap(flatten)]
+    run: RunOpt,
 
-    /// peer, router, or client
-    #[clap(short, long)]
-    mode: WhatAmI,
+    #[clap(flatten)]
+    reliability: ReliabilityOpt,
 
     /// payload size (bytes)
     #[clap(short, long)]
     payload: usize,
 
-    /// configuration file (json5 or yaml)
-    #[clap(long = "conf", value_parser)]
-    config: Option<PathBuf>,
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
 
 const KEY_EXPR: &str = "test/thr";
@@ -53,42 +50,20 @@ async fn main() {
 
     // Parse the args
     let Opt {
-        listen,
-        connect,
-        mode,
+        common,
+        run,
+        reliability,
         payload,
-        config,
+        output,
     } = Opt::parse();
+    let config = common.config();
 
-    let config = {
-        let mut config: Config = if let Some(path) = config {
-            Config::from_file(path).unwrap()
-        } else {
-            Config::default()
-        };
-        config.set_mode(Some(mode)).unwrap();
-        config.scouting.multicast.set_enabled(Some(false)).unwrap();
-        match mode {
-            WhatAmI::Peer => {
-                if let Some(endpoint) = listen {
-                    config.listen.endpoints.extend(endpoint);
-                }
-                if let Some(endpoint) = connect {
-                    config.connect.endpoints.extend(endpoint);
-                }
-            }
-            WhatAmI::Client => {
-                if let Some(endpoint) = connect {
-                    config.connect.endpoints.extend(endpoint);
-                }
-            }
-            _ => panic!("Unsupported mode: {mode}"),
-        };
-        config
-    };
-
+    let run = run.start();
+    let output = reliability.label(Output::new(output, "zenoh_sub_thr", "session"));
+    let throughput = run.throughput();
     let messages = Arc::new(AtomicUsize::new(0));
     let c_messages = messages.clone();
+    let c_run = run.clone();
 
     let session = zenoh::open(config).res().await.unwrap();
 
@@ -96,23 +71,41 @@ async fn main() {
         .declare_subscriber(KEY_EXPR)
         .callback_mut(move |_| {
             c_messages.fetch_add(1, Ordering::Relaxed);
+            c_run.tick();
         })
-        .reliable()
+        .reliability(reliability.reliability)
         .res()
         .await
         .unwrap();
-    measure(messages, payload).await;
+    task::spawn(measure(
+        messages,
+        output.clone(),
+        payload,
+        run.clone(),
+        throughput.clone(),
+    ));
+    run.wait().await;
+
+    throughput.emit(&output, "throughput");
+    run.emit_totals(&output, payload);
 }
 
-async fn measure(messages: Arc<AtomicUsize>, payload: usize) {
+async fn measure(
+    messages: Arc<AtomicUsize>,
+    output: Output,
+    payload: usize,
+    run: Arc<Run>,
+    throughput: Arc<Throughput>,
+) {
     let mut timer = Instant::now();
-    loop {
+    while run.is_running() {
         task::sleep(Duration::from_secs(1)).await;
 
         if messages.load(Ordering::Relaxed) > 0 {
             let elapsed = timer.elapsed().as_micros() as f64;
             let c = messages.swap(0, Ordering::Relaxed);
-            println!("{},{:.3}", payload, c as f64 * 1_000_000.0 / elapsed);
+            let value = c as f64 * 1_000_000.0 / elapsed;
+            throughput.record(&output, "throughput", payload, value, &[]);
             timer = Instant::now()
         }
     }
@@ -14,45 +14,56 @@
 use async_std::task;
 use clap::Parser;
 use std::collections::HashMap;
-use std::io::Write;
-use std::sync::{Arc, Barrier, Mutex};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Barrier, Mutex,
+};
 use std::time::Duration;
 use std::time::Instant;
 use zenoh::config::Config;
-use zenoh::net::protocol::io::reader::{HasReader, Reader};
-use zenoh::net::protocol::io::SplitBuffer;
-use zenoh::net::protocol::io::{WBuf, ZBuf};
+use zenoh::net::protocol::io::{SplitBuffer, ZBuf};
 use zenoh::net::protocol::proto::{DataInfo, RoutingContext};
 use zenoh::net::runtime::Runtime;
 use zenoh::net::transport::Primitives;
+use zenoh_perf_common::{
+    parse_payload, CommonOpt, CongestionOpt, Header, Output, OutputFormat, ReliabilityOpt, Run,
+    RunOpt, Summary,
+};
 use zenoh_protocol_core::{
     Channel, CongestionControl, ConsolidationStrategy, KeyExpr, PeerId, Priority, QueryTarget,
-    QueryableInfo, Reliability, SubInfo, SubMode, WhatAmI, ZInt,
+    QueryableInfo, SubInfo, SubMode, ZInt,
 };
 
 const KEY_EXPR_PING: &str = "/test/ping";
 const KEY_EXPR_PONG: &str = "/test/pong";
 
+// The header of a pong, `None` when it is not the echo of a ping, e.g. a
+// corrupted frame
+fn pong_header(payload: &ZBuf) -> Option<Header> {
+    let header = Header::decode_slices(payload.slices()).ok()?;
+    header.verify_slices(payload.slices()).ok().map(|_| header)
+}
+
 // Primitives for the non-blocking locator
 struct LatencyPrimitivesParallel {
-    scenario: String,
-    name: String,
-    interval: f64,
+    output: Output,
+    summary: Arc<Summary>,
     pending: Arc<Mutex<HashMap<u64, Instant>>>,
+    errors: Arc<AtomicU64>,
 }
 
 impl LatencyPrimitivesParallel {
     pub fn new(
-        scenario: String,
-        name: String,
-        interval: f64,
+        output: Output,
+        summary: Arc<Summary>,
         pending: Arc<Mutex<HashMap<u64, Instant>>>,
+        errors: Arc<AtomicU64>,
     ) -> Self {
         Self {
-            scenario,
-            name,
-            interval,
+            output,
+            summary,
             pending,
+            errors,
         }
     }
 }
@@ -95,23 +106,26 @@ impl Primitives for LatencyPrimitivesParallel {
         _data_info: Option<DataInfo>,
         _routing_context: Option<RoutingContext>,
     ) {
-        let mut count_bytes = [0u8; 8];
-        let mut data_reader = payload.reader();
-        if data_reader.read_exact(&mut count_bytes) {
-            let count = u64::from_le_bytes(count_bytes);
-            let instant = self.pending.lock().unwrap().remove(&count).unwrap();
-            println!(
-                "router,{},latency.parallel,{},{},{},{},{}",
-                self.scenario,
-                self.name,
-                payload.len(),
-                self.interval,
-                count,
-                instant.elapsed().as_micros()
-            );
-        } else {
-            panic!("Fail to fill the buffer");
-        }
+        let pong = pong_header(&payload).and_then(|header| {
+            let instant = self.pending.lock().unwrap().remove(&header.sequence)?;
+            Some((header, instant))
+        });
+        let (header, instant) = match pong {
+            Some(pong) => pong,
+            None => {
+                self.errors.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        };
+        let value = instant.elapsed().as_micros() as f64;
+        self.summary.record(
+            &self.output,
+            "latency.parallel",
+            payload.len(),
+            value,
+            "us",
+            &[("seq", header.sequence.to_string())],
+        );
     }
 
     fn send_query(
@@ -149,11 +163,12 @@ impl Primitives for LatencyPrimitivesParallel {
 // Primitives for the blocking locator
 struct LatencyPrimitivesSequential {
     pending: Arc<Mutex<HashMap<u64, Arc<Barrier>>>>,
+    errors: Arc<AtomicU64>,
 }
 
 impl LatencyPrimitivesSequential {
-    pub fn new(pending: Arc<Mutex<HashMap<u64, Arc<Barrier>>>>) -> Self {
-        Self { pending }
+    pub fn new(pending: Arc<Mutex<HashMap<u64, Arc<Barrier>>>>, errors: Arc<AtomicU64>) -> Self {
+        Self { pending, errors }
     }
 }
 
@@ -195,14 +210,20 @@ impl Primitives for LatencyPrimitivesSequential {
         _data_info: Option<DataInfo>,
         _routing_context: Option<RoutingContext>,
     ) {
-        let mut count_bytes = [0u8; 8];
-        let mut data_reader = payload.reader();
-        if data_reader.read_exact(&mut count_bytes) {
-            let count = u64::from_le_bytes(count_bytes);
-            let barrier = self.pending.lock().unwrap().remove(&count).unwrap();
+        let header = pong_header(&payload);
+        let mut pending = self.pending.lock().unwrap();
+        let barrier = match header {
+            Some(header) => pending.remove(&header.sequence),
+            // The only pending ping will never get a valid pong, release it
+            // rather than waiting forever
+            None => pending.drain().next().map(|(_, barrier)| barrier),
+        };
+        drop(pending);
+        if header.is_none() || barrier.is_none() {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        if let Some(barrier) = barrier {
             barrier.wait();
-        } else {
-            panic!("Fail to fill the buffer");
         }
     }
 
@@ -241,16 +262,20 @@ impl Primitives for LatencyPrimitivesSequential {
 #[derive(Debug, Parser)]
 #[clap(name = "r_ping")]
 struct Opt {
-    /// endpoint(s), e.g. --endpoint tcp/127.0.0.1:7447,tcp/127.0.0.1:7448
-    #[clap(short, long)]
-    endpoint: String,
+    #[clap(flatten)]
+    common: CommonOpt,
 
-    /// peer or client or router
-    #[clap(short, long)]
-    mode: String,
+    #[clap(flatten)]
+    run: RunOpt,
 
-    /// payload size (bytes)
-    #[clap(short, long)]
+    #[clap(flatten)]
+    reliability: ReliabilityOpt,
+
+    #[clap(flatten)]
+    congestion: CongestionOpt,
+
+    /// payload size (bytes), at least the 36 bytes of the header
+    #[clap(short, long, value_parser = parse_payload)]
     payload: usize,
 
     /// name of the test
@@ -268,23 +293,34 @@ struct Opt {
     /// spawn a task to receive or not
     #[clap(long)]
     parallel: bool,
+
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
-async fn parallel(opt: Opt, config: Config) {
+async fn parallel(
+    opt: Opt,
+    config: Config,
+    output: Output,
+    run: Arc<Run>,
+    summary: Arc<Summary>,
+    errors: Arc<AtomicU64>,
+) {
     let pending: Arc<Mutex<HashMap<u64, Instant>>> = Arc::new(Mutex::new(HashMap::new()));
 
     let runtime = Runtime::new(config).await.unwrap();
     let rx_primitives = Arc::new(LatencyPrimitivesParallel::new(
-        opt.scenario,
-        opt.name,
-        opt.interval,
+        output,
+        summary,
         pending.clone(),
+        errors,
     ));
     let tx_primitives = runtime.router.new_primitives(rx_primitives);
 
     tx_primitives.decl_resource(1, &KEY_EXPR_PONG.into());
     let rid = KeyExpr::from(1);
     let sub_info = SubInfo {
-        reliability: Reliability::Reliable,
+        reliability: opt.reliability.reliability,
         mode: SubMode::Push,
         period: None,
     };
@@ -292,44 +328,51 @@ async fn parallel(opt: Opt, config: Config) {
 
     let channel = Channel {
         priority: Priority::Data,
-        reliability: Reliability::Reliable,
+        reliability: opt.reliability.reliability,
     };
-    let congestion_control = CongestionControl::Block;
-    let payload = vec![0u8; opt.payload - 8];
+    let congestion_control = opt.congestion.congestion;
+    let producer_id = std::process::id();
     let mut count: u64 = 0;
 
     tx_primitives.decl_resource(2, &KEY_EXPR_PING.into());
     let rid = KeyExpr::from(2);
 
-    loop {
+    while run.is_running() {
         // Create and send the message
-        let mut data: WBuf = WBuf::new(opt.payload, true);
-        let count_bytes: [u8; 8] = count.to_le_bytes();
-        data.write_all(&count_bytes).unwrap();
-        data.write_all(&payload).unwrap();
-        let data: ZBuf = data.into();
+        let data: ZBuf = Header::payload(producer_id, count, opt.payload, false).into();
 
         // Insert the pending ping
         pending.lock().unwrap().insert(count, Instant::now());
 
         tx_primitives.send_data(&rid, data, channel, congestion_control, None, None);
+        run.tick();
 
         task::sleep(Duration::from_secs_f64(opt.interval)).await;
         count += 1;
     }
 }
 
-async fn single(opt: Opt, config: Config) {
+async fn single(
+    opt: Opt,
+    config: Config,
+    output: Output,
+    run: Arc<Run>,
+    summary: Arc<Summary>,
+    errors: Arc<AtomicU64>,
+) {
     let pending: Arc<Mutex<HashMap<u64, Arc<Barrier>>>> = Arc::new(Mutex::new(HashMap::new()));
 
     let runtime = Runtime::new(config).await.unwrap();
-    let rx_primitives = Arc::new(LatencyPrimitivesSequential::new(pending.clone()));
+    let rx_primitives = Arc::new(LatencyPrimitivesSequential::new(
+        pending.clone(),
+        errors.clone(),
+    ));
     let tx_primitives = runtime.router.new_primitives(rx_primitives);
 
     tx_primitives.decl_resource(1, &KEY_EXPR_PONG.into());
     let rid = KeyExpr::from(1);
     let sub_info = SubInfo {
-        reliability: Reliability::Reliable,
+        reliability: opt.reliability.reliability,
         mode: SubMode::Push,
         period: None,
     };
@@ -337,37 +380,38 @@ async fn single(opt: Opt, config: Config) {
 
     let channel = Channel {
         priority: Priority::Data,
-        reliability: Reliability::Reliable,
+        reliability: opt.reliability.reliability,
     };
-    let congestion_control = CongestionControl::Block;
-    let payload = vec![0u8; opt.payload - 8];
+    let congestion_control = opt.congestion.congestion;
+    let producer_id = std::process::id();
     let mut count: u64 = 0;
     tx_primitives.decl_resource(2, &KEY_EXPR_PING.into());
     let rid = KeyExpr::from(2);
-    loop {
+    while run.is_running() {
         // Create and send the message
-        let mut data: WBuf = WBuf::new(opt.payload, true);
-        let count_bytes: [u8; 8] = count.to_le_bytes();
-        data.write_all(&count_bytes).unwrap();
-        data.write_all(&payload).unwrap();
-        let data: ZBuf = data.into();
+        let data: ZBuf = Header::payload(producer_id, count, opt.payload, false).into();
 
         // Insert the pending ping
         let barrier = Arc::new(Barrier::new(2));
         pending.lock().unwrap().insert(count, barrier.clone());
 
+        let before = errors.load(Ordering::Relaxed);
         let now = Instant::now();
         tx_primitives.send_data(&rid, data, channel, congestion_control, None, None);
         barrier.wait();
-        println!(
-            "router,{},latency.sequential,{},{},{},{},{}",
-            opt.scenario,
-            opt.name,
-            payload.len(),
-            opt.interval,
-            count,
-            now.elapsed().as_micros()
-        );
+        let value = now.elapsed().as_micros() as f64;
+        // Leave the ping out if its pong was not a valid echo
+        if errors.load(Ordering::Relaxed) == before {
+            summary.record(
+                &output,
+                "latency.sequential",
+                opt.payload,
+                value,
+                "us",
+                &[("seq", count.to_string())],
+            );
+        }
+        run.tick();
 
         task::sleep(Duration::from_secs_f64(opt.interval)).await;
         count += 1;
@@ -381,23 +425,44 @@ async fn main() {
 
     // Parse the args
     let opt = Opt::parse();
-
-    let mut config = Config::default();
-    match opt.mode.as_str() {
-        "peer" => config.set_mode(Some(WhatAmI::Peer)).unwrap(),
-        "client" => config.set_mode(Some(WhatAmI::Client)).unwrap(),
-        "router" => config.set_mode(Some(WhatAmI::Router)).unwrap(),
-        _ => panic!("Unsupported mode {}", opt.mode),
-    };
-    config.scouting.multicast.set_enabled(Some(false)).unwrap();
-    config
-        .connect
-        .endpoints
-        .extend(opt.endpoint.split(',').map(|e| e.parse().unwrap()));
-
-    if opt.parallel {
-        parallel(opt, config).await;
+    let config = opt.common.config();
+    let output = Output::new(opt.output, "r_ping", "router")
+        .label("scenario", &opt.scenario)
+        .label("name", &opt.name)
+        .label("interval", opt.interval);
+    let output = opt.congestion.label(opt.reliability.label(output));
+
+    let run = opt.run.start();
+    let summary = run.summary();
+    let errors = Arc::new(AtomicU64::new(0));
+    let payload = opt.payload;
+    let metric = if opt.parallel {
+        parallel(
+            opt,
+            config,
+            output.clone(),
+            run.clone(),
+            summary.clone(),
+            errors.clone(),
+        )
+        .await;
+        "latency.parallel"
     } else {
-        single(opt, config).await;
-    }
+        single(
+            opt,
+            config,
+            output.clone(),
+            run.clone(),
+            summary.clone(),
+            errors.clone(),
+        )
+        .await;
+        "latency.sequential"
+    };
+
+    summary.emit(&output, metric, payload, "us");
+    // The pongs which are not the echo of a ping are left out of the latency
+    let errors = errors.load(Ordering::Relaxed) as f64;
+    output.emit("pong.errors", payload, errors, "msg");
+    run.emit_totals(&output, payload);
 }
@@ -11,18 +11,17 @@
 // Contributors:
 //   ADLINK zenoh team, <zenoh@adlink-labs.tech>
 //
-use async_std::future;
 use clap::Parser;
 use std::sync::{Arc, Mutex};
-use zenoh::config::Config;
 use zenoh::net::protocol::io::ZBuf;
 use zenoh::net::protocol::proto::{DataInfo, RoutingContext};
 use zenoh::net::routing::face::Face;
 use zenoh::net::runtime::Runtime;
 use zenoh::net::transport::Primitives;
+use zenoh_perf_common::{CommonOpt, ReliabilityOpt, Run};
 use zenoh_protocol_core::{
     Channel, CongestionControl, ConsolidationStrategy, KeyExpr, PeerId, QueryTarget, QueryableInfo,
-    Reliability, SubInfo, SubMode, WhatAmI, ZInt,
+    SubInfo, SubMode, ZInt,
 };
 
 struct LatencyPrimitives {
@@ -131,13 +130,11 @@ impl Primitives for LatencyPrimitives {
 #[derive(Debug, Parser)]
 #[clap(name = "r_pong")]
 struct Opt {
-    /// endpoint(s), e.g. --endpoint tcp/127.0.0.1:7447,tcp/127.0.0.1:7448
-    #[clap(short, long)]
-    endpoint: String,
+    #[clap(flatten)]
+    common: CommonOpt,
 
-    /// peer or client or router
-    #[clap(short, long)]
-    mode: String,
+    #[clap(flatten)]
+    reliability: ReliabilityOpt,
 }
 
 #[async_std::main]
@@ -147,34 +144,7 @@ async fn main() {
 
     // Parse the args
     let opt = Opt::parse();
-
-    let mut config = Config::default();
-
-    config.scouting.multicast.set_enabled(Some(false)).unwrap();
-    match opt.mode.as_str() {
-        "peer" => {
-            config.set_mode(Some(WhatAmI::Peer)).unwrap();
-            config
-                .listen
-                .endpoints
-                .extend(opt.endpoint.split(',').map(|e| e.parse().unwrap()));
-        }
-        "router" => {
-            config.set_mode(Some(WhatAmI::Router)).unwrap();
-            config
-                .listen
-                .endpoints
-                .extend(opt.endpoint.split(',').map(|e| e.parse().unwrap()));
-        }
-        "client" => {
-            config.set_mode(Some(WhatAmI::Client)).unwrap();
-            config
-                .connect
-                .endpoints
-                .extend(opt.endpoint.split(',').map(|e| e.parse().unwrap()));
-        }
-        _ => panic!("Unsupported mode: {}", opt.mode),
-    };
+    let config = opt.common.config();
 
     let runtime = Runtime::new(config).await.unwrap();
     let rx_primitives = Arc::new(LatencyPrimitives::new());
@@ -184,12 +154,12 @@ async fn main() {
     tx_primitives.decl_resource(2, &"/test/ping".into());
     let rid = KeyExpr::from(2);
     let sub_info = SubInfo {
-        reliability: Reliability::Reliable,
+        reliability: opt.reliability.reliability,
         mode: SubMode::Push,
         period: None,
     };
     tx_primitives.decl_subscriber(&rid, &sub_info, None);
 
-    // Stop forever
-    future::pending::<()>().await;
+    // Echo until SIGINT or SIGTERM
+    Run::new(None, None, None).wait().await;
 }
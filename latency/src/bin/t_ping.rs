@@ -15,39 +15,49 @@ use async_std::task;
 use clap::Parser;
 use std::any::Any;
 use std::collections::HashMap;
-use std::io::Write;
-use std::str::FromStr;
-use std::sync::{Arc, Barrier, Mutex};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Barrier, Mutex,
+};
 use std::time::{Duration, Instant};
 use zenoh::net::link::Link;
-use zenoh::net::protocol::io::reader::{HasReader, Reader};
-use zenoh::net::protocol::io::SplitBuffer;
-use zenoh::net::protocol::io::{WBuf, ZBuf};
+use zenoh::net::protocol::io::{SplitBuffer, ZBuf};
 use zenoh::net::protocol::proto::{Data, ZenohBody, ZenohMessage};
 use zenoh::net::transport::*;
 use zenoh_core::Result as ZResult;
-use zenoh_protocol_core::{Channel, CongestionControl, EndPoint, Priority, Reliability, WhatAmI};
+use zenoh_perf_common::{
+    parse_payload, CommonOpt, CongestionOpt, Header, MulticastHandler, MulticastOpt, Output,
+    OutputFormat, ReliabilityOpt, Run, RunOpt, Summary,
+};
+use zenoh_protocol_core::{Channel, Priority};
+
+// The header of a pong, `None` when it is not the echo of a ping, e.g. a
+// corrupted frame
+fn pong_header(payload: &ZBuf) -> Option<Header> {
+    let header = Header::decode_slices(payload.slices()).ok()?;
+    header.verify_slices(payload.slices()).ok().map(|_| header)
+}
 
 // Transport Handler for the non-blocking endpoint
 struct MySHParallel {
-    scenario: String,
-    name: String,
-    interval: f64,
+    output: Output,
+    summary: Arc<Summary>,
     pending: Arc<Mutex<HashMap<u64, Instant>>>,
+    errors: Arc<AtomicU64>,
 }
 
 impl MySHParallel {
     fn new(
-        scenario: String,
-        name: String,
-        interval: f64,
+        output: Output,
+        summary: Arc<Summary>,
         pending: Arc<Mutex<HashMap<u64, Instant>>>,
+        errors: Arc<AtomicU64>,
     ) -> Self {
         Self {
-            scenario,
-            name,
-            interval,
+            output,
+            summary,
             pending,
+            errors,
         }
     }
 }
@@ -59,10 +69,10 @@ impl TransportEventHandler for MySHParallel {
         _transport: TransportUnicast,
     ) -> ZResult<Arc<dyn TransportPeerEventHandler>> {
         Ok(Arc::new(MyMHParallel::new(
-            self.scenario.clone(),
-            self.name.clone(),
-            self.interval,
+            self.output.clone(),
+            self.summary.clone(),
             self.pending.clone(),
+            self.errors.clone(),
         )))
     }
 
@@ -70,30 +80,41 @@ impl TransportEventHandler for MySHParallel {
         &self,
         _transport: TransportMulticast,
     ) -> ZResult<Arc<dyn TransportMulticastEventHandler>> {
-        panic!();
+        let output = self.output.clone();
+        let summary = self.summary.clone();
+        let pending = self.pending.clone();
+        let errors = self.errors.clone();
+        Ok(Arc::new(MulticastHandler::new(move |_peer| {
+            MyMHParallel::new(
+                output.clone(),
+                summary.clone(),
+                pending.clone(),
+                errors.clone(),
+            )
+        })))
     }
 }
 
 // Message Handler for the endpoint
 struct MyMHParallel {
-    scenario: String,
-    name: String,
-    interval: f64,
+    output: Output,
+    summary: Arc<Summary>,
     pending: Arc<Mutex<HashMap<u64, Instant>>>,
+    errors: Arc<AtomicU64>,
 }
 
 impl MyMHParallel {
     fn new(
-        scenario: String,
-        name: String,
-        interval: f64,
+        output: Output,
+        summary: Arc<Summary>,
         pending: Arc<Mutex<HashMap<u64, Instant>>>,
+        errors: Arc<AtomicU64>,
     ) -> Self {
         Self {
-            scenario,
-            name,
-            interval,
+            output,
+            summary,
             pending,
+            errors,
         }
     }
 }
@@ -102,23 +123,26 @@ impl TransportPeerEventHandler for MyMHParallel {
     fn handle_message(&self, message: ZenohMessage) -> ZResult<()> {
         match message.body {
             ZenohBody::Data(Data { payload, .. }) => {
-                let mut count_bytes = [0u8; 8];
-                let mut data_reader = payload.reader();
-                if data_reader.read_exact(&mut count_bytes) {
-                    let count = u64::from_le_bytes(count_bytes);
-                    let instant = self.pending.lock().unwrap().remove(&count).unwrap();
-                    println!(
-                        "session,{},latency.parallel,{},{},{},{},{}",
-                        self.scenario,
-                        self.name,
-                        payload.len(),
-                        self.interval,
-                        count,
-                        instant.elapsed().as_micros()
-                    );
-                } else {
-                    panic!("Fail to fill the buffer");
-                }
+                let pong = pong_header(&payload).and_then(|header| {
+                    let instant = self.pending.lock().unwrap().remove(&header.sequence)?;
+                    Some((header, instant))
+                });
+                let (header, instant) = match pong {
+                    Some(pong) => pong,
+                    None => {
+                        self.errors.fetch_add(1, Ordering::Relaxed);
+                        return Ok(());
+                    }
+                };
+                let value = instant.elapsed().as_micros() as f64;
+                self.summary.record(
+                    &self.output,
+                    "latency.parallel",
+                    payload.len(),
+                    value,
+                    "us",
+                    &[("seq", header.sequence.to_string())],
+                );
             }
             _ => panic!("Invalid message"),
         }
@@ -137,11 +161,12 @@ impl TransportPeerEventHandler for MyMHParallel {
 // Transport Handler for the blocking endpoint
 struct MySHSequential {
     pending: Arc<Mutex<HashMap<u64, Arc<Barrier>>>>,
+    errors: Arc<AtomicU64>,
 }
 
 impl MySHSequential {
-    fn new(pending: Arc<Mutex<HashMap<u64, Arc<Barrier>>>>) -> Self {
-        Self { pending }
+    fn new(pending: Arc<Mutex<HashMap<u64, Arc<Barrier>>>>, errors: Arc<AtomicU64>) -> Self {
+        Self { pending, errors }
     }
 }
 
@@ -151,25 +176,33 @@ impl TransportEventHandler for MySHSequential {
         _peer: TransportPeer,
         _transport: TransportUnicast,
     ) -> ZResult<Arc<dyn TransportPeerEventHandler>> {
-        Ok(Arc::new(MyMHSequential::new(self.pending.clone())))
+        Ok(Arc::new(MyMHSequential::new(
+            self.pending.clone(),
+            self.errors.clone(),
+        )))
     }
 
     fn new_multicast(
         &self,
         _transport: TransportMulticast,
     ) -> ZResult<Arc<dyn TransportMulticastEventHandler>> {
-        panic!();
+        let pending = self.pending.clone();
+        let errors = self.errors.clone();
+        Ok(Arc::new(MulticastHandler::new(move |_peer| {
+            MyMHSequential::new(pending.clone(), errors.clone())
+        })))
     }
 }
 
 // Message Handler for the endpoint
 struct MyMHSequential {
     pending: Arc<Mutex<HashMap<u64, Arc<Barrier>>>>,
+    errors: Arc<AtomicU64>,
 }
 
 impl MyMHSequential {
-    fn new(pending: Arc<Mutex<HashMap<u64, Arc<Barrier>>>>) -> Self {
-        Self { pending }
+    fn new(pending: Arc<Mutex<HashMap<u64, Arc<Barrier>>>>, errors: Arc<AtomicU64>) -> Self {
+        Self { pending, errors }
     }
 }
 
@@ -177,14 +210,20 @@ impl TransportPeerEventHandler for MyMHSequential {
     fn handle_message(&self, message: ZenohMessage) -> ZResult<()> {
         match message.body {
             ZenohBody::Data(Data { payload, .. }) => {
-                let mut count_bytes = [0u8; 8];
-                let mut data_reader = payload.reader();
-                if data_reader.read_exact(&mut count_bytes) {
-                    let count = u64::from_le_bytes(count_bytes);
-                    let barrier = self.pending.lock().unwrap().remove(&count).unwrap();
+                let header = pong_header(&payload);
+                let mut pending = self.pending.lock().unwrap();
+                let barrier = match header {
+                    Some(header) => pending.remove(&header.sequence),
+                    // The only pending ping will never get a valid pong,
+                    // release it rather than waiting forever
+                    None => pending.drain().next().map(|(_, barrier)| barrier),
+                };
+                drop(pending);
+                if header.is_none() || barrier.is_none() {
+                    self.errors.fetch_add(1, Ordering::Relaxed);
+                }
+                if let Some(barrier) = barrier {
                     barrier.wait();
-                } else {
-                    panic!("Fail to fill the buffer");
                 }
             }
             _ => panic!("Invalid message"),
@@ -204,16 +243,23 @@ impl TransportPeerEventHandler for MyMHSequential {
 #[derive(Debug, Parser)]
 #[clap(name = "t_ping")]
 struct Opt {
-    /// endpoint, e.g. --endpoint tcp/127.0.0.1:7447
-    #[clap(short, long)]
-    endpoint: String,
+    #[clap(flatten)]
+    common: CommonOpt,
 
-    /// peer or client or router
-    #[clap(short, long)]
-    mode: String,
+    #[clap(flatten)]
+    multicast: MulticastOpt,
 
-    /// payload size (bytes)
-    #[clap(short, long)]
+    #[clap(flatten)]
+    run: RunOpt,
+
+    #[clap(flatten)]
+    reliability: ReliabilityOpt,
+
+    #[clap(flatten)]
+    congestion: CongestionOpt,
+
+    /// payload size (bytes), at least the 36 bytes of the header
+    #[clap(short, long, value_parser = parse_payload)]
     payload: usize,
 
     /// name of the test
@@ -231,39 +277,47 @@ struct Opt {
     /// spawn a task to receive or not
     #[clap(long)]
     parallel: bool,
+
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
 
-async fn single(opt: Opt, whatami: WhatAmI) {
+async fn single(
+    opt: Opt,
+    output: Output,
+    run: Arc<Run>,
+    summary: Arc<Summary>,
+    errors: Arc<AtomicU64>,
+) {
     let pending: Arc<Mutex<HashMap<u64, Arc<Barrier>>>> = Arc::new(Mutex::new(HashMap::new()));
     let manager = TransportManager::builder()
-        .whatami(whatami)
-        .build(Arc::new(MySHSequential::new(pending.clone())))
-        .unwrap();
-
-    // Connect to publisher
-    let session = manager
-        .open_transport(EndPoint::from_str(opt.endpoint.as_str()).unwrap())
+        .from_config(&opt.multicast.config(&opt.common))
         .await
+        .unwrap()
+        .build(Arc::new(MySHSequential::new(
+            pending.clone(),
+            errors.clone(),
+        )))
         .unwrap();
 
+    // Connect to the pong or join the multicast group
+    let session = opt.multicast.open(&manager, &opt.common).await;
+
     let sleep = Duration::from_secs_f64(opt.interval);
-    let payload = vec![0u8; opt.payload - 8];
+    let producer_id = std::process::id();
     let mut count: u64 = 0;
-    loop {
+    while run.is_running() {
         // Create and send the message
         let channel = Channel {
             priority: Priority::Data,
-            reliability: Reliability::Reliable,
+            reliability: opt.reliability.reliability,
         };
-        let congestion_control = CongestionControl::Block;
+        let congestion_control = opt.congestion.congestion;
         let key = "/test/ping";
         let info = None;
 
-        let mut data: WBuf = WBuf::new(opt.payload, true);
-        let count_bytes: [u8; 8] = count.to_le_bytes();
-        data.write_all(&count_bytes).unwrap();
-        data.write_all(&payload).unwrap();
-        let data: ZBuf = data.into();
+        let data: ZBuf = Header::payload(producer_id, count, opt.payload, false).into();
         let routing_context = None;
         let reply_context = None;
         let attachment = None;
@@ -282,61 +336,67 @@ async fn single(opt: Opt, whatami: WhatAmI) {
         // Insert the pending ping
         let barrier = Arc::new(Barrier::new(2));
         pending.lock().unwrap().insert(count, barrier.clone());
+        let before = errors.load(Ordering::Relaxed);
         let now = Instant::now();
         session.handle_message(message).unwrap();
         // Wait for the pong to arrive
         barrier.wait();
-        println!(
-            "session,{},latency.sequential,{},{},{},{},{}",
-            opt.scenario,
-            opt.name,
-            payload.len(),
-            opt.interval,
-            count,
-            now.elapsed().as_micros()
-        );
+        let value = now.elapsed().as_micros() as f64;
+        // Leave the ping out if its pong was not a valid echo
+        if errors.load(Ordering::Relaxed) == before {
+            summary.record(
+                &output,
+                "latency.sequential",
+                opt.payload,
+                value,
+                "us",
+                &[("seq", count.to_string())],
+            );
+        }
+        run.tick();
 
         task::sleep(sleep).await;
         count += 1;
     }
 }
 
-async fn parallel(opt: Opt, whatami: WhatAmI) {
+async fn parallel(
+    opt: Opt,
+    output: Output,
+    run: Arc<Run>,
+    summary: Arc<Summary>,
+    errors: Arc<AtomicU64>,
+) {
     let pending: Arc<Mutex<HashMap<u64, Instant>>> = Arc::new(Mutex::new(HashMap::new()));
     let manager = TransportManager::builder()
-        .whatami(whatami)
+        .from_config(&opt.multicast.config(&opt.common))
+        .await
+        .unwrap()
         .build(Arc::new(MySHParallel::new(
-            opt.scenario,
-            opt.name,
-            opt.interval,
+            output,
+            summary,
             pending.clone(),
+            errors,
         )))
         .unwrap();
 
-    // Connect to publisher
-    let session = manager
-        .open_transport(EndPoint::from_str(opt.endpoint.as_str()).unwrap())
-        .await
-        .unwrap();
+    // Connect to the pong or join the multicast group
+    let session = opt.multicast.open(&manager, &opt.common).await;
 
     let sleep = Duration::from_secs_f64(opt.interval);
-    let payload = vec![0u8; opt.payload - 8];
+    let producer_id = std::process::id();
     let mut count: u64 = 0;
-    loop {
+    while run.is_running() {
         // Create and send the message
         let channel = Channel {
             priority: Priority::Data,
-            reliability: Reliability::Reliable,
+            reliability: opt.reliability.reliability,
         };
-        let congestion_control = CongestionControl::Block;
+        let congestion_control = opt.congestion.congestion;
         let key = "/test/ping";
         let info = None;
 
-        let mut data: WBuf = WBuf::new(opt.payload, true);
-        let count_bytes: [u8; 8] = count.to_le_bytes();
-        data.write_all(&count_bytes).unwrap();
-        data.write_all(&payload).unwrap();
-        let data: ZBuf = data.into();
+        let data: ZBuf = Header::payload(producer_id, count, opt.payload, false).into();
         let routing_context = None;
         let reply_context = None;
         let attachment = None;
@@ -356,6 +416,7 @@ async fn parallel(opt: Opt, whatami: WhatAmI) {
         pending.lock().unwrap().insert(count, Instant::now());
 
         session.handle_message(message).unwrap();
+        run.tick();
 
         task::sleep(sleep).await;
         count += 1;
@@ -369,12 +430,42 @@ async fn main() {
 
     // Parse the args
     let opt = Opt::parse();
-
-    let whatami = WhatAmI::from_str(opt.mode.as_str()).unwrap();
-
-    if opt.parallel {
-        parallel(opt, whatami).await;
+    let output = Output::new(opt.output, "t_ping", "transport")
+        .label("scenario", &opt.scenario)
+        .label("name", &opt.name)
+        .label("interval", opt.interval);
+    let output = opt.congestion.label(opt.reliability.label(output));
+    let output = opt.multicast.label(output);
+
+    let run = opt.run.start();
+    let summary = run.summary();
+    let errors = Arc::new(AtomicU64::new(0));
+    let payload = opt.payload;
+    let metric = if opt.parallel {
+        parallel(
+            opt,
+            output.clone(),
+            run.clone(),
+            summary.clone(),
+            errors.clone(),
+        )
+        .await;
+        "latency.parallel"
     } else {
-        single(opt, whatami).await;
-    }
+        single(
+            opt,
+            output.clone(),
+            run.clone(),
+            summary.clone(),
+            errors.clone(),
+        )
+        .await;
+        "latency.sequential"
+    };
+
+    summary.emit(&output, metric, payload, "us");
+    // The pongs which are not the echo of a ping are left out of the latency
+    let errors = errors.load(Ordering::Relaxed) as f64;
+    output.emit("pong.errors", payload, errors, "msg");
+    run.emit_totals(&output, payload);
 }
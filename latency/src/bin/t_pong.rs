@@ -11,11 +11,9 @@
 // Contributors:
 //   ADLINK zenoh team, <zenoh@adlink-labs.tech>
 //
-use async_std::future;
 use async_std::sync::Arc;
 use clap::Parser;
 use std::any::Any;
-use std::str::FromStr;
 use zenoh::net::link::Link;
 use zenoh::net::protocol::proto::ZenohMessage;
 use zenoh::net::transport::{
@@ -23,7 +21,7 @@ use zenoh::net::transport::{
     TransportPeer, TransportPeerEventHandler, TransportUnicast,
 };
 use zenoh_core::Result as ZResult;
-use zenoh_protocol_core::{EndPoint, WhatAmI};
+use zenoh_perf_common::{CommonOpt, MulticastHandler, MulticastOpt, Run, Transport};
 
 // Transport Handler for the peer
 struct MySH;
@@ -40,24 +38,27 @@ impl TransportEventHandler for MySH {
         _peer: TransportPeer,
         transport: TransportUnicast,
     ) -> ZResult<Arc<dyn TransportPeerEventHandler>> {
-        Ok(Arc::new(MyMH::new(transport)))
+        Ok(Arc::new(MyMH::new(transport.into())))
     }
 
     fn new_multicast(
         &self,
-        _transport: TransportMulticast,
+        transport: TransportMulticast,
     ) -> ZResult<Arc<dyn TransportMulticastEventHandler>> {
-        panic!();
+        // The pongs are echoed to the whole group
+        Ok(Arc::new(MulticastHandler::new(move |_peer| {
+            MyMH::new(transport.clone().into())
+        })))
     }
 }
 
 // Message Handler for the peer
 struct MyMH {
-    session: TransportUnicast,
+    session: Transport,
 }
 
 impl MyMH {
-    fn new(session: TransportUnicast) -> Self {
+    fn new(session: Transport) -> Self {
         Self { session }
     }
 }
@@ -79,13 +80,11 @@ impl TransportPeerEventHandler for MyMH {
 #[derive(Debug, Parser)]
 #[clap(name = "t_pong")]
 struct Opt {
-    /// endpoint, e.g. --endpoint tcp/127.0.0.1:7447
-    #[clap(short, long)]
-    endpoint: String,
+    #[clap(flatten)]
+    common: CommonOpt,
 
-    /// peer or client or router
-    #[clap(short, long)]
-    mode: String,
+    #[clap(flatten)]
+    multicast: MulticastOpt,
 }
 
 #[async_std::main]
@@ -96,26 +95,22 @@ async fn main() {
     // Parse the args
     let opt = Opt::parse();
 
-    let whatami = WhatAmI::from_str(opt.mode.as_str()).unwrap();
-
     let manager = TransportManager::builder()
-        .whatami(whatami)
+        .from_config(&opt.multicast.config(&opt.common))
+        .await
+        .unwrap()
         .build(Arc::new(MySH::new()))
         .unwrap();
 
-    // Connect to the peer or listen
-    if whatami == WhatAmI::Peer {
-        manager
-            .add_listener(EndPoint::from_str(opt.endpoint.as_str()).unwrap())
-            .await
-            .unwrap();
-    } else {
-        let _session = manager
-            .open_transport(EndPoint::from_str(opt.endpoint.as_str()).unwrap())
-            .await
-            .unwrap();
+    // Connect to the peer, listen or join the multicast groups
+    for e in opt.common.listen_endpoints() {
+        manager.add_listener(e).await.unwrap();
+    }
+    for e in opt.common.connect_endpoints() {
+        let _session = manager.open_transport(e).await.unwrap();
     }
+    let _sessions = opt.multicast.join(&manager).await;
 
-    // Stop forever
-    future::pending::<()>().await;
+    // Echo until SIGINT or SIGTERM
+    Run::new(None, None, None).wait().await;
 }
@@ -14,15 +14,18 @@
 use async_std::sync::Arc;
 use async_std::task;
 use clap::Parser;
-use std::str::FromStr;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::Duration;
 use zenoh::net::protocol::proto::ZenohMessage;
 use zenoh::net::transport::{
     DummyTransportPeerEventHandler, TransportEventHandler, TransportManager, TransportMulticast,
     TransportMulticastEventHandler, TransportPeer, TransportPeerEventHandler, TransportUnicast,
 };
 use zenoh_core::Result as ZResult;
-use zenoh_protocol_core::{Channel, CongestionControl, EndPoint, Priority, Reliability, WhatAmI};
+use zenoh_perf_common::{
+    parse_payload, CommonOpt, CongestionOpt, Header, MulticastHandler, MulticastOpt,
+    ReliabilityOpt, RunOpt,
+};
+use zenoh_protocol_core::{Channel, Priority};
 
 struct MySH {}
 
@@ -45,23 +48,32 @@ impl TransportEventHandler for MySH {
         &self,
         _transport: TransportMulticast,
     ) -> ZResult<Arc<dyn TransportMulticastEventHandler>> {
-        panic!();
+        Ok(Arc::new(MulticastHandler::new(|_peer| {
+            DummyTransportPeerEventHandler::default()
+        })))
     }
 }
 
 #[derive(Debug, Parser)]
 #[clap(name = "t_pub_delay")]
 struct Opt {
-    /// endpoint, e.g. --endpoint tcp/127.0.0.1:7447
-    #[clap(short, long)]
-    endpoint: String,
+    #[clap(flatten)]
+    common: CommonOpt,
 
-    /// peer or client or router
-    #[clap(short, long)]
-    mode: String,
+    #[clap(flatten)]
+    multicast: MulticastOpt,
 
-    /// payload size ( >= 24 bytes)
-    #[clap(short, long)]
+    #[clap(flatten)]
+    run: RunOpt,
+
+    #[clap(flatten)]
+    reliability: ReliabilityOpt,
+
+    #[clap(flatten)]
+    congestion: CongestionOpt,
+
+    /// payload size (bytes), at least the 36 bytes of the header
+    #[clap(short, long, value_parser = parse_payload)]
     payload: usize,
 
     /// interval of sending message (sec)
@@ -77,47 +89,32 @@ async fn main() {
     // Parse the args
     let opt = Opt::parse();
 
-    let whatami = WhatAmI::from_str(opt.mode.as_str()).unwrap();
-
     let manager = TransportManager::builder()
-        .whatami(whatami)
+        .from_config(&opt.multicast.config(&opt.common))
+        .await
+        .unwrap()
         .build(Arc::new(MySH::new()))
         .unwrap();
 
-    // Connect to publisher
-    let session = manager
-        .open_transport(EndPoint::from_str(opt.endpoint.as_str()).unwrap())
-        .await
-        .unwrap();
+    // Connect to the subscriber or join the multicast group
+    let session = opt.multicast.open(&manager, &opt.common).await;
 
+    let run = opt.run.start();
+    let producer_id = std::process::id();
     let mut count: u64 = 0;
-    loop {
-        // Send reliable messages
+    while run.is_running() {
         let channel = Channel {
             priority: Priority::Data,
-            reliability: Reliability::Reliable,
+            reliability: opt.reliability.reliability,
         };
-        let congestion_control = CongestionControl::Block;
+        let congestion_control = opt.congestion.congestion;
         let key = "/test/ping";
         let info = None;
         let routing_context = None;
         let reply_context = None;
         let attachment = None;
 
-        // u64 (8 bytes) for seq num
-        // u128 (16 bytes) for system time in nanoseconds
-        if opt.payload < 24 {
-            panic!("The payload size should >= 24");
-        }
-        let mut payload = vec![0u8; opt.payload];
-        let count_bytes: [u8; 8] = count.to_le_bytes();
-        let now_bytes: [u8; 16] = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_nanos()
-            .to_le_bytes();
-        payload[0..8].copy_from_slice(&count_bytes);
-        payload[8..24].copy_from_slice(&now_bytes);
+        let payload = Header::payload(producer_id, count, opt.payload, false);
 
         let message = ZenohMessage::make_data(
             key.into(),
@@ -131,6 +128,7 @@ async fn main() {
         );
 
         session.handle_message(message.clone()).unwrap();
+        run.tick();
 
         task::sleep(Duration::from_secs_f64(opt.interval)).await;
         count += 1;
@@ -11,26 +11,32 @@
 // Contributors:
 //   ADLINK zenoh team, <zenoh@adlink-labs.tech>
 //
-use async_std::future;
 use async_std::sync::Arc;
 use clap::Parser;
 use std::any::Any;
-use std::str::FromStr;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use zenoh::net::link::Link;
-use zenoh::net::protocol::io::reader::{HasReader, Reader};
 use zenoh::net::protocol::io::SplitBuffer;
 use zenoh::net::protocol::proto::{Data, ZenohBody, ZenohMessage};
 use zenoh::net::transport::*;
 use zenoh_core::Result as ZResult;
-use zenoh_protocol_core::{EndPoint, WhatAmI};
+use zenoh_perf_common::{
+    CommonOpt, Header, MulticastHandler, MulticastOpt, Output, OutputFormat, Run, RunOpt, Summary,
+};
 
 // Transport Handler for the peer
-struct MySH;
+struct MySH {
+    output: Output,
+    run: Arc<Run>,
+    summary: Arc<Summary>,
+}
 
 impl MySH {
-    fn new() -> Self {
-        Self
+    fn new(output: Output, run: Arc<Run>, summary: Arc<Summary>) -> Self {
+        Self {
+            output,
+            run,
+            summary,
+        }
     }
 }
 
@@ -40,23 +46,40 @@ impl TransportEventHandler for MySH {
         _peer: TransportPeer,
         _transport: TransportUnicast,
     ) -> ZResult<Arc<dyn TransportPeerEventHandler>> {
-        Ok(Arc::new(MyMH::new()))
+        Ok(Arc::new(MyMH::new(
+            self.output.clone(),
+            self.run.clone(),
+            self.summary.clone(),
+        )))
     }
 
     fn new_multicast(
         &self,
         _transport: TransportMulticast,
     ) -> ZResult<Arc<dyn TransportMulticastEventHandler>> {
-        panic!();
+        let output = self.output.clone();
+        let run = self.run.clone();
+        let summary = self.summary.clone();
+        Ok(Arc::new(MulticastHandler::new(move |_peer| {
+            MyMH::new(output.clone(), run.clone(), summary.clone())
+        })))
     }
 }
 
 // Message Handler for the peer
-struct MyMH;
+struct MyMH {
+    output: Output,
+    run: Arc<Run>,
+    summary: Arc<Summary>,
+}
 
 impl MyMH {
-    fn new() -> Self {
-        Self
+    fn new(output: Output, run: Arc<Run>, summary: Arc<Summary>) -> Self {
+        Self {
+            output,
+            run,
+            summary,
+        }
     }
 }
 
@@ -64,26 +87,18 @@ impl TransportPeerEventHandler for MyMH {
     fn handle_message(&self, message: ZenohMessage) -> ZResult<()> {
         match message.body {
             ZenohBody::Data(Data { payload, .. }) => {
-                let mut count_bytes = [0u8; 8];
-                let mut now_bytes = [0u8; 16];
-
-                let mut data_reader = payload.reader();
-                if data_reader.read_exact(&mut count_bytes)
-                    && data_reader.read_exact(&mut now_bytes)
-                {
-                    let count = u64::from_le_bytes(count_bytes);
-                    let now_pub = u128::from_le_bytes(now_bytes);
-
-                    let now_sub = SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_nanos();
-                    let interval = Duration::from_nanos((now_sub - now_pub) as u64);
-
-                    println!("{} bytes: seq={} time={:?}", payload.len(), count, interval);
-                } else {
-                    panic!("Fail to fill the buffer");
-                }
+                let header = Header::decode_slices(payload.slices()).unwrap();
+                header.verify_slices(payload.slices()).unwrap();
+                let value = header.elapsed().as_micros() as f64;
+                self.summary.record(
+                    &self.output,
+                    "latency.oneway",
+                    payload.len(),
+                    value,
+                    "us",
+                    &[("seq", header.sequence.to_string())],
+                );
+                self.run.tick();
             }
             _ => panic!("Invalid message"),
         }
@@ -103,13 +118,18 @@ impl TransportPeerEventHandler for MyMH {
 #[derive(Debug, Parser)]
 #[clap(name = "t_sub_delay")]
 struct Opt {
-    /// endpoint, e.g. --endpoint tcp/127.0.0.1:7447
-    #[clap(short, long)]
-    endpoint: String,
+    #[clap(flatten)]
+    common: CommonOpt,
+
+    #[clap(flatten)]
+    multicast: MulticastOpt,
 
-    /// peer or client or router
-    #[clap(short, long)]
-    mode: String,
+    #[clap(flatten)]
+    run: RunOpt,
+
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
 
 #[async_std::main]
@@ -120,26 +140,33 @@ async fn main() {
     // Parse the args
     let opt = Opt::parse();
 
-    let whatami = WhatAmI::from_str(opt.mode.as_str()).unwrap();
-
+    let run = opt.run.start();
+    let summary = run.summary();
+    let output = Output::new(opt.output, "t_sub_delay", "transport");
+    let output = opt.multicast.label(output);
     let manager = TransportManager::builder()
-        .whatami(whatami)
-        .build(Arc::new(MySH::new()))
+        .from_config(&opt.multicast.config(&opt.common))
+        .await
+        .unwrap()
+        .build(Arc::new(MySH::new(
+            output.clone(),
+            run.clone(),
+            summary.clone(),
+        )))
         .unwrap();
 
-    // Connect to the peer or listen
-    if whatami == WhatAmI::Peer {
-        manager
-            .add_listener(EndPoint::from_str(opt.endpoint.as_str()).unwrap())
-            .await
-            .unwrap();
-    } else {
-        let _session = manager
-            .open_transport(EndPoint::from_str(opt.endpoint.as_str()).unwrap())
-            .await
-            .unwrap();
+    // Connect to the peer, listen or join the multicast groups
+    for e in opt.common.listen_endpoints() {
+        manager.add_listener(e).await.unwrap();
     }
+    for e in opt.common.connect_endpoints() {
+        let _session = manager.open_transport(e).await.unwrap();
+    }
+    let _sessions = opt.multicast.join(&manager).await;
+
+    run.wait().await;
 
-    // Stop forever
-    future::pending::<()>().await;
+    // The payload size is only known from the received messages
+    summary.emit(&output, "latency.oneway", 0, "us");
+    run.emit_totals(&output, 0);
 }
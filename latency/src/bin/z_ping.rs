@@ -16,26 +16,39 @@ use async_std::sync::{Arc, Mutex};
 use async_std::task;
 use clap::Parser;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{Duration, Instant};
 use zenoh::config::Config;
-use zenoh::net::protocol::io::reader::{HasReader, Reader};
 use zenoh::net::protocol::io::SplitBuffer;
 use zenoh::prelude::*;
-use zenoh_protocol_core::{CongestionControl, WhatAmI};
+#[cfg(feature = "shared-memory")]
+use zenoh_perf_common::ShmOpt;
+use zenoh_perf_common::{
+    parse_payload, CommonOpt, CongestionOpt, Header, Output, OutputFormat, ReliabilityOpt, Run,
+    RunOpt, Summary,
+};
 
 #[derive(Debug, Parser)]
 #[clap(name = "z_ping")]
 struct Opt {
-    /// endpoint(s), e.g. --endpoint tcp/127.0.0.1:7447,tcp/127.0.0.1:7448
-    #[clap(short, long, required(true))]
-    endpoint: Option<String>,
+    #[clap(flatten)]
+    common: CommonOpt,
 
-    /// peer or client
-    #[clap(short, long, possible_values = ["peer", "client"])]
-    mode: String,
+    #[clap(flatten)]
+    run: RunOpt,
 
-    /// payload size (bytes)
-    #[clap(short, long)]
+    #[clap(flatten)]
+    reliability: ReliabilityOpt,
+
+    #[clap(flatten)]
+    congestion: CongestionOpt,
+
+    #[cfg(feature = "shared-memory")]
+    #[clap(flatten)]
+    shm: ShmOpt,
+
+    /// payload size (bytes), at least the 36 bytes of the header
+    #[clap(short, long, value_parser = parse_payload)]
     payload: usize,
 
     #[clap(short, long)]
@@ -59,12 +72,30 @@ struct Opt {
     /// declare publication before the publisher
     #[clap(long)]
     declare_publication: bool,
+
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
 
 const KEY_EXPR_PING: &str = "/test/z_ping";
 const KEY_EXPR_PONG: &str = "/test/z_pong";
 
-async fn parallel(opt: Opt, config: Config) {
+// The header of a pong, `None` when it is not the echo of a ping, e.g. a
+// corrupted frame
+fn pong_header(value: &Value) -> Option<Header> {
+    let header = Header::decode_value(value).ok()?;
+    header.verify_value(value).ok().map(|_| header)
+}
+
+async fn parallel(
+    opt: Opt,
+    config: Config,
+    output: Output,
+    run: Arc<Run>,
+    summary: Arc<Summary>,
+    errors: Arc<AtomicU64>,
+) {
     let session = zenoh::open(config).await.unwrap();
     let session = Arc::new(session);
 
@@ -72,16 +103,21 @@ async fn parallel(opt: Opt, config: Config) {
     let pending = Arc::new(Mutex::new(HashMap::<u64, Instant>::new()));
 
     let c_pending = pending.clone();
-    let scenario = opt.scenario;
-    let name = opt.name;
-    let interval = opt.interval;
 
     let mut sub = if opt.use_expr {
         // Declare the subscriber
         let key_expr_pong = session.declare_expr(KEY_EXPR_PONG).await.unwrap();
-        session.subscribe(key_expr_pong).reliable().await.unwrap()
+        session
+            .subscribe(key_expr_pong)
+            .reliability(opt.reliability.reliability)
+            .await
+            .unwrap()
     } else {
-        session.subscribe(KEY_EXPR_PONG).reliable().await.unwrap()
+        session
+            .subscribe(KEY_EXPR_PONG)
+            .reliability(opt.reliability.reliability)
+            .await
+            .unwrap()
     };
 
     let mut key_expr_ping = 0;
@@ -95,33 +131,47 @@ async fn parallel(opt: Opt, config: Config) {
     }
     task::spawn(async move {
         while let Some(sample) = sub.next().await {
-            let mut payload_reader = sample.value.payload.reader();
-            let mut count_bytes = [0u8; 8];
-            if payload_reader.read_exact(&mut count_bytes) {
-                let count = u64::from_le_bytes(count_bytes);
-
-                let instant = c_pending.lock().await.remove(&count).unwrap();
-                println!(
-                    "zenoh,{},latency.parallel,{},{},{},{},{}",
-                    scenario,
-                    name,
-                    sample.value.payload.len(),
-                    interval,
-                    count,
-                    instant.elapsed().as_micros()
-                );
-            } else {
-                panic!("Fail to fill the buffer");
-            }
+            let pong = match pong_header(&sample.value) {
+                Some(header) => c_pending
+                    .lock()
+                    .await
+                    .remove(&header.sequence)
+                    .map(|instant| (header, instant)),
+                None => None,
+            };
+            let (header, instant) = match pong {
+                Some(pong) => pong,
+                None => {
+                    errors.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+            };
+            let value = instant.elapsed().as_micros() as f64;
+            summary.record(
+                &output,
+                "latency.parallel",
+                sample.value.payload.len(),
+                value,
+                "us",
+                &[("seq", header.sequence.to_string())],
+            );
         }
         panic!("Invalid value!");
     });
 
+    #[cfg(feature = "shared-memory")]
+    let mut segment = opt.shm.segment(&session).await;
+    let producer_id = std::process::id();
     let mut count: u64 = 0;
-    loop {
-        let count_bytes: [u8; 8] = count.to_le_bytes();
-        let mut payload = vec![0u8; opt.payload];
-        payload[0..8].copy_from_slice(&count_bytes);
+    while run.is_running() {
+        let payload = Header::payload(producer_id, count, opt.payload, false);
+        #[cfg(not(feature = "shared-memory"))]
+        let payload: Value = payload.into();
+        #[cfg(feature = "shared-memory")]
+        let payload: Value = match &mut segment {
+            Some(segment) => segment.value(&[&payload]).await,
+            None => payload.into(),
+        };
 
         pending.lock().await.insert(count, Instant::now());
 
@@ -131,28 +181,40 @@ async fn parallel(opt: Opt, config: Config) {
             session.put(KEY_EXPR_PING, payload)
         };
         writer
-            .congestion_control(CongestionControl::Block)
+            .congestion_control(opt.congestion.congestion)
             .await
             .unwrap();
 
+        run.tick();
         task::sleep(Duration::from_secs_f64(opt.interval)).await;
         count += 1;
     }
 }
 
-async fn single(opt: Opt, config: Config) {
+async fn single(
+    opt: Opt,
+    config: Config,
+    output: Output,
+    run: Arc<Run>,
+    summary: Arc<Summary>,
+    errors: Arc<AtomicU64>,
+) {
     let session = zenoh::open(config).await.unwrap();
 
-    let scenario = opt.scenario;
-    let name = opt.name;
-    let interval = opt.interval;
-
     let mut sub = if opt.use_expr {
         // Declare the subscriber
         let key_expr_pong = session.declare_expr("/test/pong").await.unwrap();
-        session.subscribe(key_expr_pong).reliable().await.unwrap()
+        session
+            .subscribe(key_expr_pong)
+            .reliability(opt.reliability.reliability)
+            .await
+            .unwrap()
     } else {
-        session.subscribe("/test/pong").reliable().await.unwrap()
+        session
+            .subscribe("/test/pong")
+            .reliability(opt.reliability.reliability)
+            .await
+            .unwrap()
     };
 
     let mut key_expr_ping = 0;
@@ -164,11 +226,19 @@ async fn single(opt: Opt, config: Config) {
     } else if opt.declare_publication {
         session.declare_publication("/test/ping").await.unwrap();
     }
+    #[cfg(feature = "shared-memory")]
+    let mut segment = opt.shm.segment(&session).await;
+    let producer_id = std::process::id();
     let mut count: u64 = 0;
-    loop {
-        let count_bytes: [u8; 8] = count.to_le_bytes();
-        let mut payload = vec![0u8; opt.payload];
-        payload[0..8].copy_from_slice(&count_bytes);
+    while run.is_running() {
+        let payload = Header::payload(producer_id, count, opt.payload, false);
+        #[cfg(not(feature = "shared-memory"))]
+        let payload: Value = payload.into();
+        #[cfg(feature = "shared-memory")]
+        let payload: Value = match &mut segment {
+            Some(segment) => segment.value(&[&payload]).await,
+            None => payload.into(),
+        };
 
         let now = Instant::now();
         let writer = if opt.use_expr {
@@ -177,32 +247,30 @@ async fn single(opt: Opt, config: Config) {
             session.put("/test/ping", payload)
         };
         writer
-            .congestion_control(CongestionControl::Block)
+            .congestion_control(opt.congestion.congestion)
             .await
             .unwrap();
 
         match sub.next().await {
-            Some(sample) => {
-                let mut payload_reader = sample.value.payload.reader();
-                let mut count_bytes = [0u8; 8];
-                if payload_reader.read_exact(&mut count_bytes) {
-                    let s_count = u64::from_le_bytes(count_bytes);
-
-                    println!(
-                        "zenoh,{},latency.sequential,{},{},{},{},{}",
-                        scenario,
-                        name,
+            Some(sample) => match pong_header(&sample.value) {
+                Some(header) => {
+                    let value = now.elapsed().as_micros() as f64;
+                    summary.record(
+                        &output,
+                        "latency.sequential",
                         sample.value.payload.len(),
-                        interval,
-                        s_count,
-                        now.elapsed().as_micros()
+                        value,
+                        "us",
+                        &[("seq", header.sequence.to_string())],
                     );
-                } else {
-                    panic!("Fail to fill the buffer");
                 }
-            }
+                None => {
+                    errors.fetch_add(1, Ordering::Relaxed);
+                }
+            },
             _ => panic!("Invalid value"),
         }
+        run.tick();
         task::sleep(Duration::from_secs_f64(opt.interval)).await;
         count += 1;
     }
@@ -215,27 +283,48 @@ async fn main() {
 
     // Parse the args
     let opt = Opt::parse();
-
-    let mut config = Config::default();
-    match opt.mode.as_str() {
-        "peer" => config.set_mode(Some(WhatAmI::Peer)).unwrap(),
-        "client" => config.set_mode(Some(WhatAmI::Client)).unwrap(),
-        _ => panic!("Unsupported mode: {}", opt.mode),
-    };
-
-    if let Some(ref l) = opt.endpoint {
-        config.scouting.multicast.set_enabled(Some(false)).unwrap();
-        config
-            .connect
-            .endpoints
-            .extend(l.split(',').map(|v| v.parse().unwrap()));
+    let config = opt.common.config();
+    #[cfg(feature = "shared-memory")]
+    let config = opt.shm.apply(config);
+    let output = Output::new(opt.output, "z_ping", "session")
+        .label("scenario", &opt.scenario)
+        .label("name", &opt.name)
+        .label("interval", opt.interval);
+    let output = opt.congestion.label(opt.reliability.label(output));
+    #[cfg(feature = "shared-memory")]
+    let output = opt.shm.label(output);
+
+    let run = opt.run.start();
+    let summary = run.summary();
+    let errors = Arc::new(AtomicU64::new(0));
+    let payload = opt.payload;
+    let metric = if opt.parallel {
+        parallel(
+            opt,
+            config,
+            output.clone(),
+            run.clone(),
+            summary.clone(),
+            errors.clone(),
+        )
+        .await;
+        "latency.parallel"
     } else {
-        config.scouting.multicast.set_enabled(Some(true)).unwrap();
-    }
+        single(
+            opt,
+            config,
+            output.clone(),
+            run.clone(),
+            summary.clone(),
+            errors.clone(),
+        )
+        .await;
+        "latency.sequential"
+    };
 
-    if opt.parallel {
-        parallel(opt, config).await;
-    } else {
-        single(opt, config).await;
-    }
+    summary.emit(&output, metric, payload, "us");
+    // The pongs which are not the echo of a ping are left out of the latency
+    let errors = errors.load(Ordering::Relaxed) as f64;
+    output.emit("pong.errors", payload, errors, "msg");
+    run.emit_totals(&output, payload);
 }
@@ -11,22 +11,28 @@
 // Contributors:
 //   ADLINK zenoh team, <zenoh@adlink-labs.tech>
 //
-use async_std::future;
+use async_std::prelude::FutureExt;
 use async_std::stream::StreamExt;
 use clap::Parser;
-use zenoh::config::Config;
-use zenoh_protocol_core::{CongestionControl, WhatAmI};
+#[cfg(feature = "shared-memory")]
+use zenoh_perf_common::ShmOpt;
+use zenoh_perf_common::{CommonOpt, CongestionOpt, ReliabilityOpt, Run};
 
 #[derive(Debug, Parser)]
 #[clap(name = "z_pong")]
 struct Opt {
-    /// endpoint(s), e.g. --endpoint tcp/127.0.0.1:7447,tcp/127.0.0.1:7448
-    #[clap(short, long)]
-    endpoint: String,
+    #[clap(flatten)]
+    common: CommonOpt,
 
-    /// peer or client
-    #[clap(short, long, possible_values = ["peer", "client"])]
-    mode: String,
+    #[clap(flatten)]
+    reliability: ReliabilityOpt,
+
+    #[clap(flatten)]
+    congestion: CongestionOpt,
+
+    #[cfg(feature = "shared-memory")]
+    #[clap(flatten)]
+    shm: ShmOpt,
 
     /// declare a numerical ID for key expression
     #[clap(long)]
@@ -47,34 +53,26 @@ async fn main() {
 
     // Parse the args
     let opt = Opt::parse();
-
-    let mut config = Config::default();
-    match opt.mode.as_str() {
-        "peer" => {
-            config.set_mode(Some(WhatAmI::Peer)).unwrap();
-            config
-                .listen
-                .endpoints
-                .extend(opt.endpoint.split(',').map(|v| v.parse().unwrap()));
-        }
-        "client" => {
-            config.set_mode(Some(WhatAmI::Client)).unwrap();
-            config
-                .connect
-                .endpoints
-                .extend(opt.endpoint.split(',').map(|v| v.parse().unwrap()));
-        }
-        _ => panic!("Unsupported mode: {}", opt.mode),
-    };
-    config.scouting.multicast.set_enabled(Some(false)).unwrap();
+    let config = opt.common.config();
+    // The pings read from shared memory are echoed without copy
+    #[cfg(feature = "shared-memory")]
+    let config = opt.shm.apply(config);
 
     let session = zenoh::open(config).await.unwrap();
     let mut sub = if opt.use_expr {
         // Declare the subscriber
         let key_expr_ping = session.declare_expr(KEY_EXPR_PING).await.unwrap();
-        session.subscribe(key_expr_ping).reliable().await.unwrap()
+        session
+            .subscribe(key_expr_ping)
+            .reliability(opt.reliability.reliability)
+            .await
+            .unwrap()
     } else {
-        session.subscribe(KEY_EXPR_PING).reliable().await.unwrap()
+        session
+            .subscribe(KEY_EXPR_PING)
+            .reliability(opt.reliability.reliability)
+            .await
+            .unwrap()
     };
     let mut key_expr_pong = 0;
     if opt.use_expr {
@@ -86,18 +84,20 @@ async fn main() {
         session.declare_publication(KEY_EXPR_PONG).await.unwrap();
     }
 
-    while let Some(sample) = sub.next().await {
-        let writer = if opt.use_expr {
-            session.put(key_expr_pong, sample)
-        } else {
-            session.put(KEY_EXPR_PONG, sample)
-        };
-        writer
-            .congestion_control(CongestionControl::Block)
-            .await
-            .unwrap();
-    }
+    let echo = async {
+        while let Some(sample) = sub.next().await {
+            let writer = if opt.use_expr {
+                session.put(key_expr_pong, sample)
+            } else {
+                session.put(KEY_EXPR_PONG, sample)
+            };
+            writer
+                .congestion_control(opt.congestion.congestion)
+                .await
+                .unwrap();
+        }
+    };
 
-    // Stop forever
-    future::pending::<()>().await;
+    // Echo until SIGINT or SIGTERM
+    echo.race(Run::new(None, None, None).wait()).await;
 }
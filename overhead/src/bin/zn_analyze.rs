@@ -13,7 +13,7 @@
 //
 
 extern crate serde;
-use structopt::StructOpt;
+use clap::Parser;
 //use zenoh::net::ResKey::*;
 use async_std::fs;
 use serde::{Deserialize, Serialize};
@@ -23,10 +23,10 @@ use zenoh::net::protocol::proto::{
     FramePayload, TransportBody, TransportMessage, ZenohBody, ZenohMessage,
 };
 
-#[derive(Debug, StructOpt)]
-#[structopt(name = "zn_analyze")]
+#[derive(Debug, Parser)]
+#[clap(name = "zn_analyze")]
 struct Opt {
-    #[structopt(short = "j", long = "json")]
+    #[clap(short = 'j', long = "json")]
     file: String,
 }
 
@@ -101,7 +101,7 @@ async fn main() {
     env_logger::init();
 
     // Parse the args
-    let opt = Opt::from_args();
+    let opt = Opt::parse();
 
     let contents = fs::read_to_string(opt.file).await.unwrap();
     let pkts: Vec<PcapData> = serde_json::from_str(&contents).unwrap();
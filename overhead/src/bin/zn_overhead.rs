@@ -13,28 +13,37 @@
 //
 use async_std::sync::Arc;
 use async_std::task;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use clap::Parser;
 use std::time::Duration;
-use structopt::StructOpt;
-use zenoh::net::ResKey::*;
-use zenoh::net::*;
-use zenoh::Properties;
+use zenoh::prelude::{CongestionControl, KeyExpr, Value};
+use zenoh_perf_common::{CommonOpt, Meter, Output, OutputFormat, RunOpt};
 
-#[derive(Debug, StructOpt)]
-#[structopt(name = "zn_overhead")]
+#[derive(Debug, Parser)]
+#[clap(name = "zn_overhead")]
 struct Opt {
-    #[structopt(short = "l", long = "locator")]
-    locator: String,
-    #[structopt(short = "m", long = "mode")]
-    mode: String,
-    #[structopt(short = "p", long = "payload")]
+    #[clap(flatten)]
+    common: CommonOpt,
+
+    #[clap(flatten)]
+    run: RunOpt,
+
+    #[clap(short, long)]
     payload: usize,
-    #[structopt(short = "v", long = "verbose")]
+
+    #[clap(short = 'v', long = "verbose")]
     print: bool,
-    #[structopt(short = "t", long = "total", default_value = "1048576")] //1MB in bytes
+
+    /// total amount of payload to send (MB)
+    #[clap(short, long, default_value_t = 1048576)]
     total: u64,
-    #[structopt(short = "i", long = "interval", default_value = "0")]
+
+    /// pause between two messages (seconds)
+    #[clap(short, long, default_value_t = 0.0)]
     interval: f64,
+
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
 
 #[async_std::main]
@@ -43,26 +52,22 @@ async fn main() {
     env_logger::init();
 
     // Parse the args
-    let opt = Opt::from_args();
+    let opt = Opt::parse();
 
     let bytes_in_mb: u64 = 1048576;
 
-    let mut config = Properties::default();
-    config.insert("mode".to_string(), opt.mode.clone());
-    config.insert("add_timestamp".to_string(), "false".to_string());
+    let config = {
+        let mut config = opt.common.config();
+        config.set_add_timestamp(Some(false)).unwrap();
+        config
+    };
 
-    config.insert("multicast_scouting".to_string(), "false".to_string());
-    config.insert("peer".to_string(), opt.locator);
+    let session = zenoh::open(config).await.unwrap();
 
-    let session = open(config.into()).await.unwrap();
+    let key_expr: KeyExpr = session.declare_expr("/test/overhead").await.unwrap().into();
+    session.declare_publication(key_expr.clone());
 
-    let reskey = RId(session
-        .declare_resource(&RName("/test/overhead".to_string()))
-        .await
-        .unwrap());
-    let _publ = session.declare_publisher(&reskey).await.unwrap();
-
-    let data: ZBuf = (0usize..opt.payload)
+    let data: Value = (0usize..opt.payload)
         .map(|i| (i % 10) as u8)
         .collect::<Vec<u8>>()
         .into();
@@ -70,48 +75,41 @@ async fn main() {
     let mut i: u64 = 0;
     let tot: u64 = (opt.total * bytes_in_mb) / (opt.payload as u64);
 
+    let run = opt.run.start();
+    let output = Output::new(opt.output, "zn_overhead", "session");
+    let throughput = run.throughput();
+    let meter = Arc::new(Meter::new());
     if opt.print {
-        let count = Arc::new(AtomicUsize::new(0));
-        let c_count = count.clone();
+        let c_meter = meter.clone();
+        let c_output = output.clone();
+        let c_throughput = throughput.clone();
         task::spawn(async move {
             loop {
                 task::sleep(Duration::from_secs(1)).await;
-                let c = count.swap(0, Ordering::Relaxed);
-                if c > 0 {
-                    println!("{} msg/s", c);
+                if let Some((payload, c)) = c_meter.take() {
+                    if c > 0 {
+                        c_throughput.record(&c_output, "throughput", payload, c as f64, &[]);
+                    }
                 }
             }
         });
+    }
 
-        while i < tot {
-            session
-                .write_ext(
-                    &reskey,
-                    data.clone(),
-                    encoding::DEFAULT,
-                    data_kind::DEFAULT,
-                    CongestionControl::Block, // Make sure to not drop messages because of congestion control
-                )
-                .await
-                .unwrap();
-            c_count.fetch_add(1, Ordering::Relaxed);
-            i += 1;
-            task::sleep(Duration::from_secs_f64(opt.interval)).await;
-        }
-    } else {
-        while i < tot {
-            session
-                .write_ext(
-                    &reskey,
-                    data.clone(),
-                    encoding::DEFAULT,
-                    data_kind::DEFAULT,
-                    CongestionControl::Block, // Make sure to not drop messages because of congestion control
-                )
-                .await
-                .unwrap();
-            i += 1;
-            task::sleep(Duration::from_secs_f64(opt.interval)).await;
-        }
+    while i < tot && run.is_running() {
+        session
+            .put(key_expr.clone(), data.clone())
+            // Make sure to not drop messages because of congestion control
+            .congestion_control(CongestionControl::Block)
+            .await
+            .unwrap();
+        meter.add(opt.payload);
+        run.tick();
+        i += 1;
+        task::sleep(Duration::from_secs_f64(opt.interval)).await;
+    }
+
+    if opt.print {
+        throughput.emit(&output, "throughput");
     }
+    run.emit_totals(&output, opt.payload);
 }
@@ -11,21 +11,20 @@
 // Contributors:
 //   ADLINK zenoh team, <zenoh@adlink-labs.tech>
 //
-use async_std::future;
+use async_std::prelude::FutureExt;
 use async_std::task;
+use clap::Parser;
 use std::sync::{Arc, Mutex};
-use structopt::StructOpt;
-use zenoh::net::protocol::core::{
-    Channel, CongestionControl, PeerId, QueryConsolidation, QueryTarget, ResKey, SubInfo, ZInt,
-};
 use zenoh::net::protocol::io::ZBuf;
 use zenoh::net::protocol::proto::{DataInfo, RoutingContext};
-use zenoh::net::queryable::ALL_KINDS;
 use zenoh::net::routing::face::Face;
 use zenoh::net::runtime::Runtime;
 use zenoh::net::transport::Primitives;
-use zenoh_util::properties::config::{
-    ConfigProperties, ZN_LISTENER_KEY, ZN_MODE_KEY, ZN_MULTICAST_SCOUTING_KEY, ZN_PEER_KEY,
+use zenoh::queryable::EVAL;
+use zenoh_perf_common::{CommonOpt, Run};
+use zenoh_protocol_core::{
+    Channel, CongestionControl, ConsolidationStrategy, KeyExpr, PeerId, QueryTarget, QueryableInfo,
+    SubInfo, ZInt,
 };
 
 struct EvalPrimitives {
@@ -50,30 +49,37 @@ impl EvalPrimitives {
 }
 
 impl Primitives for EvalPrimitives {
-    fn decl_resource(&self, _rid: ZInt, _reskey: &ResKey) {}
-    fn forget_resource(&self, _rid: ZInt) {}
-    fn decl_publisher(&self, _reskey: &ResKey, _routing_context: Option<RoutingContext>) {}
-    fn forget_publisher(&self, _reskey: &ResKey, _routing_context: Option<RoutingContext>) {}
+    fn decl_resource(&self, _expr_id: ZInt, _key_expr: &KeyExpr) {}
+    fn forget_resource(&self, _expr_id: ZInt) {}
+    fn decl_publisher(&self, _key_expr: &KeyExpr, _routing_context: Option<RoutingContext>) {}
+    fn forget_publisher(&self, _key_expr: &KeyExpr, _routing_context: Option<RoutingContext>) {}
     fn decl_subscriber(
         &self,
-        _reskey: &ResKey,
+        _key_expr: &KeyExpr,
         _sub_info: &SubInfo,
         _routing_context: Option<RoutingContext>,
     ) {
     }
-    fn forget_subscriber(&self, _reskey: &ResKey, _routing_context: Option<RoutingContext>) {}
+    fn forget_subscriber(&self, _key_expr: &KeyExpr, _routing_context: Option<RoutingContext>) {}
     fn decl_queryable(
         &self,
-        _reskey: &ResKey,
+        _key_expr: &KeyExpr,
+        _kind: ZInt,
+        _qable_info: &QueryableInfo,
+        _routing_context: Option<RoutingContext>,
+    ) {
+    }
+    fn forget_queryable(
+        &self,
+        _key_expr: &KeyExpr,
         _kind: ZInt,
         _routing_context: Option<RoutingContext>,
     ) {
     }
-    fn forget_queryable(&self, _reskey: &ResKey, _routing_context: Option<RoutingContext>) {}
 
     fn send_data(
         &self,
-        _reskey: &ResKey,
+        _key_expr: &KeyExpr,
         _payload: ZBuf,
         _channel: Channel,
         _congestion_control: CongestionControl,
@@ -83,15 +89,15 @@ impl Primitives for EvalPrimitives {
     }
     fn send_query(
         &self,
-        reskey: &ResKey,
-        _predicate: &str,
+        key_expr: &KeyExpr,
+        _value_selector: &str,
         qid: ZInt,
         _target: QueryTarget,
-        _consolidation: QueryConsolidation,
+        _consolidation: ConsolidationStrategy,
         _routing_context: Option<RoutingContext>,
     ) {
-        let reskey = reskey.clone();
-        let source_kind = 0;
+        let key_expr = key_expr.to_owned();
+        let replier_kind = EVAL;
         let pid = self.pid;
         let info = None;
         let payload = ZBuf::from(vec![0u8; self.payload]);
@@ -99,16 +105,16 @@ impl Primitives for EvalPrimitives {
 
         // @TODO: once the router is re-entrant remove the task spawn
         task::spawn(async move {
-            tx_primitives.send_reply_data(qid, source_kind, pid, reskey, info, payload);
+            tx_primitives.send_reply_data(qid, replier_kind, pid, key_expr, info, payload);
             tx_primitives.send_reply_final(qid);
         });
     }
     fn send_reply_data(
         &self,
         _qid: ZInt,
-        _source_kind: ZInt,
+        _replier_kind: ZInt,
         _replier_id: PeerId,
-        _reskey: ResKey,
+        _key_expr: KeyExpr,
         _info: Option<DataInfo>,
         _payload: ZBuf,
     ) {
@@ -117,7 +123,7 @@ impl Primitives for EvalPrimitives {
     fn send_pull(
         &self,
         _is_final: bool,
-        _reskey: &ResKey,
+        _key_expr: &KeyExpr,
         _pull_id: ZInt,
         _max_samples: &Option<ZInt>,
     ) {
@@ -125,14 +131,13 @@ impl Primitives for EvalPrimitives {
     fn send_close(&self) {}
 }
 
-#[derive(Debug, StructOpt)]
-#[structopt(name = "r_eval")]
+#[derive(Debug, Parser)]
+#[clap(name = "r_eval")]
 struct Opt {
-    #[structopt(short = "l", long = "locator")]
-    locator: String,
-    #[structopt(short = "m", long = "mode")]
-    mode: String,
-    #[structopt(short = "p", long = "payload")]
+    #[clap(flatten)]
+    common: CommonOpt,
+
+    #[clap(short, long)]
     payload: usize,
 }
 
@@ -142,29 +147,24 @@ async fn main() {
     env_logger::init();
 
     // Parse the args
-    let opt = Opt::from_args();
-
-    let mut config = ConfigProperties::default();
-    config.insert(ZN_MODE_KEY, opt.mode.clone());
+    let Opt { common, payload } = Opt::parse();
+    let config = common.config();
 
-    config.insert(ZN_MULTICAST_SCOUTING_KEY, "false".to_string());
-    match opt.mode.as_str() {
-        "peer" | "router" => config.insert(ZN_LISTENER_KEY, opt.locator),
-        "client" => config.insert(ZN_PEER_KEY, opt.locator),
-        _ => panic!("Unsupported mode: {}", opt.mode),
-    };
+    let runtime = Runtime::new(config).await.unwrap();
 
-    let runtime = Runtime::new(0u8, config, None).await.unwrap();
-
-    let rx_primitives = Arc::new(EvalPrimitives::new(opt.payload));
+    let rx_primitives = Arc::new(EvalPrimitives::new(payload));
     let tx_primitives = runtime.router.new_primitives(rx_primitives.clone());
     rx_primitives.set_tx(tx_primitives.clone());
 
-    let rid = ResKey::RName("/test/query".to_string());
-    let kind = ALL_KINDS;
+    let key_expr = KeyExpr::from("/test/query".to_string());
+    let kind = EVAL;
+    let qable_info = QueryableInfo {
+        complete: 1,
+        distance: 0,
+    };
     let routing_context = None;
-    tx_primitives.decl_queryable(&rid, kind, routing_context);
+    tx_primitives.decl_queryable(&key_expr, kind, &qable_info, routing_context);
 
-    // Stop forever
-    future::pending::<()>().await;
+    // Reply until SIGINT or SIGTERM
+    Run::new(None, None, None).wait().await;
 }
@@ -11,64 +11,74 @@
 // Contributors:
 //   ADLINK zenoh team, <zenoh@adlink-labs.tech>
 //
+use clap::Parser;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Barrier, Mutex};
 use std::time::Instant;
-use structopt::StructOpt;
-use zenoh::net::protocol::core::{
-    Channel, CongestionControl, PeerId, QueryConsolidation, QueryTarget, ResKey, SubInfo, ZInt,
-};
 use zenoh::net::protocol::io::ZBuf;
 use zenoh::net::protocol::proto::{DataInfo, RoutingContext};
 use zenoh::net::runtime::Runtime;
 use zenoh::net::transport::Primitives;
-use zenoh_util::properties::config::{
-    ConfigProperties, ZN_MODE_KEY, ZN_MULTICAST_SCOUTING_KEY, ZN_PEER_KEY,
+use zenoh_perf_common::{CommonOpt, Output, OutputFormat, RunOpt, Summary};
+use zenoh_protocol_core::{
+    Channel, CongestionControl, ConsolidationStrategy, KeyExpr, PeerId, QueryTarget, QueryableInfo,
+    SubInfo, ZInt,
 };
 
 type Pending = Arc<Mutex<HashMap<u64, (Instant, Arc<Barrier>)>>>;
 
 struct QueryPrimitives {
-    scenario: String,
-    name: String,
+    output: Output,
+    summary: Arc<Summary>,
+    // The payload of the last reply
+    payload: AtomicUsize,
     pending: Pending,
 }
 
 impl QueryPrimitives {
-    pub fn new(scenario: String, name: String, pending: Pending) -> QueryPrimitives {
+    pub fn new(output: Output, summary: Arc<Summary>, pending: Pending) -> QueryPrimitives {
         QueryPrimitives {
-            scenario,
-            name,
+            output,
+            summary,
+            payload: AtomicUsize::new(0),
             pending,
         }
     }
 }
 
 impl Primitives for QueryPrimitives {
-    fn decl_resource(&self, _rid: ZInt, _reskey: &ResKey) {}
-    fn forget_resource(&self, _rid: ZInt) {}
-    fn decl_publisher(&self, _reskey: &ResKey, _routing_context: Option<RoutingContext>) {}
-    fn forget_publisher(&self, _reskey: &ResKey, _routing_context: Option<RoutingContext>) {}
+    fn decl_resource(&self, _expr_id: ZInt, _key_expr: &KeyExpr) {}
+    fn forget_resource(&self, _expr_id: ZInt) {}
+    fn decl_publisher(&self, _key_expr: &KeyExpr, _routing_context: Option<RoutingContext>) {}
+    fn forget_publisher(&self, _key_expr: &KeyExpr, _routing_context: Option<RoutingContext>) {}
     fn decl_subscriber(
         &self,
-        _reskey: &ResKey,
+        _key_expr: &KeyExpr,
         _sub_info: &SubInfo,
         _routing_context: Option<RoutingContext>,
     ) {
     }
-    fn forget_subscriber(&self, _reskey: &ResKey, _routing_context: Option<RoutingContext>) {}
+    fn forget_subscriber(&self, _key_expr: &KeyExpr, _routing_context: Option<RoutingContext>) {}
     fn decl_queryable(
         &self,
-        _reskey: &ResKey,
+        _key_expr: &KeyExpr,
+        _kind: ZInt,
+        _qable_info: &QueryableInfo,
+        _routing_context: Option<RoutingContext>,
+    ) {
+    }
+    fn forget_queryable(
+        &self,
+        _key_expr: &KeyExpr,
         _kind: ZInt,
         _routing_context: Option<RoutingContext>,
     ) {
     }
-    fn forget_queryable(&self, _reskey: &ResKey, _routing_context: Option<RoutingContext>) {}
 
     fn send_data(
         &self,
-        _reskey: &ResKey,
+        _key_expr: &KeyExpr,
         _payload: ZBuf,
         _channel: Channel,
         _congestion_control: CongestionControl,
@@ -78,40 +88,42 @@ impl Primitives for QueryPrimitives {
     }
     fn send_query(
         &self,
-        _reskey: &ResKey,
-        _predicate: &str,
+        _key_expr: &KeyExpr,
+        _value_selector: &str,
         _qid: ZInt,
         _target: QueryTarget,
-        _consolidation: QueryConsolidation,
+        _consolidation: ConsolidationStrategy,
         _routing_context: Option<RoutingContext>,
     ) {
     }
     fn send_reply_data(
         &self,
         qid: ZInt,
-        _source_kind: ZInt,
+        _replier_kind: ZInt,
         _replier_id: PeerId,
-        _reskey: ResKey,
+        _key_expr: KeyExpr,
         _info: Option<DataInfo>,
         payload: ZBuf,
     ) {
         let tuple = self.pending.lock().unwrap().remove(&qid).unwrap();
         let (instant, barrier) = (tuple.0, tuple.1);
         barrier.wait();
-        println!(
-            "router,{},query.latency,{},{},{},{}",
-            self.scenario,
-            self.name,
+        let value = instant.elapsed().as_micros() as f64;
+        self.summary.record(
+            &self.output,
+            "query.latency",
             payload.len(),
-            qid,
-            instant.elapsed().as_micros()
+            value,
+            "us",
+            &[("seq", qid.to_string())],
         );
+        self.payload.store(payload.len(), Ordering::Relaxed);
     }
     fn send_reply_final(&self, _qid: ZInt) {}
     fn send_pull(
         &self,
         _is_final: bool,
-        _reskey: &ResKey,
+        _key_expr: &KeyExpr,
         _pull_id: ZInt,
         _max_samples: &Option<ZInt>,
     ) {
@@ -119,17 +131,24 @@ impl Primitives for QueryPrimitives {
     fn send_close(&self) {}
 }
 
-#[derive(Debug, StructOpt)]
-#[structopt(name = "r_query")]
+#[derive(Debug, Parser)]
+#[clap(name = "r_query")]
 struct Opt {
-    #[structopt(short = "l", long = "locator")]
-    locator: String,
-    #[structopt(short = "m", long = "mode")]
-    mode: String,
-    #[structopt(short = "n", long = "name")]
+    #[clap(flatten)]
+    common: CommonOpt,
+
+    #[clap(flatten)]
+    run: RunOpt,
+
+    #[clap(short, long)]
     name: String,
-    #[structopt(short = "s", long = "scenario")]
+
+    #[clap(short, long)]
     scenario: String,
+
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
 
 #[async_std::main]
@@ -138,32 +157,39 @@ async fn main() {
     env_logger::init();
 
     // Parse the args
-    let opt = Opt::from_args();
-
-    let mut config = ConfigProperties::default();
-    config.insert(ZN_MODE_KEY, opt.mode.clone());
+    let Opt {
+        common,
+        run,
+        name,
+        scenario,
+        output,
+    } = Opt::parse();
+    let config = common.config();
 
-    config.insert(ZN_MULTICAST_SCOUTING_KEY, "false".to_string());
-    config.insert(ZN_PEER_KEY, opt.locator);
+    let output = Output::new(output, "r_query", "router")
+        .label("scenario", scenario)
+        .label("name", name);
 
     let pending: Pending = Arc::new(Mutex::new(HashMap::new()));
 
-    let runtime = Runtime::new(0u8, config, None).await.unwrap();
+    let run = run.start();
+    let summary = run.summary();
+    let runtime = Runtime::new(config).await.unwrap();
     let rx_primitives = Arc::new(QueryPrimitives::new(
-        opt.scenario,
-        opt.name,
+        output.clone(),
+        summary.clone(),
         pending.clone(),
     ));
-    let tx_primitives = runtime.router.new_primitives(rx_primitives);
+    let tx_primitives = runtime.router.new_primitives(rx_primitives.clone());
 
     let barrier = Arc::new(Barrier::new(2));
     let mut count: u64 = 0;
-    loop {
-        let reskey = ResKey::RName("/test/query".to_string());
-        let predicate = "";
+    while run.is_running() {
+        let key_expr = KeyExpr::from("/test/query".to_string());
+        let value_selector = "";
         let qid = count;
         let target = QueryTarget::default();
-        let consolidation = QueryConsolidation::default();
+        let consolidation = ConsolidationStrategy::default();
         let routing_context = None;
 
         // Insert the pending query
@@ -172,8 +198,8 @@ async fn main() {
             .unwrap()
             .insert(count, (Instant::now(), barrier.clone()));
         tx_primitives.send_query(
-            &reskey,
-            predicate,
+            &key_expr,
+            value_selector,
             qid,
             target.clone(),
             consolidation.clone(),
@@ -181,7 +207,12 @@ async fn main() {
         );
         // Wait for the reply to arrive
         barrier.wait();
+        run.tick();
 
         count += 1;
     }
+
+    let payload = rx_primitives.payload.load(Ordering::Relaxed);
+    summary.emit(&output, "query.latency", payload, "us");
+    run.emit_totals(&output, payload);
 }
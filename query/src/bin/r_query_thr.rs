@@ -12,20 +12,19 @@
 //   ADLINK zenoh team, <zenoh@adlink-labs.tech>
 //
 use async_std::task;
+use clap::Parser;
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Barrier, Mutex};
 use std::time::{Duration, Instant};
-use structopt::StructOpt;
-use zenoh::net::protocol::core::{
-    Channel, CongestionControl, PeerId, QueryConsolidation, QueryTarget, ResKey, SubInfo, ZInt,
-};
 use zenoh::net::protocol::io::ZBuf;
 use zenoh::net::protocol::proto::{DataInfo, RoutingContext};
 use zenoh::net::runtime::Runtime;
 use zenoh::net::transport::Primitives;
-use zenoh_util::properties::config::{
-    ConfigProperties, ZN_MODE_KEY, ZN_MULTICAST_SCOUTING_KEY, ZN_PEER_KEY,
+use zenoh_perf_common::{CommonOpt, Output, OutputFormat, RunOpt};
+use zenoh_protocol_core::{
+    Channel, CongestionControl, ConsolidationStrategy, KeyExpr, PeerId, QueryTarget, QueryableInfo,
+    SubInfo, ZInt,
 };
 
 type Pending = Arc<Mutex<HashMap<u64, Arc<Barrier>>>>;
@@ -41,30 +40,37 @@ impl QueryPrimitives {
 }
 
 impl Primitives for QueryPrimitives {
-    fn decl_resource(&self, _rid: ZInt, _reskey: &ResKey) {}
-    fn forget_resource(&self, _rid: ZInt) {}
-    fn decl_publisher(&self, _reskey: &ResKey, _routing_context: Option<RoutingContext>) {}
-    fn forget_publisher(&self, _reskey: &ResKey, _routing_context: Option<RoutingContext>) {}
+    fn decl_resource(&self, _expr_id: ZInt, _key_expr: &KeyExpr) {}
+    fn forget_resource(&self, _expr_id: ZInt) {}
+    fn decl_publisher(&self, _key_expr: &KeyExpr, _routing_context: Option<RoutingContext>) {}
+    fn forget_publisher(&self, _key_expr: &KeyExpr, _routing_context: Option<RoutingContext>) {}
     fn decl_subscriber(
         &self,
-        _reskey: &ResKey,
+        _key_expr: &KeyExpr,
         _sub_info: &SubInfo,
         _routing_context: Option<RoutingContext>,
     ) {
     }
-    fn forget_subscriber(&self, _reskey: &ResKey, _routing_context: Option<RoutingContext>) {}
+    fn forget_subscriber(&self, _key_expr: &KeyExpr, _routing_context: Option<RoutingContext>) {}
     fn decl_queryable(
         &self,
-        _reskey: &ResKey,
+        _key_expr: &KeyExpr,
+        _kind: ZInt,
+        _qable_info: &QueryableInfo,
+        _routing_context: Option<RoutingContext>,
+    ) {
+    }
+    fn forget_queryable(
+        &self,
+        _key_expr: &KeyExpr,
         _kind: ZInt,
         _routing_context: Option<RoutingContext>,
     ) {
     }
-    fn forget_queryable(&self, _reskey: &ResKey, _routing_context: Option<RoutingContext>) {}
 
     fn send_data(
         &self,
-        _reskey: &ResKey,
+        _key_expr: &KeyExpr,
         _payload: ZBuf,
         _channel: Channel,
         _congestion_control: CongestionControl,
@@ -74,20 +80,20 @@ impl Primitives for QueryPrimitives {
     }
     fn send_query(
         &self,
-        _reskey: &ResKey,
-        _predicate: &str,
+        _key_expr: &KeyExpr,
+        _value_selector: &str,
         _qid: ZInt,
         _target: QueryTarget,
-        _consolidation: QueryConsolidation,
+        _consolidation: ConsolidationStrategy,
         _routing_context: Option<RoutingContext>,
     ) {
     }
     fn send_reply_data(
         &self,
         qid: ZInt,
-        _source_kind: ZInt,
+        _replier_kind: ZInt,
         _replier_id: PeerId,
-        _reskey: ResKey,
+        _key_expr: KeyExpr,
         _info: Option<DataInfo>,
         _payload: ZBuf,
     ) {
@@ -98,7 +104,7 @@ impl Primitives for QueryPrimitives {
     fn send_pull(
         &self,
         _is_final: bool,
-        _reskey: &ResKey,
+        _key_expr: &KeyExpr,
         _pull_id: ZInt,
         _max_samples: &Option<ZInt>,
     ) {
@@ -106,19 +112,27 @@ impl Primitives for QueryPrimitives {
     fn send_close(&self) {}
 }
 
-#[derive(Debug, StructOpt)]
-#[structopt(name = "r_query")]
+#[derive(Debug, Parser)]
+#[clap(name = "r_query_thr")]
 struct Opt {
-    #[structopt(short = "l", long = "locator")]
-    locator: String,
-    #[structopt(short = "m", long = "mode")]
-    mode: String,
-    #[structopt(short = "n", long = "name")]
+    #[clap(flatten)]
+    common: CommonOpt,
+
+    #[clap(flatten)]
+    run: RunOpt,
+
+    #[clap(short, long)]
     name: String,
-    #[structopt(short = "s", long = "scenario")]
+
+    #[clap(short, long)]
     scenario: String,
-    #[structopt(short = "p", long = "payload")]
+
+    #[clap(short, long)]
     payload: usize,
+
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
 
 #[async_std::main]
@@ -127,26 +141,39 @@ async fn main() {
     env_logger::init();
 
     // Parse the args
-    let opt = Opt::from_args();
-
-    let mut config = ConfigProperties::default();
-    config.insert(ZN_MODE_KEY, opt.mode.clone());
-
-    config.insert(ZN_MULTICAST_SCOUTING_KEY, "false".to_string());
-    config.insert(ZN_PEER_KEY, opt.locator.clone());
+    let Opt {
+        common,
+        run,
+        name,
+        scenario,
+        payload,
+        output,
+    } = Opt::parse();
+    let config = common.config();
+
+    let output = Output::new(output, "r_query_thr", "router")
+        .label("scenario", scenario)
+        .label("name", name);
+    let run = run.start();
+    let throughput = run.summary();
+    let rtts = run.summary();
 
     let rtt = Arc::new(AtomicUsize::new(0));
     let counter = Arc::new(AtomicUsize::new(0));
     let pending: Pending = Arc::new(Mutex::new(HashMap::new()));
 
-    let runtime = Runtime::new(0u8, config, None).await.unwrap();
+    let runtime = Runtime::new(config).await.unwrap();
     let rx_primitives = Arc::new(QueryPrimitives::new(pending.clone()));
     let tx_primitives = runtime.router.new_primitives(rx_primitives);
 
     let c_rtt = rtt.clone();
     let c_counter = counter.clone();
+    let c_output = output.clone();
+    let c_run = run.clone();
+    let c_throughput = throughput.clone();
+    let c_rtts = rtts.clone();
     task::spawn(async move {
-        loop {
+        while c_run.is_running() {
             let now = Instant::now();
             task::sleep(Duration::from_secs(1)).await;
             let elapsed = now.elapsed().as_micros() as f64;
@@ -154,26 +181,21 @@ async fn main() {
             let r = c_rtt.swap(0, Ordering::Relaxed);
             let c = c_counter.swap(0, Ordering::Relaxed);
             if c > 0 {
-                let interval = 1_000_000.0 / elapsed;
-                println!(
-                    "router,{},query.throughput,{},{},{},{}",
-                    opt.scenario,
-                    opt.name,
-                    opt.payload,
-                    (c as f64 / interval).floor() as usize,
-                    (r as f64 / c as f64).floor() as usize,
-                );
+                let value = (c as f64 * 1_000_000.0 / elapsed).floor();
+                c_throughput.record(&c_output, "query.throughput", payload, value, "msg/s", &[]);
+                let value = (r as f64 / c as f64).floor();
+                c_rtts.record(&c_output, "query.rtt", payload, value, "us", &[]);
             }
         }
     });
 
     let mut count: u64 = 0;
-    loop {
-        let reskey = ResKey::RName("/test/query".to_string());
-        let predicate = "";
+    while run.is_running() {
+        let key_expr = KeyExpr::from("/test/query".to_string());
+        let value_selector = "";
         let qid = count;
         let target = QueryTarget::default();
-        let consolidation = QueryConsolidation::default();
+        let consolidation = ConsolidationStrategy::default();
         let routing_context = None;
 
         // Insert the pending query
@@ -181,8 +203,8 @@ async fn main() {
         pending.lock().unwrap().insert(count, barrier.clone());
         let now = Instant::now();
         tx_primitives.send_query(
-            &reskey,
-            predicate,
+            &key_expr,
+            value_selector,
             qid,
             target.clone(),
             consolidation.clone(),
@@ -192,7 +214,12 @@ async fn main() {
         barrier.wait();
         rtt.fetch_add(now.elapsed().as_micros() as usize, Ordering::Relaxed);
         counter.fetch_add(1, Ordering::Relaxed);
+        run.tick();
 
         count += 1;
     }
+
+    throughput.emit(&output, "query.throughput", payload, "msg/s");
+    rtts.emit(&output, "query.rtt", payload, "us");
+    run.emit_totals(&output, payload);
 }
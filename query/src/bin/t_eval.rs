@@ -11,20 +11,17 @@
 // Contributors:
 //   ADLINK zenoh team, <zenoh@adlink-labs.tech>
 //
-use async_std::future;
 use async_std::sync::Arc;
+use clap::Parser;
 use std::any::Any;
-use std::path::PathBuf;
-use structopt::StructOpt;
-use zenoh::net::link::{EndPoint, Link};
-use zenoh::net::protocol::core::{
-    whatami, Channel, CongestionControl, Priority, Reliability, ResKey,
-};
+use zenoh::net::link::Link;
+use zenoh::net::protocol::core::{Channel, CongestionControl, Priority, Reliability};
 use zenoh::net::protocol::io::ZBuf;
 use zenoh::net::protocol::proto::{Query, ReplyContext, ZenohBody, ZenohMessage};
 use zenoh::net::transport::*;
-use zenoh_util::core::ZResult;
-use zenoh_util::properties::{IntKeyProperties, Properties};
+use zenoh::prelude::KeyExpr;
+use zenoh_core::zresult::ZResult;
+use zenoh_perf_common::{CommonOpt, Run};
 
 // Transport Handler for the peer
 struct MySH {
@@ -76,7 +73,7 @@ impl TransportPeerEventHandler for MyMH {
                     reliability: Reliability::Reliable,
                 };
                 let congestion_control = CongestionControl::Block;
-                let key = ResKey::RName("/test/query".to_string());
+                let key = KeyExpr::from("/test/query".to_string());
                 let info = None;
                 let payload = ZBuf::from(vec![0u8; self.payload]);
                 let routing_context = None;
@@ -109,17 +106,14 @@ impl TransportPeerEventHandler for MyMH {
     }
 }
 
-#[derive(Debug, StructOpt)]
-#[structopt(name = "s_eval")]
+#[derive(Debug, Parser)]
+#[clap(name = "t_eval")]
 struct Opt {
-    #[structopt(short = "l", long = "locator")]
-    locator: EndPoint,
-    #[structopt(short = "m", long = "mode")]
-    mode: String,
-    #[structopt(short = "p", long = "payload")]
+    #[clap(flatten)]
+    common: CommonOpt,
+
+    #[clap(short, long)]
     payload: usize,
-    #[structopt(long = "conf", parse(from_os_str))]
-    config: Option<PathBuf>,
 }
 
 #[async_std::main]
@@ -128,32 +122,23 @@ async fn main() {
     env_logger::init();
 
     // Parse the args
-    let opt = Opt::from_args();
-
-    let whatami = whatami::parse(opt.mode.as_str()).unwrap();
-
-    let bc = match opt.config.as_ref() {
-        Some(f) => {
-            let config = async_std::fs::read_to_string(f).await.unwrap();
-            let properties = Properties::from(config);
-            let int_props = IntKeyProperties::from(properties);
-            TransportManagerConfig::builder()
-                .from_config(&int_props)
-                .await
-                .unwrap()
-        }
-        None => TransportManagerConfig::builder().whatami(whatami),
-    };
-    let config = bc.build(Arc::new(MySH::new(opt.payload)));
-    let manager = TransportManager::new(config);
-
-    // Connect to the peer or listen
-    if whatami == whatami::PEER {
-        manager.add_listener(opt.locator).await.unwrap();
-    } else {
-        let _session = manager.open_transport(opt.locator).await.unwrap();
+    let Opt { common, payload } = Opt::parse();
+
+    let manager = TransportManager::builder()
+        .from_config(&common.config())
+        .await
+        .unwrap()
+        .build(Arc::new(MySH::new(payload)))
+        .unwrap();
+
+    // Listen for the queriers or connect to them
+    for e in common.listen_endpoints() {
+        manager.add_listener(e).await.unwrap();
+    }
+    for e in common.connect_endpoints() {
+        let _t = manager.open_transport_unicast(e).await.unwrap();
     }
 
-    // Stop forever
-    future::pending::<()>().await;
+    // Reply until SIGINT or SIGTERM
+    Run::new(None, None, None).wait().await;
 }
@@ -11,35 +11,39 @@
 // Contributors:
 //   ADLINK zenoh team, <zenoh@adlink-labs.tech>
 //
+use clap::Parser;
 use std::any::Any;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Barrier, Mutex};
 use std::time::Instant;
-use structopt::StructOpt;
-use zenoh::net::link::{EndPoint, Link};
-use zenoh::net::protocol::core::{whatami, QueryConsolidation, QueryTarget, ResKey};
+use zenoh::net::link::Link;
+use zenoh::net::protocol::core::{ConsolidationStrategy, QueryTarget};
 use zenoh::net::protocol::proto::{Data, ZenohBody, ZenohMessage};
 use zenoh::net::transport::*;
-use zenoh_util::core::ZResult;
-use zenoh_util::properties::{IntKeyProperties, Properties};
+use zenoh::prelude::KeyExpr;
+use zenoh_core::zresult::ZResult;
+use zenoh_perf_common::{CommonOpt, Output, OutputFormat, RunOpt, Summary};
 
 type Pending = Arc<Mutex<HashMap<u64, (Instant, Arc<Barrier>)>>>;
 
+// The replies to the pending queries, shared by the handlers
+struct Replies {
+    output: Output,
+    summary: Arc<Summary>,
+    // The payload of the last reply
+    payload: AtomicUsize,
+    pending: Pending,
+}
+
 // Transport Handler for the blocking locator
 struct MySH {
-    scenario: String,
-    name: String,
-    pending: Pending,
+    replies: Arc<Replies>,
 }
 
 impl MySH {
-    fn new(scenario: String, name: String, pending: Pending) -> Self {
-        Self {
-            scenario,
-            name,
-            pending,
-        }
+    fn new(replies: Arc<Replies>) -> Self {
+        Self { replies }
     }
 }
 
@@ -49,11 +53,7 @@ impl TransportEventHandler for MySH {
         _peer: TransportPeer,
         _transport: TransportUnicast,
     ) -> ZResult<Arc<dyn TransportPeerEventHandler>> {
-        Ok(Arc::new(MyMH::new(
-            self.scenario.clone(),
-            self.name.clone(),
-            self.pending.clone(),
-        )))
+        Ok(Arc::new(MyMH::new(self.replies.clone())))
     }
 
     fn new_multicast(
@@ -66,18 +66,12 @@ impl TransportEventHandler for MySH {
 
 // Message Handler for the locator
 struct MyMH {
-    scenario: String,
-    name: String,
-    pending: Pending,
+    replies: Arc<Replies>,
 }
 
 impl MyMH {
-    fn new(scenario: String, name: String, pending: Pending) -> Self {
-        Self {
-            scenario,
-            name,
-            pending,
-        }
+    fn new(replies: Arc<Replies>) -> Self {
+        Self { replies }
     }
 }
 
@@ -90,7 +84,8 @@ impl TransportPeerEventHandler for MyMH {
                 ..
             }) => {
                 let reply_context = reply_context.unwrap();
-                let tuple = self
+                let replies = &self.replies;
+                let tuple = replies
                     .pending
                     .lock()
                     .unwrap()
@@ -98,14 +93,16 @@ impl TransportPeerEventHandler for MyMH {
                     .unwrap();
                 let (instant, barrier) = (tuple.0, tuple.1);
                 barrier.wait();
-                println!(
-                    "session,{},query.latency,{},{},{},{}",
-                    self.scenario,
-                    self.name,
+                let value = instant.elapsed().as_micros() as f64;
+                replies.summary.record(
+                    &replies.output,
+                    "query.latency",
                     payload.len(),
-                    reply_context.qid,
-                    instant.elapsed().as_micros()
+                    value,
+                    "us",
+                    &[("seq", reply_context.qid.to_string())],
                 );
+                replies.payload.store(payload.len(), Ordering::Relaxed);
             }
             _ => panic!("Invalid message"),
         }
@@ -121,19 +118,24 @@ impl TransportPeerEventHandler for MyMH {
     }
 }
 
-#[derive(Debug, StructOpt)]
-#[structopt(name = "s_query")]
+#[derive(Debug, Parser)]
+#[clap(name = "t_query")]
 struct Opt {
-    #[structopt(short = "l", long = "locator")]
-    locator: EndPoint,
-    #[structopt(short = "m", long = "mode")]
-    mode: String,
-    #[structopt(short = "n", long = "name")]
+    #[clap(flatten)]
+    common: CommonOpt,
+
+    #[clap(flatten)]
+    run: RunOpt,
+
+    #[clap(short, long)]
     name: String,
-    #[structopt(short = "s", long = "scenario")]
+
+    #[clap(short, long)]
     scenario: String,
-    #[structopt(long = "conf", parse(from_os_str))]
-    config: Option<PathBuf>,
+
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
 
 #[async_std::main]
@@ -142,48 +144,56 @@ async fn main() {
     env_logger::init();
 
     // Parse the args
-    let opt = Opt::from_args();
-
-    let whatami = whatami::parse(opt.mode.as_str()).unwrap();
+    let Opt {
+        common,
+        run,
+        name,
+        scenario,
+        output,
+    } = Opt::parse();
+
+    let output = Output::new(output, "t_query", "transport")
+        .label("scenario", scenario)
+        .label("name", name);
 
     let pending: Pending = Arc::new(Mutex::new(HashMap::new()));
-
-    let bc = match opt.config.as_ref() {
-        Some(f) => {
-            let config = async_std::fs::read_to_string(f).await.unwrap();
-            let properties = Properties::from(config);
-            let int_props = IntKeyProperties::from(properties);
-            TransportManagerConfig::builder()
-                .from_config(&int_props)
-                .await
-                .unwrap()
-        }
-        None => TransportManagerConfig::builder().whatami(whatami),
-    };
-    let config = bc.build(Arc::new(MySH::new(
-        opt.scenario.clone(),
-        opt.name.clone(),
-        pending.clone(),
-    )));
-    let manager = TransportManager::new(config);
-
-    // Connect to publisher
-    let session = manager.open_transport(opt.locator).await.unwrap();
+    let run = run.start();
+    let replies = Arc::new(Replies {
+        output: output.clone(),
+        summary: run.summary(),
+        payload: AtomicUsize::new(0),
+        pending: pending.clone(),
+    });
+
+    let manager = TransportManager::builder()
+        .from_config(&common.config())
+        .await
+        .unwrap()
+        .build(Arc::new(MySH::new(replies.clone())))
+        .unwrap();
+
+    // Connect to the queryable
+    let endpoint = common
+        .connect_endpoints()
+        .into_iter()
+        .next()
+        .expect("Missing --connect endpoint of the queryable");
+    let session = manager.open_transport_unicast(endpoint).await.unwrap();
     let barrier = Arc::new(Barrier::new(2));
     let mut count: u64 = 0;
-    loop {
+    while run.is_running() {
         // Create and send the message
-        let key = ResKey::RName("/test/query".to_string());
-        let predicate = "".to_string();
+        let key = KeyExpr::from("/test/query".to_string());
+        let value_selector = "".to_string();
         let qid = count;
         let target = Some(QueryTarget::default());
-        let consolidation = QueryConsolidation::default();
+        let consolidation = ConsolidationStrategy::default();
         let routing_context = None;
         let attachment = None;
 
         let message = ZenohMessage::make_query(
             key,
-            predicate,
+            value_selector,
             qid,
             target,
             consolidation,
@@ -199,7 +209,14 @@ async fn main() {
         session.handle_message(message).unwrap();
         // Wait for the reply to arrive
         barrier.wait();
+        run.tick();
 
         count += 1;
     }
+
+    let payload = replies.payload.load(Ordering::Relaxed);
+    replies
+        .summary
+        .emit(&output, "query.latency", payload, "us");
+    run.emit_totals(&output, payload);
 }
@@ -12,19 +12,19 @@
 //   ADLINK zenoh team, <zenoh@adlink-labs.tech>
 //
 use async_std::task;
+use clap::Parser;
 use std::any::Any;
 use std::collections::HashMap;
-use std::path::PathBuf;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Barrier, Mutex};
 use std::time::{Duration, Instant};
-use structopt::StructOpt;
-use zenoh::net::link::{EndPoint, Link};
-use zenoh::net::protocol::core::{whatami, QueryConsolidation, QueryTarget, ResKey};
+use zenoh::net::link::Link;
+use zenoh::net::protocol::core::{ConsolidationStrategy, QueryTarget};
 use zenoh::net::protocol::proto::{Data, ZenohBody, ZenohMessage};
 use zenoh::net::transport::*;
-use zenoh_util::core::ZResult;
-use zenoh_util::properties::{IntKeyProperties, Properties};
+use zenoh::prelude::KeyExpr;
+use zenoh_core::zresult::ZResult;
+use zenoh_perf_common::{CommonOpt, Output, OutputFormat, RunOpt};
 
 type Pending = Arc<Mutex<HashMap<u64, Arc<Barrier>>>>;
 
@@ -94,21 +94,27 @@ impl TransportPeerEventHandler for MyMH {
     }
 }
 
-#[derive(Debug, StructOpt)]
-#[structopt(name = "s_query")]
+#[derive(Debug, Parser)]
+#[clap(name = "t_query_thr")]
 struct Opt {
-    #[structopt(short = "l", long = "locator")]
-    locator: EndPoint,
-    #[structopt(short = "m", long = "mode")]
-    mode: String,
-    #[structopt(short = "n", long = "name")]
+    #[clap(flatten)]
+    common: CommonOpt,
+
+    #[clap(flatten)]
+    run: RunOpt,
+
+    #[clap(short, long)]
     name: String,
-    #[structopt(short = "s", long = "scenario")]
+
+    #[clap(short, long)]
     scenario: String,
-    #[structopt(short = "p", long = "payload")]
+
+    #[clap(short, long)]
     payload: usize,
-    #[structopt(long = "conf", parse(from_os_str))]
-    config: Option<PathBuf>,
+
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
 
 #[async_std::main]
@@ -117,36 +123,49 @@ async fn main() {
     env_logger::init();
 
     // Parse the args
-    let opt = Opt::from_args();
-
-    let whatami = whatami::parse(opt.mode.as_str()).unwrap();
+    let Opt {
+        common,
+        run,
+        name,
+        scenario,
+        payload,
+        output,
+    } = Opt::parse();
+
+    let output = Output::new(output, "t_query_thr", "transport")
+        .label("scenario", scenario)
+        .label("name", name);
+    let run = run.start();
+    let throughput = run.summary();
+    let rtts = run.summary();
 
     let rtt = Arc::new(AtomicUsize::new(0));
     let counter = Arc::new(AtomicUsize::new(0));
     let pending: Pending = Arc::new(Mutex::new(HashMap::new()));
 
-    let bc = match opt.config.as_ref() {
-        Some(f) => {
-            let config = async_std::fs::read_to_string(f).await.unwrap();
-            let properties = Properties::from(config);
-            let int_props = IntKeyProperties::from(properties);
-            TransportManagerConfig::builder()
-                .from_config(&int_props)
-                .await
-                .unwrap()
-        }
-        None => TransportManagerConfig::builder().whatami(whatami),
-    };
-    let config = bc.build(Arc::new(MySH::new(pending.clone())));
-    let manager = TransportManager::new(config);
-
-    // Connect to publisher
-    let session = manager.open_transport(opt.locator.clone()).await.unwrap();
+    let manager = TransportManager::builder()
+        .from_config(&common.config())
+        .await
+        .unwrap()
+        .build(Arc::new(MySH::new(pending.clone())))
+        .unwrap();
+
+    // Connect to the queryable
+    let endpoint = common
+        .connect_endpoints()
+        .into_iter()
+        .next()
+        .expect("Missing --connect endpoint of the queryable");
+    let session = manager.open_transport_unicast(endpoint).await.unwrap();
 
     let c_rtt = rtt.clone();
     let c_counter = counter.clone();
+    let c_output = output.clone();
+    let c_run = run.clone();
+    let c_throughput = throughput.clone();
+    let c_rtts = rtts.clone();
     task::spawn(async move {
-        loop {
+        while c_run.is_running() {
             let now = Instant::now();
             task::sleep(Duration::from_secs(1)).await;
             let elapsed = now.elapsed().as_micros() as f64;
@@ -154,33 +173,28 @@ async fn main() {
             let r = c_rtt.swap(0, Ordering::Relaxed);
             let c = c_counter.swap(0, Ordering::Relaxed);
             if c > 0 {
-                let interval = 1_000_000.0 / elapsed;
-                println!(
-                    "session,{},query.throughput,{},{},{},{}",
-                    opt.scenario,
-                    opt.name,
-                    opt.payload,
-                    (c as f64 / interval).floor() as usize,
-                    (r as f64 / c as f64).floor() as usize,
-                );
+                let value = (c as f64 * 1_000_000.0 / elapsed).floor();
+                c_throughput.record(&c_output, "query.throughput", payload, value, "msg/s", &[]);
+                let value = (r as f64 / c as f64).floor();
+                c_rtts.record(&c_output, "query.rtt", payload, value, "us", &[]);
             }
         }
     });
 
     let mut count: u64 = 0;
-    loop {
+    while run.is_running() {
         // Create and send the message
-        let key = ResKey::RName("/test/query".to_string());
-        let predicate = "".to_string();
+        let key = KeyExpr::from("/test/query".to_string());
+        let value_selector = "".to_string();
         let qid = count;
         let target = Some(QueryTarget::default());
-        let consolidation = QueryConsolidation::default();
+        let consolidation = ConsolidationStrategy::default();
         let routing_context = None;
         let attachment = None;
 
         let message = ZenohMessage::make_query(
             key,
-            predicate,
+            value_selector,
             qid,
             target,
             consolidation,
@@ -197,7 +211,12 @@ async fn main() {
         barrier.wait();
         rtt.fetch_add(now.elapsed().as_micros() as usize, Ordering::Relaxed);
         counter.fetch_add(1, Ordering::Relaxed);
+        run.tick();
 
         count += 1;
     }
+
+    throughput.emit(&output, "query.throughput", payload, "msg/s");
+    rtts.emit(&output, "query.rtt", payload, "us");
+    run.emit_totals(&output, payload);
 }
@@ -11,20 +11,17 @@
 // Contributors:
 //   ADLINK zenoh team, <zenoh@adlink-labs.tech>
 //
+use async_std::prelude::FutureExt;
 use async_std::stream::StreamExt;
 use clap::Parser;
-use zenoh::{config::Config, prelude::Sample, queryable::EVAL};
-use zenoh_protocol_core::{EndPoint, WhatAmI};
+use zenoh::{prelude::Sample, queryable::EVAL};
+use zenoh_perf_common::{CommonOpt, Run};
 
 #[derive(Debug, Parser)]
 #[clap(name = "z_eval")]
 struct Opt {
-    /// endpoint(s), e.g. --endpoint tcp/127.0.0.1:7447,tcp/127.0.0.1:7448
-    #[clap(short, long)]
-    endpoint: Vec<EndPoint>,
-
-    #[clap(short, long)]
-    mode: WhatAmI,
+    #[clap(flatten)]
+    common: CommonOpt,
 
     #[clap(short, long)]
     payload: usize,
@@ -38,28 +35,19 @@ async fn main() {
     env_logger::init();
 
     // Parse the args
-    let Opt {
-        endpoint,
-        mode,
-        payload,
-    } = Opt::parse();
-    let config = {
-        let mut config: Config = Config::default();
-        config.set_mode(Some(mode)).unwrap();
-        config.scouting.multicast.set_enabled(Some(false)).unwrap();
-        match mode {
-            WhatAmI::Peer => config.listen.endpoints.extend(endpoint),
-            WhatAmI::Client => config.connect.endpoints.extend(endpoint),
-            _ => panic!("Unsupported mode: {}", mode),
-        }
-        config
-    };
+    let Opt { common, payload } = Opt::parse();
+    let config = common.config();
 
     let session = zenoh::open(config).await.unwrap();
     let mut queryable = session.queryable(KEY_EXPR).kind(EVAL).await.unwrap();
-    while let Some(query) = queryable.next().await {
-        query
-            .reply_async(Sample::new(KEY_EXPR, vec![0u8; payload]))
-            .await;
-    }
+    let reply = async {
+        while let Some(query) = queryable.next().await {
+            query
+                .reply_async(Sample::new(KEY_EXPR, vec![0u8; payload]))
+                .await;
+        }
+    };
+
+    // Reply until SIGINT or SIGTERM
+    reply.race(Run::new(None, None, None).wait()).await;
 }
@@ -14,26 +14,27 @@
 use async_std::stream::StreamExt;
 use clap::Parser;
 use std::time::Instant;
-use zenoh::config::Config;
 use zenoh_buffers::SplitBuffer;
-use zenoh_protocol_core::{EndPoint, WhatAmI};
+use zenoh_perf_common::{CommonOpt, Output, OutputFormat, RunOpt};
 
 #[derive(Debug, Parser)]
 #[clap(name = "z_query")]
 struct Opt {
-    /// endpoint(s), e.g. --endpoint tcp/127.0.0.1:7447,tcp/127.0.0.1:7448
-    #[clap(short, long)]
-    endpoint: Vec<EndPoint>,
+    #[clap(flatten)]
+    common: CommonOpt,
 
-    /// peer, router, or client
-    #[clap(short, long)]
-    mode: WhatAmI,
+    #[clap(flatten)]
+    run: RunOpt,
 
     #[clap(short, long)]
     name: String,
 
     #[clap(short, long)]
     scenario: String,
+
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
 
 #[async_std::main]
@@ -43,24 +44,29 @@ async fn main() {
 
     // Parse the args
     let Opt {
-        endpoint,
-        mode,
+        common,
+        run,
         name,
         scenario,
+        output,
     } = Opt::parse();
     let config = {
-        let mut config: Config = Config::default();
-        config.set_mode(Some(mode)).unwrap();
+        let mut config = common.config();
         config.set_add_timestamp(Some(false)).unwrap();
-        config.scouting.multicast.set_enabled(Some(false)).unwrap();
-        config.connect.endpoints.extend(endpoint);
         config
     };
 
+    let output = Output::new(output, "z_query", "session")
+        .label("scenario", scenario)
+        .label("name", name);
+
     let session = zenoh::open(config).await.unwrap();
 
+    let run = run.start();
+    let summary = run.summary();
+    let mut size: usize = 0;
     let mut count: u64 = 0;
-    loop {
+    while run.is_running() {
         let now = Instant::now();
         let mut data_stream = session.get("/test/query").await.unwrap();
 
@@ -69,14 +75,20 @@ async fn main() {
             payload += reply.sample.value.payload.len();
         }
 
-        println!(
-            "zenoh,{},query.latency,{},{},{},{}",
-            scenario,
-            name,
+        let value = now.elapsed().as_micros() as f64;
+        summary.record(
+            &output,
+            "query.latency",
             payload,
-            count,
-            now.elapsed().as_micros()
+            value,
+            "us",
+            &[("seq", count.to_string())],
         );
+        run.tick();
+        size = payload;
         count += 1;
     }
+
+    summary.emit(&output, "query.latency", size, "us");
+    run.emit_totals(&output, size);
 }
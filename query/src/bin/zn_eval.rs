@@ -11,51 +11,44 @@
 // Contributors:
 //   ADLINK zenoh team, <zenoh@adlink-labs.tech>
 //
-use structopt::StructOpt;
-use zenoh::net::queryable::EVAL;
-use zenoh::net::*;
-use zenoh::Properties;
+use async_std::prelude::FutureExt;
+use async_std::stream::StreamExt;
+use clap::Parser;
+use zenoh::{prelude::Sample, queryable::EVAL};
+use zenoh_perf_common::{CommonOpt, Run};
 
-#[derive(Debug, StructOpt)]
-#[structopt(name = "zn_eval")]
+#[derive(Debug, Parser)]
+#[clap(name = "zn_eval")]
 struct Opt {
-    #[structopt(short = "l", long = "locator")]
-    locator: String,
-    #[structopt(short = "m", long = "mode")]
-    mode: String,
-    #[structopt(short = "p", long = "payload")]
+    #[clap(flatten)]
+    common: CommonOpt,
+
+    #[clap(short, long)]
     payload: usize,
 }
 
+const KEY_EXPR: &str = "/test/query";
+
 #[async_std::main]
 async fn main() {
     // initiate logging
     env_logger::init();
 
     // Parse the args
-    let opt = Opt::from_args();
+    let Opt { common, payload } = Opt::parse();
+    let config = common.config();
 
-    let mut config = Properties::default();
-    config.insert("mode".to_string(), opt.mode.clone());
+    let session = zenoh::open(config).await.unwrap();
 
-    config.insert("multicast_scouting".to_string(), "false".to_string());
-    match opt.mode.as_str() {
-        "peer" => config.insert("listener".to_string(), opt.locator),
-        "client" => config.insert("peer".to_string(), opt.locator),
-        _ => panic!("Unsupported mode: {}", opt.mode),
+    let mut queryable = session.queryable(KEY_EXPR).kind(EVAL).await.unwrap();
+    let reply = async {
+        while let Some(query) = queryable.next().await {
+            query
+                .reply_async(Sample::new(KEY_EXPR, vec![0u8; payload]))
+                .await;
+        }
     };
 
-    let session = open(config.into()).await.unwrap();
-
-    // The resource to read the data from
-    let path = "/test/query".to_string();
-    let reskey = ResKey::RName(path.clone());
-    let mut queryable = session.declare_queryable(&reskey, EVAL).await.unwrap();
-    while let Ok(query) = queryable.receiver().recv() {
-        query.reply(Sample {
-            res_name: path.clone(),
-            payload: vec![0u8; opt.payload].into(),
-            data_info: None,
-        });
-    }
+    // Reply until SIGINT or SIGTERM
+    reply.race(Run::new(None, None, None).wait()).await;
 }
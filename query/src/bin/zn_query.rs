@@ -12,23 +12,29 @@
 //   ADLINK zenoh team, <zenoh@adlink-labs.tech>
 //
 use async_std::stream::StreamExt;
+use clap::Parser;
 use std::time::Instant;
-use structopt::StructOpt;
-use zenoh::net::ResKey;
-use zenoh::net::*;
-use zenoh::Properties;
+use zenoh_buffers::SplitBuffer;
+use zenoh_perf_common::{CommonOpt, Output, OutputFormat, RunOpt};
 
-#[derive(Debug, StructOpt)]
-#[structopt(name = "zn_query")]
+#[derive(Debug, Parser)]
+#[clap(name = "zn_query")]
 struct Opt {
-    #[structopt(short = "l", long = "locator")]
-    locator: String,
-    #[structopt(short = "m", long = "mode")]
-    mode: String,
-    #[structopt(short = "n", long = "name")]
+    #[clap(flatten)]
+    common: CommonOpt,
+
+    #[clap(flatten)]
+    run: RunOpt,
+
+    #[clap(short, long)]
     name: String,
-    #[structopt(short = "s", long = "scenario")]
+
+    #[clap(short, long)]
     scenario: String,
+
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
 
 #[async_std::main]
@@ -37,42 +43,52 @@ async fn main() {
     env_logger::init();
 
     // Parse the args
-    let opt = Opt::from_args();
-
-    let mut config = Properties::default();
-    config.insert("mode".to_string(), opt.mode.clone());
+    let Opt {
+        common,
+        run,
+        name,
+        scenario,
+        output,
+    } = Opt::parse();
+    let config = {
+        let mut config = common.config();
+        config.set_add_timestamp(Some(false)).unwrap();
+        config
+    };
 
-    config.insert("multicast_scouting".to_string(), "false".to_string());
-    config.insert("peer".to_string(), opt.locator);
+    let output = Output::new(output, "zn_query", "session")
+        .label("scenario", scenario)
+        .label("name", name);
 
-    let session = open(config.into()).await.unwrap();
+    let session = zenoh::open(config).await.unwrap();
 
+    let run = run.start();
+    let summary = run.summary();
+    let mut size: usize = 0;
     let mut count: u64 = 0;
-    loop {
-        let reskey = ResKey::RName("/test/query".to_string());
-        let predicate = "";
-        let target = QueryTarget::default();
-        let consolidation = QueryConsolidation::default();
-
+    while run.is_running() {
         let now = Instant::now();
-        let mut replies = session
-            .query(&reskey, predicate, target, consolidation)
-            .await
-            .unwrap();
+        let mut data_stream = session.get("/test/query").await.unwrap();
 
         let mut payload: usize = 0;
-        while let Some(reply) = replies.next().await {
-            payload += reply.data.payload.len();
+        while let Some(reply) = data_stream.next().await {
+            payload += reply.sample.value.payload.len();
         }
-        println!(
-            "zenoh-net,{},query.latency,{},{},{},{}",
-            opt.scenario,
-            opt.name,
+
+        let value = now.elapsed().as_micros() as f64;
+        summary.record(
+            &output,
+            "query.latency",
             payload,
-            count,
-            now.elapsed().as_micros()
+            value,
+            "us",
+            &[("seq", count.to_string())],
         );
-
+        run.tick();
+        size = payload;
         count += 1;
     }
+
+    summary.emit(&output, "query.latency", size, "us");
+    run.emit_totals(&output, size);
 }
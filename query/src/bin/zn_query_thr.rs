@@ -14,26 +14,32 @@
 use async_std::stream::StreamExt;
 use async_std::sync::Arc;
 use async_std::task;
+use clap::Parser;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
-use structopt::StructOpt;
-use zenoh::net::ResKey;
-use zenoh::net::*;
-use zenoh::Properties;
+use zenoh_perf_common::{CommonOpt, Output, OutputFormat, RunOpt};
 
-#[derive(Debug, StructOpt)]
-#[structopt(name = "zn_query")]
+#[derive(Debug, Parser)]
+#[clap(name = "zn_query_thr")]
 struct Opt {
-    #[structopt(short = "l", long = "locator")]
-    locator: String,
-    #[structopt(short = "m", long = "mode")]
-    mode: String,
-    #[structopt(short = "n", long = "name")]
+    #[clap(flatten)]
+    common: CommonOpt,
+
+    #[clap(flatten)]
+    run: RunOpt,
+
+    #[clap(short, long)]
     name: String,
-    #[structopt(short = "s", long = "scenario")]
+
+    #[clap(short, long)]
     scenario: String,
-    #[structopt(short = "p", long = "payload")]
+
+    #[clap(short, long)]
     payload: usize,
+
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
 
 #[async_std::main]
@@ -42,23 +48,36 @@ async fn main() {
     env_logger::init();
 
     // Parse the args
-    let opt = Opt::from_args();
-
-    let mut config = Properties::default();
-    config.insert("mode".to_string(), opt.mode.clone());
+    let Opt {
+        common,
+        run,
+        name,
+        scenario,
+        payload,
+        output,
+    } = Opt::parse();
+    let config = common.config();
 
-    config.insert("multicast_scouting".to_string(), "false".to_string());
-    config.insert("peer".to_string(), opt.locator.clone());
+    let session = zenoh::open(config).await.unwrap();
 
-    let session = open(config.into()).await.unwrap();
+    let output = Output::new(output, "zn_query_thr", "session")
+        .label("scenario", scenario)
+        .label("name", name);
+    let run = run.start();
+    let throughput = run.summary();
+    let rtts = run.summary();
 
     let rtt = Arc::new(AtomicUsize::new(0));
     let counter = Arc::new(AtomicUsize::new(0));
 
     let c_rtt = rtt.clone();
     let c_counter = counter.clone();
+    let c_output = output.clone();
+    let c_run = run.clone();
+    let c_throughput = throughput.clone();
+    let c_rtts = rtts.clone();
     task::spawn(async move {
-        loop {
+        while c_run.is_running() {
             let now = Instant::now();
             task::sleep(Duration::from_secs(1)).await;
             let elapsed = now.elapsed().as_micros() as f64;
@@ -66,33 +85,25 @@ async fn main() {
             let r = c_rtt.swap(0, Ordering::Relaxed);
             let c = c_counter.swap(0, Ordering::Relaxed);
             if c > 0 {
-                let interval = 1_000_000.0 / elapsed;
-                println!(
-                    "zenoh-net,{},query.throughput,{},{},{},{}",
-                    opt.scenario,
-                    opt.name,
-                    opt.payload,
-                    (c as f64 / interval).floor() as usize,
-                    (r as f64 / c as f64).floor() as usize
-                );
+                let value = (c as f64 * 1_000_000.0 / elapsed).floor();
+                c_throughput.record(&c_output, "query.throughput", payload, value, "msg/s", &[]);
+                let value = (r as f64 / c as f64).floor();
+                c_rtts.record(&c_output, "query.rtt", payload, value, "us", &[]);
             }
         }
     });
 
-    loop {
-        let reskey = ResKey::RName("/test/query".to_string());
-        let predicate = "";
-        let target = QueryTarget::default();
-        let consolidation = QueryConsolidation::default();
-
+    while run.is_running() {
         let now = Instant::now();
-        let mut replies = session
-            .query(&reskey, predicate, target, consolidation)
-            .await
-            .unwrap();
+        let mut replies = session.get("/test/query").await.unwrap();
         while replies.next().await.is_some() {}
 
         rtt.fetch_add(now.elapsed().as_micros() as usize, Ordering::Relaxed);
         counter.fetch_add(1, Ordering::Relaxed);
+        run.tick();
     }
+
+    throughput.emit(&output, "query.throughput", payload, "msg/s");
+    rtts.emit(&output, "query.rtt", payload, "us");
+    run.emit_totals(&output, payload);
 }
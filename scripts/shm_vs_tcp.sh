@@ -0,0 +1,80 @@
+#!/usr/bin/env bash
+#
+# Compares the shared-memory mode of z_put_thr/z_sub_thr and z_ping/z_pong
+# against the TCP loopback, for the same payload sizes, on this host.
+#
+# Every tool runs twice per payload size, over tcp/127.0.0.1 without then
+# with --shm, and the median of each run is printed side by side as CSV:
+#
+#   test,payload,tcp,shm,unit,shm/tcp
+#
+# The records of every run are kept in LOG_DIR.
+set -e
+
+function print_usage() {
+    echo "Usage: $0 [PAYLOAD_SIZE...]"
+    echo "  DURATION (10), WARMUP (2s), INTERVAL (0) and LOG_DIR may be set in the environment"
+    exit 1
+}
+
+[[ "$1" == "-h" || "$1" == "--help" ]] && print_usage
+
+payload_sizes="${*:-64 1024 16384 65536 1048576}"
+duration="${DURATION:-10}"
+warmup="${WARMUP:-2s}"
+interval="${INTERVAL:-0}"
+endpoint="tcp/127.0.0.1:7447"
+
+root_dir="$( cd -- "$( dirname -- "${BASH_SOURCE[0]}" )/.." &> /dev/null && pwd )"
+log_dir="${LOG_DIR:-${root_dir}/$(date --rfc-3339=seconds | tr ' ' 'T' | tr ':' '-')_shm_vs_tcp}"
+bin_dir="${root_dir}/target/release"
+mkdir -p "$log_dir"
+
+cargo build --release --manifest-path "${root_dir}/Cargo.toml" \
+    --features zenoh-perf-throughput/shared-memory,zenoh-perf-latency/shared-memory \
+    --bin z_put_thr --bin z_sub_thr --bin z_ping --bin z_pong
+
+# The median of the `<metric>.summary` records of a run in the given unit
+function median() {
+    awk -F, -v metric="$2.summary" -v unit="$3" \
+        '$3 == metric && $6 == unit && $8 ~ /(^|;)stat=p50(;|$)/ { print $5 }' "$1"
+}
+
+# Both modes share the same endpoint, the shared memory being negotiated over it
+function run_throughput() {
+    local payload_size="$1" mode="$2" shm="$3"
+    local log="${log_dir}/throughput_${payload_size}_${mode}.csv"
+    "$bin_dir/z_sub_thr" -m peer -l "$endpoint" -n shm_vs_tcp -s "$mode" \
+        --duration "$duration" --warmup "$warmup" $shm > "$log" &
+    local sub=$!
+    sleep 1
+    "$bin_dir/z_put_thr" -m peer -c "$endpoint" -p "$payload_size" \
+        --duration "$((duration + 1))" $shm > /dev/null
+    wait "$sub"
+    median "$log" throughput msg/s
+}
+
+function run_latency() {
+    local payload_size="$1" mode="$2" shm="$3"
+    local log="${log_dir}/latency_${payload_size}_${mode}.csv"
+    "$bin_dir/z_pong" -m peer -l "$endpoint" $shm &
+    local pong=$!
+    sleep 1
+    "$bin_dir/z_ping" -m peer -c "$endpoint" -p "$payload_size" -n shm_vs_tcp -s "$mode" \
+        -i "$interval" --duration "$duration" --warmup "$warmup" $shm > "$log"
+    kill "$pong"
+    wait "$pong" || true
+    median "$log" latency.sequential us
+}
+
+echo "test,payload,tcp,shm,unit,shm/tcp"
+for payload_size in $payload_sizes
+do
+    tcp=$(run_throughput "$payload_size" tcp "")
+    shm=$(run_throughput "$payload_size" shm --shm)
+    echo "throughput,$payload_size,$tcp,$shm,msg/s,$(awk -v a="$shm" -v b="$tcp" 'BEGIN { if (b > 0) printf "%.2f", a / b }')"
+
+    tcp=$(run_latency "$payload_size" tcp "")
+    shm=$(run_latency "$payload_size" shm --shm)
+    echo "latency,$payload_size,$tcp,$shm,us,$(awk -v a="$shm" -v b="$tcp" 'BEGIN { if (b > 0) printf "%.2f", a / b }')"
+done
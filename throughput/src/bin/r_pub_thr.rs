@@ -13,45 +13,49 @@
 //
 use async_std::{sync::Arc, task};
 use clap::Parser;
-use std::{
-    path::PathBuf,
-    sync::atomic::{AtomicUsize, Ordering},
-    time::Duration,
+use std::time::Duration;
+use zenoh::net::{
+    protocol::io::ZBuf,
+    runtime::Runtime,
+    transport::{DummyPrimitives, Primitives},
 };
-use zenoh::{
-    config::Config,
-    net::{
-        protocol::io::ZBuf,
-        runtime::Runtime,
-        transport::{DummyPrimitives, Primitives},
-    },
-};
-use zenoh_protocol_core::{
-    Channel, CongestionControl, EndPoint, KeyExpr, Priority, Reliability, WhatAmI,
+use zenoh_perf_common::{
+    CommonOpt, CongestionOpt, LinkOpt, Meter, Output, OutputFormat, RateOpt, ReliabilityOpt, Run,
+    RunOpt, SweepOpt,
 };
+use zenoh_protocol_core::{Channel, KeyExpr, Priority};
 
 #[derive(Debug, Parser)]
 #[clap(name = "r_pub_thr")]
 struct Opt {
-    /// endpoint(s), e.g. --endpoint tcp/127.0.0.1:7447,tcp/127.0.0.1:7448
-    #[clap(short, long, required(true), value_delimiter = ',')]
-    endpoint: Vec<EndPoint>,
+    #[clap(flatten)]
+    common: CommonOpt,
+
+    #[clap(flatten)]
+    run: RunOpt,
+
+    #[clap(flatten)]
+    rate: RateOpt,
+
+    #[clap(flatten)]
+    reliability: ReliabilityOpt,
+
+    #[clap(flatten)]
+    congestion: CongestionOpt,
 
-    /// peer, router, or client
-    #[clap(short, long)]
-    mode: WhatAmI,
+    #[clap(flatten)]
+    link: LinkOpt,
 
-    /// payload size (bytes)
-    #[clap(short, long)]
-    payload: usize,
+    #[clap(flatten)]
+    sweep: SweepOpt,
 
     /// print the counter
     #[clap(short = 't', long)]
     print: bool,
 
-    /// configuration file (json5 or yaml)
-    #[clap(long = "conf", parse(from_os_str))]
-    config: Option<PathBuf>,
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
 
 #[async_std::main]
@@ -61,23 +65,20 @@ async fn main() {
 
     // Parse the args
     let Opt {
-        endpoint,
-        mode,
-        payload,
+        common,
+        run,
+        rate,
+        reliability,
+        congestion,
+        link,
+        sweep,
         print,
-        config,
+        output,
     } = Opt::parse();
 
     let config = {
-        let mut config: Config = if let Some(path) = config {
-            Config::from_file(path).unwrap()
-        } else {
-            Config::default()
-        };
-        config.set_mode(Some(mode)).unwrap();
+        let mut config = link.apply(common.config());
         config.set_add_timestamp(Some(false)).unwrap();
-        config.scouting.multicast.set_enabled(Some(false)).unwrap();
-        config.connect.endpoints.extend(endpoint);
         config
     };
 
@@ -96,24 +97,38 @@ async fn main() {
 
     let channel = Channel {
         priority: Priority::Data,
-        reliability: Reliability::Reliable,
+        reliability: reliability.reliability,
     };
-    let congestion_control = CongestionControl::Block;
-    let payload = ZBuf::from(vec![0u8; payload]);
+    let congestion_control = congestion.congestion;
+    let run = run.start();
+    let output = rate.label(Output::new(output, "r_pub_thr", "router"));
+    let output = link.label(output);
+    let output = congestion.label(reliability.label(output));
+    let throughput = run.throughput();
+    let meter = Arc::new(Meter::new());
     if print {
-        let count = Arc::new(AtomicUsize::new(0));
-        let c_count = count.clone();
+        let c_meter = meter.clone();
+        let c_output = output.clone();
+        let c_throughput = throughput.clone();
         task::spawn(async move {
             loop {
                 task::sleep(Duration::from_secs(1)).await;
-                let c = count.swap(0, Ordering::Relaxed);
-                if c > 0 {
-                    println!("{} msg/s", c);
+                if let Some((payload, c)) = c_meter.take() {
+                    if c > 0 {
+                        c_throughput.record(&c_output, "throughput", payload, c as f64, &[]);
+                    }
                 }
             }
         });
+    }
+
+    for &payload_size in sweep.sizes() {
+        let payload = ZBuf::from(vec![0u8; payload_size]);
 
-        loop {
+        let phase = Run::new(sweep.phase(), None, None);
+        let mut pacer = rate.pacer();
+        while run.is_running() && phase.is_running() {
+            pacer.wait().await;
             primitives.send_data(
                 &rid,
                 payload.clone(),
@@ -122,18 +137,16 @@ async fn main() {
                 None,
                 None,
             );
-            c_count.fetch_add(1, Ordering::Relaxed);
+            meter.add(payload_size);
+            run.tick();
         }
-    } else {
-        loop {
-            primitives.send_data(
-                &rid,
-                payload.clone(),
-                channel,
-                congestion_control,
-                None,
-                None,
-            );
+        if !run.is_running() {
+            break;
         }
     }
+
+    if print {
+        throughput.emit(&output, "throughput");
+    }
+    run.emit_totals(&output, sweep.payload());
 }
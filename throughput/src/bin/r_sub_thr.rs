@@ -13,52 +13,47 @@
 //
 use async_std::{sync::Arc, task};
 use clap::Parser;
-use std::{
-    path::PathBuf,
-    sync::atomic::{AtomicUsize, Ordering},
-    time::{Duration, Instant},
-};
-use zenoh::{
-    config::Config,
-    net::{
-        protocol::{
-            io::ZBuf,
-            proto::{DataInfo, RoutingContext},
-        },
-        runtime::Runtime,
-        transport::Primitives,
+use std::time::{Duration, Instant};
+use zenoh::net::{
+    protocol::{
+        io::{SplitBuffer, ZBuf},
+        proto::{DataInfo, RoutingContext},
     },
+    runtime::Runtime,
+    transport::Primitives,
 };
+use zenoh_perf_common::{CommonOpt, Meter, Output, OutputFormat, ReliabilityOpt, Run, RunOpt};
 use zenoh_protocol_core::{
-    Channel, CongestionControl, ConsolidationStrategy, EndPoint, KeyExpr, PeerId, QueryTarget,
-    QueryableInfo, Reliability, SubInfo, SubMode, WhatAmI, ZInt,
+    Channel, CongestionControl, ConsolidationStrategy, KeyExpr, PeerId, QueryTarget, QueryableInfo,
+    SubInfo, SubMode, ZInt,
 };
 
 struct ThroughputPrimitives {
-    count: Arc<AtomicUsize>,
+    meter: Arc<Meter>,
+    run: Arc<Run>,
 }
 
 impl ThroughputPrimitives {
-    pub fn new(count: Arc<AtomicUsize>) -> ThroughputPrimitives {
-        ThroughputPrimitives { count }
+    pub fn new(meter: Arc<Meter>, run: Arc<Run>) -> ThroughputPrimitives {
+        ThroughputPrimitives { meter, run }
     }
 }
 
 impl Primitives for ThroughputPrimitives {
     fn decl_resource(&self, _expr_id: ZInt, _key_expr: &KeyExpr) {
-        self.count.fetch_add(1, Ordering::Relaxed);
+        self.meter.add_unsized();
     }
 
     fn forget_resource(&self, _expr_id: ZInt) {
-        self.count.fetch_add(1, Ordering::Relaxed);
+        self.meter.add_unsized();
     }
 
     fn decl_publisher(&self, _key_expr: &KeyExpr, _routing_context: Option<RoutingContext>) {
-        self.count.fetch_add(1, Ordering::Relaxed);
+        self.meter.add_unsized();
     }
 
     fn forget_publisher(&self, _key_expr: &KeyExpr, _routing_context: Option<RoutingContext>) {
-        self.count.fetch_add(1, Ordering::Relaxed);
+        self.meter.add_unsized();
     }
 
     fn decl_subscriber(
@@ -67,11 +62,11 @@ impl Primitives for ThroughputPrimitives {
         _sub_info: &SubInfo,
         _routing_context: Option<RoutingContext>,
     ) {
-        self.count.fetch_add(1, Ordering::Relaxed);
+        self.meter.add_unsized();
     }
 
     fn forget_subscriber(&self, _key_expr: &KeyExpr, _routing_context: Option<RoutingContext>) {
-        self.count.fetch_add(1, Ordering::Relaxed);
+        self.meter.add_unsized();
     }
 
     fn decl_queryable(
@@ -81,7 +76,7 @@ impl Primitives for ThroughputPrimitives {
         _qable_info: &QueryableInfo,
         _routing_context: Option<RoutingContext>,
     ) {
-        self.count.fetch_add(1, Ordering::Relaxed);
+        self.meter.add_unsized();
     }
 
     fn forget_queryable(
@@ -90,19 +85,20 @@ impl Primitives for ThroughputPrimitives {
         _kind: ZInt,
         _routing_context: Option<RoutingContext>,
     ) {
-        self.count.fetch_add(1, Ordering::Relaxed);
+        self.meter.add_unsized();
     }
 
     fn send_data(
         &self,
         _key_expr: &KeyExpr,
-        _payload: ZBuf,
+        payload: ZBuf,
         _channel: Channel,
         _congestion_control: CongestionControl,
         _data_info: Option<DataInfo>,
         _routing_context: Option<RoutingContext>,
     ) {
-        self.count.fetch_add(1, Ordering::Relaxed);
+        self.meter.add(payload.len());
+        self.run.tick();
     }
 
     fn send_query(
@@ -114,7 +110,7 @@ impl Primitives for ThroughputPrimitives {
         _consolidation: ConsolidationStrategy,
         _routing_context: Option<RoutingContext>,
     ) {
-        self.count.fetch_add(1, Ordering::Relaxed);
+        self.meter.add_unsized();
     }
 
     fn send_reply_data(
@@ -126,11 +122,11 @@ impl Primitives for ThroughputPrimitives {
         _info: Option<DataInfo>,
         _payload: ZBuf,
     ) {
-        self.count.fetch_add(1, Ordering::Relaxed);
+        self.meter.add_unsized();
     }
 
     fn send_reply_final(&self, _qid: ZInt) {
-        self.count.fetch_add(1, Ordering::Relaxed);
+        self.meter.add_unsized();
     }
 
     fn send_pull(
@@ -140,28 +136,25 @@ impl Primitives for ThroughputPrimitives {
         _pull_id: ZInt,
         _max_samples: &Option<ZInt>,
     ) {
-        self.count.fetch_add(1, Ordering::Relaxed);
+        self.meter.add_unsized();
     }
 
     fn send_close(&self) {
-        self.count.fetch_add(1, Ordering::Relaxed);
+        self.meter.add_unsized();
     }
 }
 
 #[derive(Debug, Parser)]
 #[clap(name = "r_sub_thr")]
 struct Opt {
-    /// endpoint(s), e.g. --endpoint tcp/127.0.0.1:7447,tcp/127.0.0.1:7448
-    #[clap(short, long, required(true), value_delimiter = ',')]
-    endpoint: Vec<EndPoint>,
+    #[clap(flatten)]
+    common: CommonOpt,
 
-    /// peer, router, or client
-    #[clap(short, long)]
-    mode: WhatAmI,
+    #[clap(flatten)]
+    run: RunOpt,
 
-    /// payload size (bytes)
-    #[clap(short, long)]
-    payload: usize,
+    #[clap(flatten)]
+    reliability: ReliabilityOpt,
 
     #[clap(short, long)]
     name: String,
@@ -169,9 +162,9 @@ struct Opt {
     #[clap(short, long)]
     scenario: String,
 
-    /// configuration file (json5 or yaml)
-    #[clap(long = "conf", parse(from_os_str))]
-    config: Option<PathBuf>,
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
 
 #[async_std::main]
@@ -181,31 +174,23 @@ async fn main() {
 
     // Parse the args
     let Opt {
-        endpoint,
-        mode,
-        payload,
+        common,
+        run,
+        reliability,
         name,
         scenario,
-        config,
+        output,
     } = Opt::parse();
+    let config = common.config();
+    let output = Output::new(output, "r_sub_thr", "router")
+        .label("scenario", scenario)
+        .label("name", name);
+    let output = reliability.label(output);
 
-    let config = {
-        let mut config: Config = if let Some(path) = config {
-            Config::from_file(path).unwrap()
-        } else {
-            Config::default()
-        };
-        config.set_mode(Some(mode)).unwrap();
-        config.scouting.multicast.set_enabled(Some(false)).unwrap();
-        match mode {
-            WhatAmI::Peer | WhatAmI::Router => config.listen.endpoints.extend(endpoint),
-            WhatAmI::Client => config.connect.endpoints.extend(endpoint),
-        };
-        config
-    };
-
-    let count = Arc::new(AtomicUsize::new(0));
-    let my_primitives = Arc::new(ThroughputPrimitives::new(count.clone()));
+    let run = run.start();
+    let throughput = run.throughput();
+    let meter = Arc::new(Meter::new());
+    let my_primitives = Arc::new(ThroughputPrimitives::new(meter.clone(), run.clone()));
 
     let runtime = Runtime::new(config).await.unwrap();
     let primitives = runtime.router.new_primitives(my_primitives);
@@ -214,27 +199,27 @@ async fn main() {
 
     let rid = KeyExpr::from(1);
     let sub_info = SubInfo {
-        reliability: Reliability::Reliable,
+        reliability: reliability.reliability,
         mode: SubMode::Push,
         period: None,
     };
     primitives.decl_subscriber(&rid, &sub_info, None);
 
-    loop {
+    while run.is_running() {
         let now = Instant::now();
         task::sleep(Duration::from_secs(1)).await;
         let elapsed = now.elapsed().as_micros() as f64;
 
-        let c = count.swap(0, Ordering::Relaxed);
-        if c > 0 {
-            let interval = 1_000_000.0 / elapsed;
-            println!(
-                "router,{},throughput,{},{},{}",
-                scenario,
-                name,
-                payload,
-                (c as f64 / interval).floor() as usize
-            );
+        // Skip the intervals mixing two payload sizes of a sweep
+        if let Some((payload, c)) = meter.take() {
+            if c > 0 {
+                let interval = 1_000_000.0 / elapsed;
+                let value = (c as f64 / interval).floor();
+                throughput.record(&output, "throughput", payload, value, &[]);
+            }
         }
     }
+
+    throughput.emit(&output, "throughput");
+    run.emit_totals(&output, throughput.payload());
 }
@@ -12,17 +12,11 @@
 //   ADLINK zenoh team, <zenoh@adlink-labs.tech>
 //
 use async_std::{sync::Arc, task};
-use clap::Parser;
-use std::{
-    path::PathBuf,
-    sync::atomic::{AtomicUsize, Ordering},
-    time::Duration,
-};
+use clap::{error::ErrorKind, CommandFactory, FromArgMatches, Parser};
+use std::time::{Duration, Instant};
 use zenoh::net::{
-    link::EndPoint,
     protocol::{
-        core::{Channel, CongestionControl, Priority, Reliability},
-        io::ZBuf,
+        core::{Channel, Priority},
         proto::ZenohMessage,
     },
     transport::{
@@ -31,11 +25,13 @@ use zenoh::net::{
         TransportPeerEventHandler, TransportUnicast,
     },
 };
-use zenoh::{
-    config::{Config, WhatAmI},
-    prelude::KeyExpr,
-};
+use zenoh::prelude::KeyExpr;
 use zenoh_core::zresult::ZResult;
+use zenoh_perf_common::{
+    parse_publishers, priority_name, CommonOpt, CongestionOpt, Flow, LinkOpt, Meter, Meters,
+    MulticastHandler, MulticastOpt, Output, OutputFormat, Payload, RateOpt, ReliabilityOpt, Run,
+    RunOpt, Stalls, SweepOpt, Transport,
+};
 
 struct MySH {}
 
@@ -58,32 +54,165 @@ impl TransportEventHandler for MySH {
         &self,
         _transport: TransportMulticast,
     ) -> ZResult<Arc<dyn TransportMulticastEventHandler>> {
-        panic!();
+        Ok(Arc::new(MulticastHandler::new(|_peer| {
+            DummyTransportPeerEventHandler::default()
+        })))
     }
 }
 
 #[derive(Debug, Parser)]
 #[clap(name = "t_pub_thr")]
 struct Opt {
-    /// endpoint(s), e.g. --endpoint tcp/127.0.0.1:7447,tcp/127.0.0.1:7448
-    #[clap(short, long, required(true), value_delimiter = ',')]
-    endpoint: Vec<EndPoint>,
+    #[clap(flatten)]
+    common: CommonOpt,
+
+    #[clap(flatten)]
+    multicast: MulticastOpt,
+
+    #[clap(flatten)]
+    run: RunOpt,
+
+    #[clap(flatten)]
+    rate: RateOpt,
+
+    #[clap(flatten)]
+    reliability: ReliabilityOpt,
 
-    /// peer, router, or client
-    #[clap(short, long)]
-    mode: WhatAmI,
+    #[clap(flatten)]
+    congestion: CongestionOpt,
 
-    /// payload size (bytes)
-    #[clap(short, long)]
-    payload: usize,
+    #[clap(flatten)]
+    link: LinkOpt,
+
+    #[clap(flatten)]
+    sweep: SweepOpt,
 
     /// print the counter
     #[clap(short = 't', long)]
     print: bool,
 
-    /// configuration file (json5 or yaml)
-    #[clap(long = "conf", parse(from_os_str))]
-    config: Option<PathBuf>,
+    /// number of concurrent publishers, at most 256
+    #[clap(long, default_value_t = 1, value_parser = parse_publishers)]
+    publishers: usize,
+
+    /// open transports per publisher instead of sharing the same ones
+    #[clap(long)]
+    separate: bool,
+
+    /// send a flow on a priority over the same transports, repeat it for concurrent flows:
+    /// PRIORITY[:RATE[:PAYLOAD]], e.g. real-time:1000:64 or background
+    #[clap(long)]
+    flow: Vec<Flow>,
+
+    /// checksum the content of the payloads, for the subscribers to verify it
+    /// with --verify, e.g. once reassembled from its fragments
+    #[clap(long)]
+    checksum: bool,
+
+    /// time the calls publishing the messages and report the share of the time
+    /// the publishers are blocked, e.g. by the congestion control
+    #[clap(long)]
+    stalls: bool,
+
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
+}
+
+// One of the publishers of the process
+struct Publisher {
+    producer_id: u32,
+    transports: Vec<Transport>,
+    meter: Arc<Meter>,
+    flow: Option<Flow>,
+    stalls: Option<Arc<Stalls>>,
+}
+
+impl Publisher {
+    async fn publish(self, opt: Arc<Opt>, run: Arc<Run>) {
+        let channel = Channel {
+            priority: self.flow.map_or(Priority::Data, |flow| flow.priority),
+            reliability: opt.reliability.reliability,
+        };
+        let congestion_control = opt.congestion.congestion;
+        let key = KeyExpr::from(1);
+        let info = None;
+        let reply_context = None;
+        let routing_context = None;
+        let attachment = None;
+
+        // The sequence numbers go on across the payload sizes of a sweep
+        let mut sequence = 0;
+        // A flow with its own payload size keeps it for the whole run
+        let (sizes, phase) = match self.flow.and_then(|flow| flow.payload) {
+            Some(payload) => (vec![payload], None),
+            None => (opt.sweep.sizes().to_vec(), opt.sweep.phase()),
+        };
+        let rate = RateOpt {
+            rate: self.flow.and_then(|flow| flow.rate).or(opt.rate.rate),
+            ..opt.rate.clone()
+        };
+        for payload_size in sizes {
+            // The producer id lets the subscribers tell the publishers apart
+            let data = Payload::new(self.producer_id, payload_size, opt.checksum);
+
+            let phase = Run::new(phase, None, None);
+            let mut pacer = rate.pacer();
+            while run.is_running() && phase.is_running() {
+                pacer.wait().await;
+                let payload = data.zbuf(sequence);
+                sequence += 1;
+                for t in self.transports.iter() {
+                    let message = ZenohMessage::make_data(
+                        key.clone(),
+                        payload.clone(),
+                        channel,
+                        congestion_control,
+                        info.clone(),
+                        routing_context,
+                        reply_context.clone(),
+                        attachment.clone(),
+                    );
+                    let start = Instant::now();
+                    t.handle_message(message).unwrap();
+                    if let Some(stalls) = &self.stalls {
+                        stalls.add(payload_size, start.elapsed());
+                    }
+                }
+                self.meter.add(payload_size);
+                run.tick();
+            }
+            if !run.is_running() {
+                break;
+            }
+        }
+    }
+}
+
+// Setup a TransportManager, connect to the subscribers and join the multicast groups
+async fn connect(opt: &Opt, listen: bool) -> (TransportManager, Vec<Transport>) {
+    let builder = TransportManager::builder()
+        .from_config(&opt.link.apply(opt.multicast.config(&opt.common)))
+        .await
+        .unwrap();
+    let handler = Arc::new(MySH::new());
+    let manager = builder.build(handler).unwrap();
+
+    if listen {
+        for e in opt.common.listen_endpoints() {
+            manager.add_listener(e).await.unwrap();
+        }
+    }
+
+    let mut transports: Vec<Transport> = vec![];
+    for e in opt.common.connect_endpoints() {
+        let t = manager.open_transport_unicast(e).await.unwrap();
+        transports.push(t.into());
+    }
+    for t in opt.multicast.join(&manager).await {
+        transports.push(t.into());
+    }
+    (manager, transports)
 }
 
 #[async_std::main]
@@ -91,74 +220,102 @@ async fn main() {
     // Enable logging
     env_logger::init();
 
-    // Parse the args
-    let Opt {
-        endpoint,
-        mode,
-        payload,
-        print,
-        config,
-    } = Opt::parse();
-
-    // Setup TransportManager
-    let builder = match config {
-        Some(path) => TransportManager::builder()
-            .from_config(&Config::from_file(path).unwrap())
-            .await
-            .unwrap(),
-        None => TransportManager::builder().whatami(mode),
-    };
-    let handler = Arc::new(MySH::new());
-    let manager = builder.build(handler).unwrap();
+    // Parse the args, --payload being optional when every flow sets its own
+    let matches = Opt::command()
+        .mut_arg("payload", |arg| arg.required(false))
+        .get_matches();
+    let opt = Opt::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if opt.sweep.payload.is_none()
+        && (opt.flow.is_empty() || opt.flow.iter().any(|flow| flow.payload.is_none()))
+    {
+        Opt::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "--payload is required unless every --flow sets its payload size",
+            )
+            .exit();
+    }
+    for rate in opt.flow.iter().filter_map(|flow| flow.rate) {
+        if let Err(e) = opt.rate.check(rate) {
+            Opt::command().error(ErrorKind::ValueValidation, e).exit();
+        }
+    }
+    let opt = Arc::new(opt);
 
-    // Connect to publisher
-    let mut transports: Vec<TransportUnicast> = vec![];
-    for e in endpoint {
-        let t = manager.open_transport_unicast(e.clone()).await.unwrap();
-        transports.push(t);
+    // Only the first TransportManager listens, the others use their own transports
+    let mut managers = vec![connect(&opt, true).await];
+    if opt.separate {
+        for _ in 1..opt.publishers {
+            managers.push(connect(&opt, false).await);
+        }
     }
 
-    // Send reliable messages
-    let channel = Channel {
-        priority: Priority::Data,
-        reliability: Reliability::Reliable,
+    let run = opt.run.start();
+    let output = Output::new(opt.output, "t_pub_thr", "transport");
+    let output = opt.multicast.label(opt.rate.label(output));
+    let output = opt.congestion.label(opt.reliability.label(output));
+    let output = opt.link.label(output);
+    let publishers = if opt.flow.is_empty() {
+        Meters::publishers(&run, opt.publishers)
+    } else {
+        assert!(
+            opt.publishers == 1 && !opt.separate,
+            "--flow sends all the flows over the same transports, without --publishers nor --separate"
+        );
+        let names = opt.flow.iter().map(|flow| priority_name(flow.priority));
+        Meters::flows(&run, names.map(str::to_string).collect())
     };
-    let congestion_control = CongestionControl::Block;
-    let key = KeyExpr::from(1);
-    let info = None;
-    let payload = ZBuf::from(vec![0u8; payload]);
-    let reply_context = None;
-    let routing_context = None;
-    let attachment = None;
-
-    let count = Arc::new(AtomicUsize::new(0));
-    if print {
-        let c_count = count.clone();
+    let publishers = Arc::new(publishers);
+    if opt.print {
+        let c_publishers = publishers.clone();
+        let c_output = output.clone();
         task::spawn(async move {
             loop {
+                let now = Instant::now();
                 task::sleep(Duration::from_secs(1)).await;
-                let c = c_count.swap(0, Ordering::Relaxed);
-                if c > 0 {
-                    println!("{} msg/s", c);
-                }
+                let elapsed = now.elapsed().as_micros() as f64;
+                c_publishers.record(&c_output, elapsed);
             }
         });
     }
 
-    loop {
-        for t in transports.iter() {
-            let message = ZenohMessage::make_data(
-                key.clone(),
-                payload.clone(),
-                channel,
-                congestion_control,
-                info.clone(),
-                routing_context,
-                reply_context.clone(),
-                attachment.clone(),
-            );
-            t.handle_message(message).unwrap();
-        }
-        count.fetch_add(1, Ordering::Relaxed);
+    let count = opt.publishers.max(opt.flow.len());
+    let stalls = opt.stalls.then(|| Arc::new(Stalls::new(&run, count)));
+    if let Some(stalls) = &stalls {
+        let c_stalls = stalls.clone();
+        let c_output = output.clone();
+        task::spawn(async move {
+            loop {
+                let now = Instant::now();
+                task::sleep(Duration::from_secs(1)).await;
+                let elapsed = now.elapsed().as_micros() as f64;
+                c_stalls.record(&c_output, elapsed);
+            }
+        });
+    }
+
+    let mut tasks = vec![];
+    for i in 0..count {
+        let (_, transports) = &managers[if opt.separate { i } else { 0 }];
+        let (producer_id, meter) = publishers.get(i);
+        let publisher = Publisher {
+            producer_id,
+            transports: transports.clone(),
+            meter,
+            flow: opt.flow.get(i).copied(),
+            stalls: stalls.clone(),
+        };
+        tasks.push(task::spawn(publisher.publish(opt.clone(), run.clone())));
+    }
+    for t in tasks {
+        t.await;
+    }
+
+    if opt.print {
+        publishers.emit(&output);
+    }
+    if let Some(stalls) = &stalls {
+        stalls.emit(&output);
     }
+    run.emit_totals(&output, opt.sweep.payload());
 }
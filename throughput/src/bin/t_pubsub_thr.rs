@@ -15,15 +15,13 @@ use async_std::{sync::Arc, task};
 use clap::Parser;
 use std::{
     any::Any,
-    path::PathBuf,
     sync::atomic::{AtomicBool, AtomicUsize, Ordering},
     time::Duration,
 };
 use zenoh::{
-    config::{Config, WhatAmI},
     net::{
-        link::{EndPoint, Link},
-        protocol::core::{Channel, CongestionControl, Priority, Reliability},
+        link::Link,
+        protocol::core::{Channel, Priority},
         protocol::{io::ZBuf, proto::ZenohMessage},
         transport::{
             TransportEventHandler, TransportManager, TransportMulticast,
@@ -34,47 +32,71 @@ use zenoh::{
     prelude::KeyExpr,
 };
 use zenoh_core::zresult::ZResult;
+use zenoh_perf_common::{
+    CommonOpt, CongestionOpt, LinkOpt, MulticastHandler, MulticastOpt, Output, OutputFormat,
+    ReliabilityOpt, Run, RunOpt, Throughput, Transport,
+};
 
 // Transport Handler for the peer
 struct MySH {
-    scenario: String,
-    name: String,
+    output: Output,
     payload: usize,
     counter: Arc<AtomicUsize>,
+    run: Arc<Run>,
+    throughput: Arc<Throughput>,
     active: AtomicBool,
 }
 
 impl MySH {
-    fn new(scenario: String, name: String, payload: usize, counter: Arc<AtomicUsize>) -> Self {
+    fn new(
+        output: Output,
+        payload: usize,
+        counter: Arc<AtomicUsize>,
+        run: Arc<Run>,
+        throughput: Arc<Throughput>,
+    ) -> Self {
         Self {
-            scenario,
-            name,
+            output,
             payload,
             counter,
+            run,
+            throughput,
             active: AtomicBool::new(false),
         }
     }
-}
 
-impl TransportEventHandler for MySH {
-    fn new_unicast(
-        &self,
-        _peer: TransportPeer,
-        _transport: TransportUnicast,
-    ) -> ZResult<Arc<dyn TransportPeerEventHandler>> {
+    // Reports the received throughput once the first transport is open
+    fn activate(&self) {
         if !self.active.swap(true, Ordering::Acquire) {
             let count = self.counter.clone();
-            let scenario = self.scenario.clone();
-            let name = self.name.clone();
+            let output = self.output.clone();
             let payload = self.payload;
+            let run = self.run.clone();
+            let throughput = self.throughput.clone();
             task::spawn(async move {
-                loop {
+                while run.is_running() {
                     task::sleep(Duration::from_secs(1)).await;
                     let c = count.swap(0, Ordering::Relaxed);
-                    println!("session,{},throughput,{},{},{}", scenario, name, payload, c);
+                    throughput.record(
+                        &output,
+                        "throughput",
+                        payload,
+                        c as f64,
+                        &[("direction", "rx".to_string())],
+                    );
                 }
             });
         }
+    }
+}
+
+impl TransportEventHandler for MySH {
+    fn new_unicast(
+        &self,
+        _peer: TransportPeer,
+        _transport: TransportUnicast,
+    ) -> ZResult<Arc<dyn TransportPeerEventHandler>> {
+        self.activate();
         Ok(Arc::new(MyMH::new(self.counter.clone())))
     }
 
@@ -82,7 +104,11 @@ impl TransportEventHandler for MySH {
         &self,
         _transport: TransportMulticast,
     ) -> ZResult<Arc<dyn TransportMulticastEventHandler>> {
-        panic!();
+        self.activate();
+        let counter = self.counter.clone();
+        Ok(Arc::new(MulticastHandler::new(move |_peer| {
+            MyMH::new(counter.clone())
+        })))
     }
 }
 
@@ -115,17 +141,23 @@ impl TransportPeerEventHandler for MyMH {
 #[derive(Debug, Parser)]
 #[clap(name = "t_pubsub_thr")]
 struct Opt {
-    /// which endpoints to listen on. e.g. --listen tcp/127.0.0.1:7447,tcp/127.0.0.1:7448
-    #[clap(short, long, value_delimiter = ',')]
-    listen: Vec<EndPoint>,
+    #[clap(flatten)]
+    common: CommonOpt,
 
-    /// which endpoints to connect to., e.g. --connect tcp/127.0.0.1:7447,tcp/127.0.0.1:7448
-    #[clap(short, long, value_delimiter = ',')]
-    connect: Vec<EndPoint>,
+    #[clap(flatten)]
+    multicast: MulticastOpt,
 
-    /// peer, router, or client
-    #[clap(short, long)]
-    mode: WhatAmI,
+    #[clap(flatten)]
+    run: RunOpt,
+
+    #[clap(flatten)]
+    reliability: ReliabilityOpt,
+
+    #[clap(flatten)]
+    congestion: CongestionOpt,
+
+    #[clap(flatten)]
+    link: LinkOpt,
 
     /// payload size (bytes)
     #[clap(short, long)]
@@ -141,9 +173,9 @@ struct Opt {
     #[clap(short = 't', long)]
     print: bool,
 
-    /// configuration file (json5 or yaml)
-    #[clap(long = "conf", parse(from_os_str))]
-    config: Option<PathBuf>,
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
 
 #[async_std::main]
@@ -153,55 +185,68 @@ async fn main() {
 
     // Parse the args
     let Opt {
-        listen,
-        connect,
-        mode,
+        common,
+        multicast,
+        run,
+        reliability,
+        congestion,
+        link,
         payload,
         name,
         scenario,
         print,
-        config,
+        output,
     } = Opt::parse();
 
+    let run = run.start();
+    let rx_throughput = run.throughput();
+    let tx_throughput = run.throughput();
     let count = Arc::new(AtomicUsize::new(0));
-    let builder = match config {
-        Some(path) => TransportManager::builder()
-            .from_config(&Config::from_file(path).unwrap())
-            .await
-            .unwrap(),
-        None => TransportManager::builder().whatami(mode),
-    };
-    let handler = Arc::new(MySH::new(scenario, name, payload, count));
+    let builder = TransportManager::builder()
+        .from_config(&link.apply(multicast.config(&common)))
+        .await
+        .unwrap();
+    let output = Output::new(output, "t_pubsub_thr", "transport")
+        .label("scenario", scenario)
+        .label("name", name);
+    let output = congestion.label(reliability.label(output));
+    let output = link.label(multicast.label(output));
+    let handler = Arc::new(MySH::new(
+        output.clone(),
+        payload,
+        count,
+        run.clone(),
+        rx_throughput.clone(),
+    ));
     let manager = builder.build(handler).unwrap();
 
-    if listen.is_empty() && connect.is_empty() {
-        panic!("Either --listen or --connect needs to be specified, see --help for more details");
-    }
-
     // Connect to publisher
-    for e in listen {
-        let _ = manager.add_listener(e.clone()).await.unwrap();
+    for e in common.listen_endpoints() {
+        let _ = manager.add_listener(e).await.unwrap();
     }
 
-    let mut transports: Vec<TransportUnicast> = vec![];
-    for e in connect {
+    let mut transports: Vec<Transport> = vec![];
+    for e in common.connect_endpoints() {
         let t = loop {
             match manager.open_transport_unicast(e.clone()).await {
                 Ok(t) => break t,
                 Err(_) => task::sleep(Duration::from_secs(1)).await,
             }
         };
-        transports.push(t);
+        transports.push(t.into());
+    }
+    for t in multicast.join(&manager).await {
+        transports.push(t.into());
     }
 
-    // Send reliable messages
     let channel = Channel {
         priority: Priority::Data,
-        reliability: Reliability::Reliable,
+        reliability: reliability.reliability,
     };
-    let congestion_control = CongestionControl::Block;
+    let congestion_control = congestion.congestion;
     let key = KeyExpr::from("test");
     let info = None;
+    let payload_size = payload;
     let payload = ZBuf::from(vec![0u8; payload]);
     let reply_context = None;
     let routing_context = None;
@@ -210,18 +255,26 @@ async fn main() {
     let count = Arc::new(AtomicUsize::new(0));
     if print {
         let c_count = count.clone();
+        let c_output = output.clone();
+        let c_throughput = tx_throughput.clone();
         task::spawn(async move {
             loop {
                 task::sleep(Duration::from_secs(1)).await;
                 let c = c_count.swap(0, Ordering::Relaxed);
                 if c > 0 {
-                    println!("{} msg/s", c);
+                    c_throughput.record(
+                        &c_output,
+                        "throughput",
+                        payload_size,
+                        c as f64,
+                        &[("direction", "tx".to_string())],
+                    );
                 }
             }
         });
     }
 
-    loop {
+    while run.is_running() {
         for t in transports.iter() {
             let message = ZenohMessage::make_data(
                 key.clone(),
@@ -236,5 +289,12 @@ async fn main() {
             t.handle_message(message).unwrap();
         }
         count.fetch_add(1, Ordering::Relaxed);
+        run.tick();
+    }
+
+    rx_throughput.emit(&output.clone().label("direction", "rx"), "throughput");
+    if print {
+        tx_throughput.emit(&output.clone().label("direction", "tx"), "throughput");
     }
+    run.emit_totals(&output, payload_size);
 }
@@ -11,36 +11,227 @@
 // Contributors:
 //   ADLINK zenoh team, <zenoh@adlink-labs.tech>
 //
-use async_std::future;
+use async_std::task;
 use clap::Parser;
 use slab::Slab;
 use std::{
     any::Any,
-    path::PathBuf,
-    sync::{Arc, RwLock},
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
 };
-use zenoh::{
-    config::{Config, WhatAmI},
-    net::{
-        link::{EndPoint, Link},
-        protocol::proto::ZenohMessage,
-        transport::*,
+use zenoh::net::{
+    link::Link,
+    protocol::{
+        core::{KeyExpr, ZInt},
+        proto::{
+            Data, Declaration, Declare, ForgetPublisher, ForgetResource, ForgetSubscriber,
+            Publisher, Resource, Subscriber, ZenohBody, ZenohMessage,
+        },
     },
+    transport::*,
 };
 use zenoh_core::zresult::ZResult;
+use zenoh_perf_common::{
+    matches, CommonOpt, MulticastHandler, MulticastOpt, Output, OutputFormat, Run, RunOpt, Summary,
+    Transport,
+};
+
+// A key expression carrying the whole key, without any resource id
+fn full_key(key: String) -> KeyExpr<'static> {
+    KeyExpr {
+        scope: 0,
+        suffix: key.into(),
+    }
+}
+
+// A transport of the router with what it learnt from the declarations of
+// the peer and its counters since the last report
+struct Peer {
+    transport: Transport,
+    resources: Mutex<HashMap<ZInt, String>>,
+    subscriptions: RwLock<Vec<String>>,
+    ingress: AtomicUsize,
+    egress: AtomicUsize,
+    drops: AtomicUsize,
+    // The messages being handed over to the transport of the peer, which
+    // blocks on its full queues with the blocking congestion control
+    inflight: AtomicUsize,
+}
+
+impl Peer {
+    fn new(transport: Transport) -> Self {
+        Self {
+            transport,
+            resources: Mutex::new(HashMap::new()),
+            subscriptions: RwLock::new(vec![]),
+            ingress: AtomicUsize::new(0),
+            egress: AtomicUsize::new(0),
+            drops: AtomicUsize::new(0),
+            inflight: AtomicUsize::new(0),
+        }
+    }
+
+    // The whole key of a key expression of the peer, `None` for an unknown resource id
+    fn resolve(&self, key: &KeyExpr) -> Option<String> {
+        match key.scope {
+            0 => Some(key.suffix.to_string()),
+            scope => {
+                let resources = self.resources.lock().unwrap();
+                let prefix = resources.get(&scope)?;
+                Some(format!("{prefix}{}", key.suffix))
+            }
+        }
+    }
+
+    fn subscribed(&self, key: &str) -> bool {
+        let subscriptions = self.subscriptions.read().unwrap();
+        subscriptions.iter().any(|expr| matches(expr, key))
+    }
+
+    // Learns the resources and the subscriptions of the peer. The resources
+    // are not forwarded, the keys of the other declarations are rewritten in
+    // full since the resource ids only make sense on the transport of the peer.
+    fn declare(&self, declarations: &mut Vec<Declaration>) {
+        declarations.retain_mut(|declaration| match declaration {
+            Declaration::Resource(Resource { expr_id, key }) => {
+                if let Some(full) = self.resolve(key) {
+                    self.resources.lock().unwrap().insert(*expr_id, full);
+                }
+                false
+            }
+            Declaration::ForgetResource(ForgetResource { expr_id }) => {
+                self.resources.lock().unwrap().remove(expr_id);
+                false
+            }
+            Declaration::Subscriber(Subscriber { key, .. }) => {
+                if let Some(full) = self.resolve(key) {
+                    self.subscriptions.write().unwrap().push(full.clone());
+                    *key = full_key(full);
+                }
+                true
+            }
+            Declaration::ForgetSubscriber(ForgetSubscriber { key }) => {
+                if let Some(full) = self.resolve(key) {
+                    let mut subscriptions = self.subscriptions.write().unwrap();
+                    if let Some(i) = subscriptions.iter().position(|expr| *expr == full) {
+                        subscriptions.swap_remove(i);
+                    }
+                    *key = full_key(full);
+                }
+                true
+            }
+            Declaration::Publisher(Publisher { key })
+            | Declaration::ForgetPublisher(ForgetPublisher { key }) => {
+                if let Some(full) = self.resolve(key) {
+                    *key = full_key(full);
+                }
+                true
+            }
+            // The other declarations are forwarded as they are
+            _ => true,
+        });
+    }
+
+    fn send(&self, message: ZenohMessage) {
+        self.inflight.fetch_add(1, Ordering::Relaxed);
+        match self.transport.handle_message(message) {
+            Ok(()) => self.egress.fetch_add(1, Ordering::Relaxed),
+            Err(_) => self.drops.fetch_add(1, Ordering::Relaxed),
+        };
+        self.inflight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// The peers of the router
+struct Router {
+    peers: RwLock<Slab<Arc<Peer>>>,
+    // Forward the data to the peers with a matching subscription only
+    routing: bool,
+    // The data matching no subscription since the last report
+    unrouted: AtomicUsize,
+    // Counts the messages received from all the peers
+    run: Arc<Run>,
+    forwarded: Arc<Summary>,
+}
+
+impl Router {
+    fn new(routing: bool, run: &Arc<Run>) -> Self {
+        Self {
+            peers: RwLock::new(Slab::new()),
+            routing,
+            unrouted: AtomicUsize::new(0),
+            run: run.clone(),
+            forwarded: run.summary(),
+        }
+    }
+
+    fn add(&self, transport: Transport) -> (usize, Arc<Peer>) {
+        let peer = Arc::new(Peer::new(transport));
+        let index = self.peers.write().unwrap().insert(peer.clone());
+        (index, peer)
+    }
+
+    // The peers to send a message to, taken out of the lock since sending
+    // may block on their full queues
+    fn targets<F>(&self, filter: F) -> Vec<Arc<Peer>>
+    where
+        F: Fn(usize, &Peer) -> bool,
+    {
+        let peers = self.peers.read().unwrap();
+        peers
+            .iter()
+            .filter(|(i, peer)| filter(*i, peer))
+            .map(|(_, peer)| peer.clone())
+            .collect()
+    }
+
+    fn remove(&self, index: usize) {
+        self.peers.write().unwrap().try_remove(index);
+    }
 
-type Table = Arc<RwLock<Slab<TransportUnicast>>>;
+    // Reports the ingress, the egress, the drops and the in-flight messages of every
+    // peer over the last `elapsed` microseconds, then the forwarded and the
+    // dropped messages of the router
+    fn record(&self, output: &Output, elapsed: f64) {
+        let rate = |c: usize| (c as f64 * 1_000_000.0 / elapsed).floor();
+        let mut forwarded = 0;
+        let mut drops = self.unrouted.swap(0, Ordering::Relaxed);
+        for (index, peer) in self.peers.read().unwrap().iter() {
+            let ingress = peer.ingress.swap(0, Ordering::Relaxed);
+            let egress = peer.egress.swap(0, Ordering::Relaxed);
+            let peer_drops = peer.drops.swap(0, Ordering::Relaxed);
+            let inflight = peer.inflight.load(Ordering::Relaxed);
+            forwarded += egress;
+            drops += peer_drops;
+            let labels = [("peer", index.to_string())];
+            output.emit_with("ingress", 0, rate(ingress), "msg/s", &labels);
+            output.emit_with("egress", 0, rate(egress), "msg/s", &labels);
+            output.emit_with("drops", 0, rate(peer_drops), "msg/s", &labels);
+            output.emit_with("inflight", 0, inflight as f64, "msg", &labels);
+        }
+        self.forwarded
+            .record(output, "forwarded", 0, rate(forwarded), "msg/s", &[]);
+        output.emit("drops", 0, rate(drops), "msg/s");
+    }
+
+    // Emits the summary of the forwarded messages
+    fn emit(&self, output: &Output) {
+        self.forwarded.emit(output, "forwarded", 0, "msg/s");
+    }
+}
 
 // Transport Handler for the peer
 struct MySH {
-    table: Table,
+    router: Arc<Router>,
 }
 
 impl MySH {
-    fn new() -> Self {
-        Self {
-            table: Arc::new(RwLock::new(Slab::new())),
-        }
+    fn new(router: Arc<Router>) -> Self {
+        Self { router }
     }
 }
 
@@ -50,46 +241,95 @@ impl TransportEventHandler for MySH {
         _peer: TransportPeer,
         transport: TransportUnicast,
     ) -> ZResult<Arc<dyn TransportPeerEventHandler>> {
-        let index = self.table.write().unwrap().insert(transport);
-        Ok(Arc::new(MyMH::new(self.table.clone(), index)))
+        let (index, peer) = self.router.add(transport.into());
+        Ok(Arc::new(MyMH::new(self.router.clone(), index, peer, true)))
     }
 
     fn new_multicast(
         &self,
-        _transport: TransportMulticast,
+        transport: TransportMulticast,
     ) -> ZResult<Arc<dyn TransportMulticastEventHandler>> {
-        panic!();
+        // The messages of the group are routed to the other transports only
+        let (index, peer) = self.router.add(transport.into());
+        let router = self.router.clone();
+        Ok(Arc::new(MulticastHandler::new(move |_peer| {
+            MyMH::new(router.clone(), index, peer.clone(), false)
+        })))
     }
 }
 
 // Message Handler for the peer
 struct MyMH {
-    table: Table,
+    router: Arc<Router>,
     index: usize,
+    peer: Arc<Peer>,
+    // Remove the peer from the router once closed, the members of a multicast
+    // group share the peer of the group instead
+    owned: bool,
 }
 
 impl MyMH {
-    fn new(table: Table, index: usize) -> Self {
-        Self { table, index }
+    fn new(router: Arc<Router>, index: usize, peer: Arc<Peer>, owned: bool) -> Self {
+        Self {
+            router,
+            index,
+            peer,
+            owned,
+        }
     }
 }
 
 impl TransportPeerEventHandler for MyMH {
-    fn handle_message(&self, message: ZenohMessage) -> ZResult<()> {
-        let read_table = self.table.read().unwrap();
-        let read_table = read_table.iter();
-        for (i, e) in read_table {
-            if i != self.index {
-                let _ = e.handle_message(message.clone());
+    fn handle_message(&self, mut message: ZenohMessage) -> ZResult<()> {
+        self.peer.ingress.fetch_add(1, Ordering::Relaxed);
+        self.router.run.tick();
+        if self.router.routing {
+            match &mut message.body {
+                ZenohBody::Data(Data { key, .. }) => {
+                    let full = match self.peer.resolve(key) {
+                        Some(full) => full,
+                        None => {
+                            self.router.unrouted.fetch_add(1, Ordering::Relaxed);
+                            return Ok(());
+                        }
+                    };
+                    let targets = self
+                        .router
+                        .targets(|i, peer| i != self.index && peer.subscribed(&full));
+                    if targets.is_empty() {
+                        self.router.unrouted.fetch_add(1, Ordering::Relaxed);
+                        return Ok(());
+                    }
+                    *key = full_key(full);
+                    for peer in targets {
+                        peer.send(message.clone());
+                    }
+                    return Ok(());
+                }
+                ZenohBody::Declare(Declare { declarations }) => {
+                    self.peer.declare(declarations);
+                    if declarations.is_empty() {
+                        return Ok(());
+                    }
+                }
+                _ => {}
             }
         }
+
+        for peer in self.router.targets(|i, _| i != self.index) {
+            peer.send(message.clone());
+        }
         Ok(())
     }
 
     fn new_link(&self, _link: Link) {}
     fn del_link(&self, _link: Link) {}
     fn closing(&self) {}
-    fn closed(&self) {}
+    fn closed(&self) {
+        if self.owned {
+            self.router.remove(self.index);
+        }
+    }
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -98,51 +338,83 @@ impl TransportPeerEventHandler for MyMH {
 #[derive(Debug, Parser)]
 #[clap(name = "t_router_thr")]
 struct Opt {
-    /// which endpoints to listen on. e.g. --listen tcp/127.0.0.1:7447,tcp/127.0.0.1:7448
-    #[clap(short, long, value_delimiter = ',')]
-    listen: Vec<EndPoint>,
+    #[clap(flatten)]
+    common: CommonOpt,
+
+    #[clap(flatten)]
+    multicast: MulticastOpt,
+
+    #[clap(flatten)]
+    run: RunOpt,
+
+    /// learn the subscriptions from the declarations and forward the data to
+    /// the matching peers only, instead of flooding every peer
+    #[clap(long)]
+    routing: bool,
 
-    /// which endpoints to connect to., e.g. --connect tcp/127.0.0.1:7447,tcp/127.0.0.1:7448
-    #[clap(short, long, value_delimiter = ',')]
-    connect: Vec<EndPoint>,
+    /// report the ingress, egress, drops and in-flight messages of every peer every second
+    #[clap(short = 't', long)]
+    print: bool,
 
-    /// configuration file (json5 or yaml)
-    #[clap(long = "conf", parse(from_os_str))]
-    config: Option<PathBuf>,
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
 
 #[async_std::main]
 async fn main() {
     // Parse the args
     let Opt {
-        listen,
-        connect,
-        config,
+        common,
+        multicast,
+        run,
+        routing,
+        print,
+        output,
     } = Opt::parse();
 
-    if listen.is_empty() && connect.is_empty() {
-        panic!("Either --listen or --connect needs to be specified, see --help for more details");
-    }
-
     // Create the session manager
-    let builder = match config {
-        Some(path) => TransportManager::builder()
-            .from_config(&Config::from_file(path).unwrap())
-            .await
-            .unwrap(),
-        None => TransportManager::builder().whatami(WhatAmI::Router),
-    };
-    let handler = Arc::new(MySH::new());
-    let manager = builder.build(handler).unwrap();
+    let run = run.start();
+    let router = Arc::new(Router::new(routing, &run));
+    let handler = Arc::new(MySH::new(router.clone()));
+    let manager = TransportManager::builder()
+        .from_config(&multicast.config(&common))
+        .await
+        .unwrap()
+        .build(handler)
+        .unwrap();
+
+    let output = Output::new(output, "t_router_thr", "router")
+        .label("routing", if routing { "keys" } else { "flood" });
+    let output = multicast.label(output);
+    if print {
+        let c_output = output.clone();
+        let c_router = router.clone();
+        let c_run = run.clone();
+        task::spawn(async move {
+            while c_run.is_running() {
+                let now = Instant::now();
+                task::sleep(Duration::from_secs(1)).await;
+                let elapsed = now.elapsed().as_micros() as f64;
+                c_router.record(&c_output, elapsed);
+            }
+        });
+    }
 
     // Create listeners
-    for l in listen {
-        manager.add_listener(l.clone()).await.unwrap();
+    for e in common.listen_endpoints() {
+        manager.add_listener(e).await.unwrap();
     }
     // Connect to other routers
-    for l in connect {
-        let _t = manager.open_transport_unicast(l.clone()).await.unwrap();
+    for e in common.connect_endpoints() {
+        let _t = manager.open_transport_unicast(e).await.unwrap();
+    }
+    // Join the multicast groups
+    let _t = multicast.join(&manager).await;
+
+    run.wait().await;
+    if print {
+        router.emit(&output);
     }
-    // Stop forever
-    future::pending::<()>().await;
+    run.emit_totals(&output, 0);
 }
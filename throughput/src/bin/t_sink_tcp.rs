@@ -11,28 +11,28 @@
 // Contributors:
 //   ADLINK zenoh team, <zenoh@adlink-labs.tech>
 //
-use async_std::{
-    net::{SocketAddr, TcpListener, TcpStream},
-    prelude::*,
-    sync::Arc,
-    task,
-};
-use clap::Parser;
+use async_std::{prelude::*, sync::Arc, task};
+use clap::{error::ErrorKind, CommandFactory, Parser};
 use std::{
+    collections::HashMap,
     convert::TryInto,
     io::Write,
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
     time::Duration,
 };
 use zenoh::net::protocol::{
+    core::ZInt,
     io::{WBuf, ZBuf, ZSlice},
-    proto::{InitSyn, OpenSyn, TransportBody, TransportMessage},
+    proto::{Frame, FramePayload, InitSyn, OpenSyn, TransportBody, TransportMessage, ZenohBody},
 };
 use zenoh::{
     config::WhatAmI,
-    prelude::{MessageReader, MessageWriter, PeerId},
+    prelude::{MessageReader, MessageWriter, PeerId, Reliability},
+};
+use zenoh_buffers::traits::reader::{HasReader, Reader};
+use zenoh_perf_common::{
+    Histogram, Output, OutputFormat, RawAddr, RawListener, RawStream, Run, RunOpt, Summary, Warmup,
 };
-use zenoh_buffers::traits::reader::HasReader;
 
 macro_rules! zsend {
     ($msg:expr, $stream:expr) => {{
@@ -69,7 +69,84 @@ macro_rules! zrecv {
     }};
 }
 
-async fn handle_client(mut stream: TcpStream) -> Result<(), Box<dyn std::error::Error>> {
+// The statistics of the decoded transport messages shared by all the clients
+struct Decoding {
+    // Accounts for the data messages, bounding the run with --count
+    run: Arc<Run>,
+    messages: Arc<Summary>,
+    // The number of data messages packed in every frame
+    packing: Histogram,
+}
+
+// The transport messages decoded since the last report
+#[derive(Default)]
+struct Decoded {
+    frames: AtomicUsize,
+    data: AtomicUsize,
+    // The frames carrying a fragment of a message larger than a batch
+    fragments: AtomicUsize,
+    // The bytes of the batches holding frames
+    frame_bytes: AtomicUsize,
+    // The frames of the batches counted in `frame_bytes`
+    frame_batches: AtomicUsize,
+    keep_alives: AtomicUsize,
+    // Frames whose sequence number is not the next one of their channel
+    sn_errors: AtomicUsize,
+    // Batches which failed to decode
+    errors: AtomicUsize,
+}
+
+impl Decoded {
+    // Reports the counters over the last second, the data messages are
+    // also recorded in `messages`
+    fn record(&self, output: &Output, messages: &Summary) {
+        let frames = self.frames.swap(0, Ordering::Relaxed);
+        let data = self.data.swap(0, Ordering::Relaxed);
+        let fragments = self.fragments.swap(0, Ordering::Relaxed);
+        let frame_bytes = self.frame_bytes.swap(0, Ordering::Relaxed);
+        let frame_batches = self.frame_batches.swap(0, Ordering::Relaxed);
+        let keep_alives = self.keep_alives.swap(0, Ordering::Relaxed);
+        let sn_errors = self.sn_errors.swap(0, Ordering::Relaxed);
+        let errors = self.errors.swap(0, Ordering::Relaxed);
+        if frames + keep_alives + errors == 0 {
+            return;
+        }
+        messages.record(output, "throughput", 0, data as f64, "msg/s", &[]);
+        output.emit("frames", 0, frames as f64, "frame/s");
+        if frames > 0 {
+            output.emit(
+                "frame.messages",
+                0,
+                data as f64 / frames as f64,
+                "msg/frame",
+            );
+        }
+        if frame_batches > 0 {
+            let size = frame_bytes as f64 / frame_batches as f64;
+            output.emit("frame.size", 0, size, "bytes");
+        }
+        output.emit("fragments", 0, fragments as f64, "frame/s");
+        output.emit("keep_alives", 0, keep_alives as f64, "msg/s");
+        output.emit("sn.errors", 0, sn_errors as f64, "frame");
+        output.emit("decode.errors", 0, errors as f64, "batch");
+    }
+}
+
+// Stops the tasks of a client once dropped, i.e. once it disconnected
+struct Closed(Arc<AtomicBool>);
+
+impl Drop for Closed {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+async fn handle_client(
+    mut stream: RawStream,
+    output: Output,
+    summary: Arc<Summary>,
+    decoding: Option<Arc<Decoding>>,
+) -> Result<(), Box<dyn std::error::Error>> {
     let my_whatami = WhatAmI::Router;
     let my_pid = PeerId::rand();
 
@@ -78,8 +155,12 @@ async fn handle_client(mut stream: TcpStream) -> Result<(), Box<dyn std::error::
 
     // Read the InitSyn
     let message = zrecv!(stream, buffer);
-    match &message.body {
-        TransportBody::InitSyn(InitSyn { is_qos, .. }) => {
+    let sn_resolution = match &message.body {
+        TransportBody::InitSyn(InitSyn {
+            is_qos,
+            sn_resolution: resolution,
+            ..
+        }) => {
             let whatami = my_whatami;
             let sn_resolution = None;
             let cookie = ZSlice::from(vec![0u8; 8]);
@@ -94,13 +175,14 @@ async fn handle_client(mut stream: TcpStream) -> Result<(), Box<dyn std::error::
             );
             // Send the InitAck
             zsend!(message, stream).unwrap();
+            *resolution
         }
         _ => panic!(),
-    }
+    };
 
     // Read the OpenSyn
     let message = zrecv!(stream, buffer);
-    match &message.body {
+    let initial_sn = match &message.body {
         TransportBody::OpenSyn(OpenSyn {
             lease, initial_sn, ..
         }) => {
@@ -108,64 +190,214 @@ async fn handle_client(mut stream: TcpStream) -> Result<(), Box<dyn std::error::
             let mut message = TransportMessage::make_open_ack(*lease, *initial_sn, attachment);
             // Send the OpenAck
             zsend!(message, stream).unwrap();
+            *initial_sn
         }
         _ => panic!(),
-    }
+    };
+
+    let closed = Arc::new(AtomicBool::new(false));
+    let _closed = Closed(closed.clone());
 
     // Spawn the loggin task
+    let c_decoding = decoding.clone();
     let counter = Arc::new(AtomicUsize::new(0));
+    let decoded = Arc::new(Decoded::default());
     let c_c = counter.clone();
+    let c_decoded = decoded.clone();
+    let c_closed = closed.clone();
     task::spawn(async move {
         loop {
             task::sleep(Duration::from_secs(1)).await;
+            if c_closed.load(Ordering::Relaxed) {
+                break;
+            }
             let c = c_c.swap(0, Ordering::Relaxed);
             if c > 0 {
-                println!("{:.6} Gbit/s", (8_f64 * c as f64) / 1000000000_f64);
+                summary.record(&output, "throughput", 0, 8_f64 * c as f64, "bit/s", &[]);
+            }
+            if let Some(decoding) = &c_decoding {
+                c_decoded.record(&output, &decoding.messages);
             }
         }
     });
 
     // Spawn the KeepAlive task
     let mut c_stream = stream.clone();
+    let c_closed = closed.clone();
     task::spawn(async move {
         loop {
             task::sleep(Duration::from_secs(1)).await;
+            if c_closed.load(Ordering::Relaxed) {
+                break;
+            }
             let mut message = TransportMessage::make_keep_alive(None, None);
             let _ = zsend!(message, c_stream);
         }
     });
 
-    // Read from the socket
+    let decoding = match decoding {
+        Some(decoding) => decoding,
+        None => {
+            // Read from the socket
+            loop {
+                let n = stream.read(&mut buffer).await?;
+                let _ = counter.fetch_add(n, Ordering::Relaxed);
+            }
+        }
+    };
+
+    // Decode the batches of transport messages, the next sequence number
+    // expected on every channel is kept by (priority, reliable)
+    let mut next_sn: HashMap<(u8, bool), ZInt> = HashMap::new();
     loop {
-        let n = stream.read(&mut buffer).await?;
-        let _ = counter.fetch_add(n, Ordering::Relaxed);
+        stream.read_exact(&mut buffer[0..2]).await?;
+        let length: [u8; 2] = buffer[0..2].try_into().unwrap();
+        let to_read = u16::from_le_bytes(length) as usize;
+        stream.read_exact(&mut buffer[0..to_read]).await?;
+        let _ = counter.fetch_add(2 + to_read, Ordering::Relaxed);
+
+        let zbuf = ZBuf::from(buffer[0..to_read].to_vec());
+        let mut reader = zbuf.reader();
+        let mut frames = 0;
+        while reader.can_read() {
+            let message = match reader.read_transport_message() {
+                Some(message) => message,
+                None => {
+                    decoded.errors.fetch_add(1, Ordering::Relaxed);
+                    break;
+                }
+            };
+            match message.body {
+                TransportBody::Frame(Frame {
+                    channel,
+                    sn,
+                    payload,
+                }) => {
+                    decoded.frames.fetch_add(1, Ordering::Relaxed);
+                    frames += 1;
+                    let key = (
+                        channel.priority as u8,
+                        channel.reliability == Reliability::Reliable,
+                    );
+                    let expected = next_sn.get(&key).copied().unwrap_or(initial_sn);
+                    if sn != expected {
+                        decoded.sn_errors.fetch_add(1, Ordering::Relaxed);
+                    }
+                    next_sn.insert(key, (sn + 1) % sn_resolution);
+                    let data = match payload {
+                        // The declarations and the queries are not data
+                        FramePayload::Messages { messages } => {
+                            let data = messages
+                                .iter()
+                                .filter(|message| matches!(message.body, ZenohBody::Data(_)))
+                                .count();
+                            decoding.packing.add(data);
+                            data
+                        }
+                        // A fragmented message counts once complete
+                        FramePayload::Fragment { is_final, .. } => {
+                            decoded.fragments.fetch_add(1, Ordering::Relaxed);
+                            is_final as usize
+                        }
+                    };
+                    decoded.data.fetch_add(data, Ordering::Relaxed);
+                    decoding.run.add(data as u64);
+                }
+                TransportBody::KeepAlive(_) => {
+                    decoded.keep_alives.fetch_add(1, Ordering::Relaxed);
+                }
+                _ => {}
+            }
+        }
+        if frames > 0 {
+            decoded.frame_bytes.fetch_add(to_read, Ordering::Relaxed);
+            decoded.frame_batches.fetch_add(frames, Ordering::Relaxed);
+        }
     }
 }
 
-async fn run(addr: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
-    let locator = TcpListener::bind(addr).await?;
-    let mut incoming = locator.incoming();
+async fn serve(
+    addr: RawAddr,
+    output: Output,
+    summary: Arc<Summary>,
+    decoding: Option<Arc<Decoding>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut listener = RawListener::bind(&addr).await?;
 
-    while let Some(stream) = incoming.next().await {
-        let stream = stream?;
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let output = output.clone().label("peer", peer);
+        let summary = summary.clone();
+        let decoding = decoding.clone();
         task::spawn(async move {
-            let _ = handle_client(stream).await;
+            let _ = handle_client(stream, output, summary, decoding).await;
         });
     }
-
-    Ok(())
 }
 
 #[derive(Debug, Parser)]
 #[clap(name = "t_sink_tcp")]
 struct Opt {
+    /// address to listen on, e.g. 127.0.0.1:7447, tcp/127.0.0.1:7447 or
+    /// unixsock-stream//tmp/zenoh.sock
     #[clap(short, long)]
-    listen: SocketAddr,
+    listen: RawAddr,
+
+    #[clap(flatten)]
+    run: RunOpt,
+
+    /// decode the transport messages to count the frames and the data messages,
+    /// and validate the sequence numbers of the frames, required by --count
+    /// and a warm-up in messages
+    #[clap(long)]
+    decode: bool,
+
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
 
 #[async_std::main]
 async fn main() {
     env_logger::init();
     let opt = Opt::parse();
-    let _ = run(opt.listen).await;
+    if !opt.decode
+        && (opt.run.count.is_some() || matches!(opt.run.warmup, Some(Warmup::Messages(_))))
+    {
+        Opt::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "The messages are only counted with --decode, required by --count and a warm-up in messages",
+            )
+            .exit();
+    }
+    let output =
+        Output::new(opt.output, "t_sink_tcp", "link").label("protocol", opt.listen.protocol());
+    let run = opt.run.start();
+    let summary = run.summary();
+    let decoding = opt.decode.then(|| {
+        Arc::new(Decoding {
+            run: run.clone(),
+            messages: run.summary(),
+            packing: Histogram::new(run.clone()),
+        })
+    });
+
+    let _ = serve(
+        opt.listen,
+        output.clone(),
+        summary.clone(),
+        decoding.clone(),
+    )
+    .race(async {
+        run.wait().await;
+        Ok(())
+    })
+    .await;
+
+    summary.emit(&output, "throughput", 0, "bit/s");
+    if let Some(decoding) = &decoding {
+        decoding.messages.emit(&output, "throughput", 0, "msg/s");
+        decoding.packing.emit(&output, "frame.messages", 0, "frame");
+    }
 }
@@ -13,21 +13,26 @@
 //
 use async_std::{
     net::{SocketAddr, UdpSocket},
+    prelude::*,
     sync::Arc,
     task,
 };
 use clap::Parser;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
 use std::time::Duration;
 use zenoh::net::protocol::{
+    core::ZInt,
     io::{WBuf, ZBuf, ZSlice},
-    proto::{InitSyn, OpenSyn, TransportBody, TransportMessage},
+    proto::{Frame, FramePayload, InitSyn, OpenSyn, TransportBody, TransportMessage, ZenohBody},
 };
 use zenoh::{
     config::WhatAmI,
-    prelude::{MessageReader, MessageWriter, PeerId},
+    prelude::{MessageReader, MessageWriter, PeerId, Reliability},
 };
-use zenoh_buffers::traits::reader::HasReader;
+use zenoh_buffers::traits::reader::{HasReader, Reader};
+use zenoh_perf_common::{Losses, Output, OutputFormat, Run, RunOpt, Sequences, Summary};
 
 macro_rules! zsend {
     ($msg:expr, $socket:expr, $addr:expr) => {{
@@ -44,97 +49,245 @@ macro_rules! zsend {
     }};
 }
 
-macro_rules! zrecv {
-    ($socket:expr, $buffer:expr) => {{
-        let (n, addr) = $socket.recv_from(&mut $buffer).await.unwrap();
-        let zbuf = ZBuf::from($buffer[..n].to_vec());
-        (zbuf.reader().read_transport_message().unwrap(), addr)
-    }};
+// The state of a client, identified by its address
+struct Client {
+    // The resolution of the sequence numbers, agreed on in the InitSyn
+    sn_resolution: ZInt,
+    // Set once the OpenSyn is received
+    open: Option<Open>,
 }
 
-async fn handle_client(socket: Arc<UdpSocket>) -> Result<(), Box<dyn std::error::Error>> {
-    let my_whatami = WhatAmI::Router;
-    let my_pid = PeerId::rand();
+// The counters of an opened client, whose tasks stop once dropped
+struct Open {
+    closed: Arc<AtomicBool>,
+    counter: Arc<AtomicUsize>,
+    // The data messages received, a fragmented one counting once complete
+    messages: Arc<AtomicUsize>,
+    sequences: Arc<Sequences>,
+    // The last sequence number of every channel and how many times it wrapped
+    channels: HashMap<u32, (ZInt, u64)>,
+}
 
-    // Create the reading buffer
-    let mut buffer = vec![0u8; 16_000_000];
+impl Open {
+    // The sequence number of a frame unwrapped from the resolution, for the
+    // loss and reordering accounting to go on across the wraps
+    fn unwrap_sn(&mut self, channel: u32, sn: ZInt, resolution: ZInt) -> u64 {
+        let (last, wraps) = self.channels.entry(channel).or_insert((sn, 0));
+        let half = resolution / 2;
+        if sn < *last && *last - sn > half {
+            *wraps += 1;
+            *last = sn;
+        } else if sn > *last && sn - *last > half {
+            // A late frame sent before the last wrap
+            return wraps.saturating_sub(1) * resolution + sn;
+        } else if sn > *last {
+            *last = sn;
+        }
+        *wraps * resolution + sn
+    }
+}
 
-    // Read the InitSyn
-    let (message, addr) = zrecv!(socket, buffer);
+impl Drop for Open {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+}
+
+// Answers the handshake of a client, then counts its bytes and tracks the
+// sequence numbers of its frames
+async fn handle_message(
+    socket: &Arc<UdpSocket>,
+    addr: SocketAddr,
+    message: TransportMessage,
+    clients: &mut HashMap<SocketAddr, Client>,
+    sink: &Sink,
+) -> Result<(), Box<dyn std::error::Error>> {
     match &message.body {
-        TransportBody::InitSyn(InitSyn { is_qos, .. }) => {
-            let whatami = my_whatami;
-            let sn_resolution = None;
+        TransportBody::InitSyn(InitSyn {
+            is_qos,
+            sn_resolution,
+            ..
+        }) => {
+            let whatami = WhatAmI::Router;
             let cookie = ZSlice::from(vec![0u8; 8]);
             let attachment = None;
             let mut message = TransportMessage::make_init_ack(
-                whatami,
-                my_pid,
-                sn_resolution,
-                *is_qos,
-                cookie,
-                attachment,
+                whatami, sink.pid, None, *is_qos, cookie, attachment,
             );
-            // Send the InitAck
-            let _ = zsend!(message, socket, addr).unwrap();
+            // Send the InitAck, a client opening again starts over and the
+            // tasks of its previous session stop
+            zsend!(message, socket, addr)?;
+            let client = Client {
+                sn_resolution: *sn_resolution,
+                open: None,
+            };
+            clients.insert(addr, client);
         }
-        _ => panic!(),
-    }
-
-    // Read the OpenSyn
-    let (message, a) = zrecv!(socket, buffer);
-    if a != addr {
-        panic!("Received data from {}, expected from {}", a, addr);
-    }
-    match &message.body {
         TransportBody::OpenSyn(OpenSyn {
             lease, initial_sn, ..
         }) => {
+            let client = match clients.get_mut(&addr) {
+                Some(client) if client.open.is_none() => client,
+                _ => {
+                    log::warn!("Unexpected OpenSyn from {}", addr);
+                    return Ok(());
+                }
+            };
             let attachment = None;
             let mut message = TransportMessage::make_open_ack(*lease, *initial_sn, attachment);
             // Send the OpenAck
-            let _ = zsend!(message, socket, addr).unwrap();
+            zsend!(message, socket, addr)?;
+            client.open = Some(sink.open(socket, addr));
         }
-        _ => panic!(),
+        TransportBody::Frame(Frame {
+            channel,
+            sn,
+            payload,
+        }) => {
+            // The frames of a client are ignored until it is opened
+            let (sn_resolution, open) = match clients.get_mut(&addr) {
+                Some(Client {
+                    sn_resolution,
+                    open: Some(open),
+                }) => (sn_resolution, open),
+                _ => return Ok(()),
+            };
+            // A fragmented message counts once complete
+            let data = match payload {
+                FramePayload::Messages { messages } => messages
+                    .iter()
+                    .filter(|message| matches!(message.body, ZenohBody::Data(_)))
+                    .count(),
+                FramePayload::Fragment { is_final, .. } => *is_final as usize,
+            };
+            sink.run.add(data as u64);
+            open.messages.fetch_add(data, Ordering::Relaxed);
+            let id =
+                2 * channel.priority as u32 + (channel.reliability == Reliability::Reliable) as u32;
+            let sequence = open.unwrap_sn(id, *sn, *sn_resolution);
+            open.sequences.add(id, sequence);
+        }
+        _ => {}
     }
+    Ok(())
+}
+
+// What the sink shares with all its clients
+struct Sink {
+    pid: PeerId,
+    output: Output,
+    run: Arc<Run>,
+    summary: Arc<Summary>,
+    messages: Arc<Summary>,
+    // The losses of every client opened so far, with its output
+    losses: Arc<Mutex<HashMap<SocketAddr, (Output, Arc<Losses>)>>>,
+}
 
-    // Spawn the loggin task
-    let counter = Arc::new(AtomicUsize::new(0));
-    let c_c = counter.clone();
-    task::spawn(async move {
-        loop {
-            task::sleep(Duration::from_secs(1)).await;
-            let c = c_c.swap(0, Ordering::Relaxed);
-            if c > 0 {
-                println!("{:.6} Gbit/s", (8_f64 * c as f64) / 1000000000_f64);
+impl Sink {
+    // Spawns the logging and the KeepAlive tasks of a client, a client
+    // opening again goes on with the losses of its previous sessions
+    fn open(&self, socket: &Arc<UdpSocket>, addr: SocketAddr) -> Open {
+        let (output, losses) = self
+            .losses
+            .lock()
+            .unwrap()
+            .entry(addr)
+            .or_insert_with(|| {
+                let output = self.output.clone().label("peer", addr);
+                (output, Arc::new(Losses::new(&self.run, 1)))
+            })
+            .clone();
+        let sequences = losses.get(0);
+        // The sequence numbers start over with the session
+        sequences.restart();
+        let closed = Arc::new(AtomicBool::new(false));
+
+        // Spawn the logging task
+        let counter = Arc::new(AtomicUsize::new(0));
+        let c_c = counter.clone();
+        let messages = Arc::new(AtomicUsize::new(0));
+        let c_m = messages.clone();
+        let summary = self.summary.clone();
+        let m_summary = self.messages.clone();
+        let run = self.run.clone();
+        let c_closed = closed.clone();
+        task::spawn(async move {
+            while run.is_running() && !c_closed.load(Ordering::Relaxed) {
+                task::sleep(Duration::from_secs(1)).await;
+                let c = c_c.swap(0, Ordering::Relaxed);
+                if c > 0 {
+                    summary.record(&output, "throughput", 0, 8_f64 * c as f64, "bit/s", &[]);
+                }
+                let m = c_m.swap(0, Ordering::Relaxed);
+                if m > 0 {
+                    m_summary.record(&output, "throughput", 0, m as f64, "msg/s", &[]);
+                }
+                losses.record(&output, Some(0));
             }
-        }
-    });
-
-    // Spawn the KeepAlive task
-    let c_socket = socket.clone();
-    let c_addr = addr;
-    task::spawn(async move {
-        loop {
-            task::sleep(Duration::from_secs(1)).await;
-            let mut message = TransportMessage::make_keep_alive(None, None);
-            let _ = zsend!(message, c_socket, c_addr).unwrap();
-        }
-    });
+        });
 
-    // Read from the socket
-    loop {
-        let (n, a) = socket.recv_from(&mut buffer).await.unwrap();
-        if a != addr {
-            panic!("Received data from {}, expected from {}", a, addr);
+        // Spawn the KeepAlive task
+        let c_socket = socket.clone();
+        let run = self.run.clone();
+        let c_closed = closed.clone();
+        task::spawn(async move {
+            while run.is_running() {
+                task::sleep(Duration::from_secs(1)).await;
+                if c_closed.load(Ordering::Relaxed) {
+                    break;
+                }
+                let mut message = TransportMessage::make_keep_alive(None, None);
+                let _ = zsend!(message, c_socket, addr);
+            }
+        });
+
+        Open {
+            closed,
+            counter,
+            messages,
+            sequences,
+            channels: HashMap::new(),
         }
-        let _ = counter.fetch_add(n, Ordering::Relaxed);
     }
 }
 
-async fn run(addr: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
-    let socket = UdpSocket::bind(addr).await?;
-    handle_client(Arc::new(socket)).await
+async fn serve(addr: SocketAddr, sink: Sink) -> Result<(), Box<dyn std::error::Error>> {
+    let socket = Arc::new(UdpSocket::bind(addr).await?);
+    let mut clients: HashMap<SocketAddr, Client> = HashMap::new();
+
+    // Create the reading buffer
+    let mut buffer = vec![0u8; 65_535];
+
+    // Demultiplex the datagrams of the clients by their address
+    loop {
+        let (n, addr) = socket.recv_from(&mut buffer).await?;
+        if let Some(Client {
+            open: Some(open), ..
+        }) = clients.get(&addr)
+        {
+            let _ = open.counter.fetch_add(n, Ordering::Relaxed);
+        }
+        let zbuf = ZBuf::from(buffer[..n].to_vec());
+        let mut reader = zbuf.reader();
+        while reader.can_read() {
+            match reader.read_transport_message() {
+                Some(message) => {
+                    // A failing client is dropped, its tasks stopping with it,
+                    // while the sink goes on serving the others
+                    let res = handle_message(&socket, addr, message, &mut clients, &sink).await;
+                    if let Err(e) = res {
+                        log::warn!("Dropping {}: {}", addr, e);
+                        clients.remove(&addr);
+                        break;
+                    }
+                }
+                None => {
+                    log::warn!("Failed to decode a datagram from {}", addr);
+                    break;
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug, Parser)]
@@ -142,11 +295,43 @@ async fn run(addr: SocketAddr) -> Result<(), Box<dyn std::error::Error>> {
 struct Opt {
     #[clap(short, long)]
     listen: SocketAddr,
+
+    #[clap(flatten)]
+    run: RunOpt,
+
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
 
 #[async_std::main]
 async fn main() {
     env_logger::init();
     let opt = Opt::parse();
-    let _ = run(opt.listen).await;
+    let output = Output::new(opt.output, "t_sink_udp", "link");
+    let run = opt.run.start();
+    let summary = run.summary();
+    let messages = run.summary();
+    let losses = Arc::new(Mutex::new(HashMap::new()));
+    let sink = Sink {
+        pid: PeerId::rand(),
+        output: output.clone(),
+        run: run.clone(),
+        summary: summary.clone(),
+        messages: messages.clone(),
+        losses: losses.clone(),
+    };
+
+    let _ = serve(opt.listen, sink)
+        .race(async {
+            run.wait().await;
+            Ok(())
+        })
+        .await;
+
+    summary.emit(&output, "throughput", 0, "bit/s");
+    messages.emit(&output, "throughput", 0, "msg/s");
+    for (output, losses) in losses.lock().unwrap().values() {
+        losses.emit(output);
+    }
 }
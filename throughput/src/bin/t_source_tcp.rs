@@ -0,0 +1,246 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+use async_std::{prelude::*, sync::Arc, task};
+use clap::{builder::RangedU64ValueParser, Parser};
+use std::{
+    convert::TryInto,
+    io::Write,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+use zenoh::net::protocol::{
+    core::{Channel, CongestionControl, Priority, Reliability, ZInt},
+    io::{WBuf, ZBuf},
+    proto::{FramePayload, InitAck, OpenAck, TransportBody, TransportMessage, ZenohMessage},
+    VERSION,
+};
+use zenoh::{
+    config::WhatAmI,
+    prelude::{KeyExpr, MessageReader, MessageWriter, PeerId},
+};
+use zenoh_buffers::traits::reader::HasReader;
+use zenoh_perf_common::{Output, OutputFormat, RawAddr, RawStream, RunOpt};
+
+// The resolution of the sequence numbers proposed to the receiver, small
+// enough for a frame of every sequence number to be serialized beforehand
+const SN_RESOLUTION: ZInt = 256;
+const KEY_EXPR: &str = "/test/thr";
+
+macro_rules! zserialize {
+    ($msg:expr) => {{
+        // Create the buffer for serializing the message
+        let mut wbuf = WBuf::new(32, false);
+        // Reserve 16 bits to write the length
+        assert!(wbuf.write(&[0u8, 0u8]).unwrap() > 0);
+        // Serialize the message
+        assert!(wbuf.write_transport_message(&mut $msg));
+        // Write the length on the first 16 bits
+        let length: u16 = (wbuf.len() - 2)
+            .try_into()
+            .expect("A batch cannot exceed 65535 bytes, lower --payload or --messages");
+        let bits = wbuf.get_first_slice_mut(..2);
+        bits.copy_from_slice(&length.to_le_bytes());
+        let mut bytes = vec![0u8; wbuf.len()];
+        wbuf.reader().copy_into_slice(&mut bytes[..]);
+        bytes
+    }};
+}
+
+macro_rules! zsend {
+    ($msg:expr, $stream:expr) => {{
+        let bytes = zserialize!($msg);
+        // Send the message on the link
+        let res = $stream.write_all(&bytes).await;
+        log::trace!("Sending {:?}: {:?}", $msg, res);
+
+        res
+    }};
+}
+
+macro_rules! zrecv {
+    ($stream:expr, $buffer:expr) => {{
+        let _ = $stream.read_exact(&mut $buffer[0..2]).await.unwrap();
+        let length: [u8; 2] = $buffer[0..2].try_into().unwrap();
+        // Decode the total amount of bytes that we are expected to read
+        let to_read = u16::from_le_bytes(length) as usize;
+        $stream.read_exact(&mut $buffer[0..to_read]).await.unwrap();
+        let zbuf = ZBuf::from($buffer[0..to_read].to_vec());
+        zbuf.reader().read_transport_message().unwrap()
+    }};
+}
+
+// Serializes a frame of `messages` data messages of `payload` bytes for
+// every sequence number, starting from `initial_sn`
+fn batches(initial_sn: ZInt, messages: usize, payload: usize) -> Vec<Vec<u8>> {
+    let channel = Channel {
+        priority: Priority::Data,
+        reliability: Reliability::Reliable,
+    };
+    let data = ZBuf::from(vec![0u8; payload]);
+    let messages: Vec<ZenohMessage> = (0..messages)
+        .map(|_| {
+            ZenohMessage::make_data(
+                KeyExpr::from(KEY_EXPR),
+                data.clone(),
+                channel,
+                CongestionControl::Block,
+                None,
+                None,
+                None,
+                None,
+            )
+        })
+        .collect();
+    (0..SN_RESOLUTION)
+        .map(|i| {
+            let sn = (initial_sn + i) % SN_RESOLUTION;
+            let payload = FramePayload::Messages {
+                messages: messages.clone(),
+            };
+            let mut message = TransportMessage::make_frame(channel, sn, payload, None);
+            zserialize!(message)
+        })
+        .collect()
+}
+
+#[derive(Debug, Parser)]
+#[clap(name = "t_source_tcp")]
+struct Opt {
+    /// address of the zenoh receiver to send to, e.g. 127.0.0.1:7447,
+    /// tcp/127.0.0.1:7447 or unixsock-stream//tmp/zenoh.sock
+    #[clap(short, long)]
+    connect: RawAddr,
+
+    #[clap(flatten)]
+    run: RunOpt,
+
+    /// payload size of the data messages (bytes)
+    #[clap(short, long)]
+    payload: usize,
+
+    /// number of data messages per frame
+    #[clap(long, default_value_t = 1, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    messages: usize,
+
+    /// print the counter
+    #[clap(short = 't', long)]
+    print: bool,
+
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
+}
+
+#[async_std::main]
+async fn main() {
+    env_logger::init();
+    let opt = Opt::parse();
+    let mut stream = RawStream::connect(&opt.connect).await.unwrap();
+
+    // Create the reading buffer
+    let mut buffer = vec![0u8; 65_535];
+
+    // Send the InitSyn
+    let is_qos = false;
+    let mut message = TransportMessage::make_init_syn(
+        VERSION,
+        WhatAmI::Peer,
+        PeerId::rand(),
+        SN_RESOLUTION,
+        is_qos,
+        None,
+    );
+    zsend!(message, stream).unwrap();
+
+    // Read the InitAck and send the OpenSyn
+    let message = zrecv!(stream, buffer);
+    let initial_sn = 0;
+    match message.body {
+        TransportBody::InitAck(InitAck {
+            sn_resolution,
+            cookie,
+            ..
+        }) => {
+            if let Some(sn_resolution) = sn_resolution {
+                assert_eq!(
+                    sn_resolution, SN_RESOLUTION,
+                    "The receiver imposed another sequence number resolution"
+                );
+            }
+            let lease = Duration::from_secs(10);
+            let mut message = TransportMessage::make_open_syn(lease, initial_sn, cookie, None);
+            zsend!(message, stream).unwrap();
+        }
+        _ => panic!(),
+    }
+
+    // Read the OpenAck
+    let message = zrecv!(stream, buffer);
+    match message.body {
+        TransportBody::OpenAck(OpenAck { .. }) => {}
+        _ => panic!(),
+    }
+
+    // Drain the KeepAlive of the receiver
+    let mut c_stream = stream.clone();
+    task::spawn(async move {
+        let mut buffer = vec![0u8; 65_535];
+        while let Ok(n) = c_stream.read(&mut buffer).await {
+            if n == 0 {
+                break;
+            }
+        }
+    });
+
+    let run = opt.run.start();
+    let throughput = run.throughput();
+    let output = Output::new(opt.output, "t_source_tcp", "link")
+        .label("protocol", opt.connect.protocol())
+        .label("messages", opt.messages);
+    let counter = Arc::new(AtomicUsize::new(0));
+    if opt.print {
+        let c_counter = counter.clone();
+        let c_output = output.clone();
+        let c_throughput = throughput.clone();
+        let payload = opt.payload;
+        task::spawn(async move {
+            loop {
+                let now = Instant::now();
+                task::sleep(Duration::from_secs(1)).await;
+                let elapsed = now.elapsed().as_micros() as f64;
+                let c = c_counter.swap(0, Ordering::Relaxed);
+                let value = (c as f64 * 1_000_000.0 / elapsed).floor();
+                c_throughput.record(&c_output, "throughput", payload, value, &[]);
+            }
+        });
+    }
+
+    // Write the frames at line rate
+    let batches = batches(initial_sn, opt.messages, opt.payload);
+    'run: loop {
+        for batch in batches.iter() {
+            if !run.is_running() {
+                break 'run;
+            }
+            stream.write_all(batch).await.unwrap();
+            counter.fetch_add(opt.messages, Ordering::Relaxed);
+            run.add(opt.messages as u64);
+        }
+    }
+
+    if opt.print {
+        throughput.emit(&output, "throughput");
+    }
+    run.emit_totals(&output, opt.payload);
+}
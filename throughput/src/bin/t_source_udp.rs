@@ -0,0 +1,231 @@
+//
+// Copyright (c) 2022 ZettaScale Technology
+//
+// This program and the accompanying materials are made available under the
+// terms of the Eclipse Public License 2.0 which is available at
+// http://www.eclipse.org/legal/epl-2.0, or the Apache License, Version 2.0
+// which is available at https://www.apache.org/licenses/LICENSE-2.0.
+//
+// SPDX-License-Identifier: EPL-2.0 OR Apache-2.0
+//
+// Contributors:
+//   ZettaScale Zenoh Team, <zenoh@zettascale.tech>
+//
+use async_std::{
+    net::{SocketAddr, UdpSocket},
+    sync::Arc,
+    task,
+};
+use clap::Parser;
+use std::{
+    io::Write,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+use zenoh::net::protocol::{
+    core::{Channel, CongestionControl, Priority, Reliability, ZInt},
+    io::{WBuf, ZBuf},
+    proto::{FramePayload, InitAck, OpenAck, TransportBody, TransportMessage, ZenohMessage},
+    VERSION,
+};
+use zenoh::{
+    config::WhatAmI,
+    prelude::{KeyExpr, MessageReader, MessageWriter, PeerId},
+};
+use zenoh_buffers::traits::reader::HasReader;
+use zenoh_perf_common::{Output, OutputFormat, RunOpt};
+
+// The resolution of the sequence numbers proposed to the receiver, small
+// enough for a frame of every sequence number to be serialized beforehand
+const SN_RESOLUTION: ZInt = 256;
+const KEY_EXPR: &str = "/test/thr";
+
+// The largest payload of a UDP datagram
+const MAX_DATAGRAM: usize = 65_507;
+
+macro_rules! zserialize {
+    ($msg:expr) => {{
+        // Create the buffer for serializing the message
+        let mut wbuf = WBuf::new(32, false);
+        // Serialize the message
+        assert!(wbuf.write_transport_message(&mut $msg));
+        assert!(
+            wbuf.len() <= MAX_DATAGRAM,
+            "A batch cannot exceed {} bytes, lower --payload or --messages",
+            MAX_DATAGRAM
+        );
+        let mut bytes = vec![0u8; wbuf.len()];
+        wbuf.reader().copy_into_slice(&mut bytes[..]);
+        bytes
+    }};
+}
+
+macro_rules! zsend {
+    ($msg:expr, $socket:expr) => {{
+        let bytes = zserialize!($msg);
+        // Send the message on the link
+        let res = $socket.send(&bytes).await;
+        log::trace!("Sending {:?}: {:?}", $msg, res);
+        res
+    }};
+}
+
+macro_rules! zrecv {
+    ($socket:expr, $buffer:expr) => {{
+        let n = $socket.recv(&mut $buffer).await.unwrap();
+        let zbuf = ZBuf::from($buffer[..n].to_vec());
+        zbuf.reader().read_transport_message().unwrap()
+    }};
+}
+
+// Serializes a frame of `messages` data messages of `payload` bytes for
+// every sequence number, starting from `initial_sn`
+fn batches(initial_sn: ZInt, messages: usize, payload: usize) -> Vec<Vec<u8>> {
+    let channel = Channel {
+        priority: Priority::Data,
+        reliability: Reliability::BestEffort,
+    };
+    let data = ZBuf::from(vec![0u8; payload]);
+    let messages: Vec<ZenohMessage> = (0..messages)
+        .map(|_| {
+            ZenohMessage::make_data(
+                KeyExpr::from(KEY_EXPR),
+                data.clone(),
+                channel,
+                CongestionControl::Block,
+                None,
+                None,
+                None,
+                None,
+            )
+        })
+        .collect();
+    (0..SN_RESOLUTION)
+        .map(|i| {
+            let sn = (initial_sn + i) % SN_RESOLUTION;
+            let payload = FramePayload::Messages {
+                messages: messages.clone(),
+            };
+            let mut message = TransportMessage::make_frame(channel, sn, payload, None);
+            zserialize!(message)
+        })
+        .collect()
+}
+
+#[derive(Debug, Parser)]
+#[clap(name = "t_source_udp")]
+struct Opt {
+    /// address of the zenoh receiver to send to, e.g. 127.0.0.1:7447
+    #[clap(short, long)]
+    connect: SocketAddr,
+
+    #[clap(flatten)]
+    run: RunOpt,
+
+    /// payload size of the data messages (bytes)
+    #[clap(short, long)]
+    payload: usize,
+
+    /// number of data messages per frame
+    #[clap(long, default_value_t = 1)]
+    messages: usize,
+
+    /// print the counter
+    #[clap(short = 't', long)]
+    print: bool,
+
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
+}
+
+#[async_std::main]
+async fn main() {
+    env_logger::init();
+    let opt = Opt::parse();
+    let socket = UdpSocket::bind("0.0.0.0:0").await.unwrap();
+    socket.connect(opt.connect).await.unwrap();
+
+    // Create the reading buffer
+    let mut buffer = vec![0u8; MAX_DATAGRAM];
+
+    // Send the InitSyn
+    let is_qos = false;
+    let mut message = TransportMessage::make_init_syn(
+        VERSION,
+        WhatAmI::Peer,
+        PeerId::rand(),
+        SN_RESOLUTION,
+        is_qos,
+        None,
+    );
+    zsend!(message, socket).unwrap();
+
+    // Read the InitAck and send the OpenSyn
+    let message = zrecv!(socket, buffer);
+    let initial_sn = 0;
+    match message.body {
+        TransportBody::InitAck(InitAck {
+            sn_resolution,
+            cookie,
+            ..
+        }) => {
+            if let Some(sn_resolution) = sn_resolution {
+                assert_eq!(
+                    sn_resolution, SN_RESOLUTION,
+                    "The receiver imposed another sequence number resolution"
+                );
+            }
+            let lease = Duration::from_secs(10);
+            let mut message = TransportMessage::make_open_syn(lease, initial_sn, cookie, None);
+            zsend!(message, socket).unwrap();
+        }
+        _ => panic!(),
+    }
+
+    // Read the OpenAck
+    let message = zrecv!(socket, buffer);
+    match message.body {
+        TransportBody::OpenAck(OpenAck { .. }) => {}
+        _ => panic!(),
+    }
+
+    let run = opt.run.start();
+    let throughput = run.throughput();
+    let output = Output::new(opt.output, "t_source_udp", "link").label("messages", opt.messages);
+    let counter = Arc::new(AtomicUsize::new(0));
+    if opt.print {
+        let c_counter = counter.clone();
+        let c_output = output.clone();
+        let c_throughput = throughput.clone();
+        let payload = opt.payload;
+        task::spawn(async move {
+            loop {
+                let now = Instant::now();
+                task::sleep(Duration::from_secs(1)).await;
+                let elapsed = now.elapsed().as_micros() as f64;
+                let c = c_counter.swap(0, Ordering::Relaxed);
+                let value = (c as f64 * 1_000_000.0 / elapsed).floor();
+                c_throughput.record(&c_output, "throughput", payload, value, &[]);
+            }
+        });
+    }
+
+    // Write the frames at line rate
+    let batches = batches(initial_sn, opt.messages, opt.payload);
+    'run: loop {
+        for batch in batches.iter() {
+            if !run.is_running() {
+                break 'run;
+            }
+            socket.send(batch).await.unwrap();
+            counter.fetch_add(opt.messages, Ordering::Relaxed);
+            run.add(opt.messages as u64);
+        }
+    }
+
+    if opt.print {
+        throughput.emit(&output, "throughput");
+    }
+    run.emit_totals(&output, opt.payload);
+}
@@ -11,39 +11,90 @@
 // Contributors:
 //   ADLINK zenoh team, <zenoh@adlink-labs.tech>
 //
-use async_std::{future, sync::Arc, task};
+use async_std::{sync::Arc, task};
 use clap::Parser;
 use std::{
     any::Any,
-    path::PathBuf,
-    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
     time::{Duration, Instant},
 };
-use zenoh::config::{Config, WhatAmI};
 use zenoh::net::{
-    link::{EndPoint, Link},
-    protocol::proto::ZenohMessage,
+    link::Link,
+    protocol::{
+        core::Priority,
+        io::SplitBuffer,
+        proto::{Data, ZenohBody, ZenohMessage},
+    },
     transport::*,
 };
 use zenoh_core::zresult::ZResult;
+#[cfg(feature = "stats")]
+use zenoh_perf_common::Wire;
+use zenoh_perf_common::{
+    CommonOpt, Header, Integrity, Losses, Meter, Meters, MulticastHandler, MulticastOpt, Output,
+    OutputFormat, Priorities, Producers, Run, RunOpt, Sequences,
+};
+
+// What a subscriber accounts for in every message it receives
+#[derive(Clone)]
+struct Counters {
+    meter: Arc<Meter>,
+    run: Arc<Run>,
+    producers: Option<Arc<Producers>>,
+    sequences: Option<Arc<Sequences>>,
+    priorities: Option<Arc<Priorities>>,
+    integrity: Option<Arc<Integrity>>,
+}
 
-// Transport Handler for the peer
+impl Counters {
+    fn add(&self, priority: Priority, body: &ZenohBody) {
+        match body {
+            ZenohBody::Data(Data { payload, .. }) => {
+                self.meter.add(payload.len());
+                let decode = self.producers.is_some()
+                    || self.sequences.is_some()
+                    || self.priorities.is_some();
+                let header = if decode {
+                    Header::decode_slices(payload.slices()).ok()
+                } else {
+                    None
+                };
+                match (&header, &self.sequences) {
+                    (Some(header), Some(sequences)) => {
+                        sequences.add(header.producer_id, header.sequence)
+                    }
+                    (None, Some(sequences)) => sequences.add_unstamped(),
+                    _ => {}
+                }
+                if let (Some(header), Some(producers)) = (&header, &self.producers) {
+                    producers.add(header.producer_id);
+                }
+                if let Some(priorities) = &self.priorities {
+                    let latency = header.map(|header| header.elapsed());
+                    priorities.add(priority, payload.len(), latency);
+                }
+                if let Some(integrity) = &self.integrity {
+                    let slices: Vec<&[u8]> = payload.slices().collect();
+                    integrity.add(&slices);
+                }
+            }
+            _ => self.meter.add_unsized(),
+        }
+    }
+}
+
+// Transport Handler for the peer, one per subscriber
 struct MySH {
-    scenario: String,
-    name: String,
-    payload: usize,
-    counter: Arc<AtomicUsize>,
-    active: AtomicBool,
+    counters: Counters,
+    #[cfg(feature = "stats")]
+    wire: Arc<Wire>,
 }
 
 impl MySH {
-    fn new(scenario: String, name: String, payload: usize, counter: Arc<AtomicUsize>) -> Self {
+    fn new(counters: Counters, #[cfg(feature = "stats")] wire: Arc<Wire>) -> Self {
         Self {
-            scenario,
-            name,
-            payload,
-            counter,
-            active: AtomicBool::new(false),
+            counters,
+            #[cfg(feature = "stats")]
+            wire,
         }
     }
 }
@@ -54,56 +105,37 @@ impl TransportEventHandler for MySH {
         _peer: TransportPeer,
         _transport: TransportUnicast,
     ) -> ZResult<Arc<dyn TransportPeerEventHandler>> {
-        if !self.active.swap(true, Ordering::Acquire) {
-            let count = self.counter.clone();
-            let scenario = self.scenario.clone();
-            let name = self.name.clone();
-            let payload = self.payload;
-            task::spawn(async move {
-                loop {
-                    let now = Instant::now();
-                    task::sleep(Duration::from_secs(1)).await;
-                    let elapsed = now.elapsed().as_micros() as f64;
-
-                    let c = count.swap(0, Ordering::Relaxed);
-                    if c > 0 {
-                        let interval = 1_000_000.0 / elapsed;
-                        println!(
-                            "session,{},throughput,{},{},{}",
-                            scenario,
-                            name,
-                            payload,
-                            (c as f64 / interval).floor() as usize
-                        );
-                    }
-                }
-            });
-        }
-        Ok(Arc::new(MyMH::new(self.counter.clone())))
+        #[cfg(feature = "stats")]
+        self.wire.add(_transport);
+        Ok(Arc::new(MyMH::new(self.counters.clone())))
     }
 
     fn new_multicast(
         &self,
         _transport: TransportMulticast,
     ) -> ZResult<Arc<dyn TransportMulticastEventHandler>> {
-        panic!();
+        let counters = self.counters.clone();
+        Ok(Arc::new(MulticastHandler::new(move |_peer| {
+            MyMH::new(counters.clone())
+        })))
     }
 }
 
 // Message Handler for the peer
 struct MyMH {
-    counter: Arc<AtomicUsize>,
+    counters: Counters,
 }
 
 impl MyMH {
-    fn new(counter: Arc<AtomicUsize>) -> Self {
-        Self { counter }
+    fn new(counters: Counters) -> Self {
+        Self { counters }
     }
 }
 
 impl TransportPeerEventHandler for MyMH {
-    fn handle_message(&self, _message: ZenohMessage) -> ZResult<()> {
-        self.counter.fetch_add(1, Ordering::Relaxed);
+    fn handle_message(&self, message: ZenohMessage) -> ZResult<()> {
+        self.counters.add(message.channel.priority, &message.body);
+        self.counters.run.tick();
         Ok(())
     }
 
@@ -120,17 +152,14 @@ impl TransportPeerEventHandler for MyMH {
 #[derive(Debug, Parser)]
 #[clap(name = "t_sub_thr")]
 struct Opt {
-    /// endpoint(s), e.g. --endpoint tcp/127.0.0.1:7447,tcp/127.0.0.1:7448
-    #[clap(short, long, required(true), value_delimiter = ',')]
-    endpoint: Vec<EndPoint>,
+    #[clap(flatten)]
+    common: CommonOpt,
 
-    /// peer, router, or client
-    #[clap(short, long)]
-    mode: WhatAmI,
+    #[clap(flatten)]
+    multicast: MulticastOpt,
 
-    /// payload size (bytes)
-    #[clap(short, long)]
-    payload: usize,
+    #[clap(flatten)]
+    run: RunOpt,
 
     #[clap(short, long)]
     name: String,
@@ -138,9 +167,36 @@ struct Opt {
     #[clap(short, long)]
     scenario: String,
 
-    /// configuration file (json5 or yaml)
-    #[clap(long = "conf", parse(from_os_str))]
-    config: Option<PathBuf>,
+    /// break down the throughput per producer and report their fairness index
+    #[clap(long)]
+    producers: bool,
+
+    /// track the sequence numbers of the producers and report the lost,
+    /// duplicated and reordered messages
+    #[clap(long)]
+    losses: bool,
+
+    /// break down the throughput and the latency per priority of the messages
+    #[clap(long)]
+    priorities: bool,
+
+    /// verify the length and the checksum, if any, of every payload once
+    /// reassembled, see --checksum on t_pub_thr
+    #[clap(long)]
+    verify: bool,
+
+    /// number of concurrent subscribers, each with its own transports, see --separate
+    #[clap(long, default_value_t = 1)]
+    subscribers: usize,
+
+    /// open transports per subscriber, each subscriber listening on its own
+    /// --listen endpoint, required by several subscribers
+    #[clap(long)]
+    separate: bool,
+
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
 
 #[async_std::main]
@@ -150,35 +206,134 @@ async fn main() {
 
     // Parse the args
     let Opt {
-        endpoint,
-        mode,
-        payload,
+        common,
+        multicast,
+        run,
         name,
         scenario,
-        config,
+        producers,
+        losses,
+        priorities,
+        verify,
+        subscribers,
+        separate,
+        output,
     } = Opt::parse();
+    // The subscribers sharing the same transports would only count the same
+    // messages several times, which is no fan-out
+    if subscribers > 1 && !separate {
+        panic!("--subscribers needs --separate to give every subscriber its own transports");
+    }
+    let listen = common.listen_endpoints();
+    if separate && !listen.is_empty() && listen.len() != subscribers {
+        panic!(
+            "--separate needs a --listen endpoint per subscriber, e.g. --subscribers 2 --listen tcp/127.0.0.1:7447,tcp/127.0.0.1:7448"
+        );
+    }
+
+    let run = run.start();
+    let fairness = run.summary();
+    let meters = Arc::new(Meters::subscribers(&run, subscribers));
+    let producers = producers.then(|| Arc::new(Producers::new()));
+    let losses = losses.then(|| Arc::new(Losses::new(&run, subscribers)));
+    let priorities = priorities.then(|| Arc::new(Priorities::new(&run)));
+    let integrity = verify.then(|| Arc::new(Integrity::new(&run)));
+    #[cfg(feature = "stats")]
+    let wire = Arc::new(Wire::new(&run));
+    let output = Output::new(output, "t_sub_thr", "transport")
+        .label("scenario", scenario)
+        .label("name", name);
+    let output = multicast.label(output);
 
-    // Setup TransportManager
-    let count = Arc::new(AtomicUsize::new(0));
-    let builder = match config {
-        Some(path) => TransportManager::builder()
-            .from_config(&Config::from_file(path).unwrap())
+    let counters: Vec<Counters> = (0..subscribers)
+        .map(|i| Counters {
+            meter: meters.get(i).1,
+            run: run.clone(),
+            producers: producers.clone(),
+            sequences: losses.as_ref().map(|losses| losses.get(i)),
+            priorities: priorities.clone(),
+            integrity: integrity.clone(),
+        })
+        .collect();
+
+    // Setup a TransportManager per subscriber, listening on its own endpoint
+    // with --separate, all of them join the multicast groups
+    let mut managers = vec![];
+    for (i, counters) in counters.into_iter().enumerate() {
+        let handler = Arc::new(MySH::new(
+            counters,
+            #[cfg(feature = "stats")]
+            wire.clone(),
+        ));
+        let manager = TransportManager::builder()
+            .from_config(&multicast.config(&common))
             .await
-            .unwrap(),
-        None => TransportManager::builder().whatami(WhatAmI::Router),
-    };
-    let handler = Arc::new(MySH::new(scenario, name, payload, count));
-    let manager = builder.build(handler).unwrap();
-
-    if mode == WhatAmI::Peer {
-        for e in endpoint {
+            .unwrap()
+            .build(handler)
+            .unwrap();
+        let endpoints = match separate {
+            true => listen.get(i..=i).unwrap_or_default(),
+            false => &listen[..],
+        };
+        for e in endpoints.iter() {
             manager.add_listener(e.clone()).await.unwrap();
         }
-    } else {
-        for e in endpoint {
-            let _t = manager.open_transport_unicast(e.clone()).await.unwrap();
+        for e in common.connect_endpoints() {
+            let _t = manager.open_transport_unicast(e).await.unwrap();
+        }
+        let _t = multicast.join(&manager).await;
+        managers.push(manager);
+    }
+
+    let c_meters = meters.clone();
+    let c_output = output.clone();
+    let c_run = run.clone();
+    let c_producers = producers.clone();
+    let c_fairness = fairness.clone();
+    let c_losses = losses.clone();
+    let c_priorities = priorities.clone();
+    let c_integrity = integrity.clone();
+    #[cfg(feature = "stats")]
+    let c_wire = wire.clone();
+    task::spawn(async move {
+        while c_run.is_running() {
+            let now = Instant::now();
+            task::sleep(Duration::from_secs(1)).await;
+            let elapsed = now.elapsed().as_micros() as f64;
+
+            let payload = c_meters.record(&c_output, elapsed);
+            if let Some(producers) = &c_producers {
+                producers.record(&c_output, payload, elapsed, &c_fairness);
+            }
+            if let Some(losses) = &c_losses {
+                losses.record(&c_output, payload);
+            }
+            if let Some(priorities) = &c_priorities {
+                priorities.record(&c_output, elapsed);
+            }
+            if let Some(integrity) = &c_integrity {
+                integrity.record(&c_output, elapsed);
+            }
+            #[cfg(feature = "stats")]
+            c_wire.record(&c_output, payload, elapsed);
         }
+    });
+    run.wait().await;
+
+    meters.emit(&output);
+    if producers.is_some() {
+        fairness.emit(&output, "fairness", meters.payload(), "index");
+    }
+    if let Some(losses) = &losses {
+        losses.emit(&output);
+    }
+    if let Some(priorities) = &priorities {
+        priorities.emit(&output);
+    }
+    if let Some(integrity) = &integrity {
+        integrity.emit(&output);
     }
-    // Stop forever
-    future::pending::<()>().await;
+    #[cfg(feature = "stats")]
+    wire.emit(&output);
+    run.emit_totals(&output, meters.payload());
 }
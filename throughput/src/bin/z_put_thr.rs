@@ -13,37 +13,50 @@
 //
 use async_std::{sync::Arc, task};
 use clap::Parser;
-use std::{
-    path::PathBuf,
-    sync::atomic::{AtomicUsize, Ordering},
-    time::Duration,
+use std::time::{Duration, Instant};
+use zenoh::{
+    prelude::{KeyExpr, Value},
+    Session,
+};
+#[cfg(feature = "shared-memory")]
+use zenoh_perf_common::ShmOpt;
+use zenoh_perf_common::{
+    parse_publishers, CommonOpt, CongestionOpt, KeysOpt, LinkOpt, Meter, Meters, Output,
+    OutputFormat, Payload, RateOpt, Run, RunOpt, Stalls, SweepOpt,
 };
-use zenoh::{config::Config, prelude::Value};
-use zenoh_protocol_core::{CongestionControl, EndPoint, WhatAmI};
 
 #[derive(Debug, Parser)]
 #[clap(name = "z_put_thr")]
 struct Opt {
-    /// endpoint(s), e.g. --endpoint tcp/127.0.0.1:7447,tcp/127.0.0.1:7448
-    #[clap(short, long, required(true), value_delimiter = ',')]
-    endpoint: Vec<EndPoint>,
+    #[clap(flatten)]
+    common: CommonOpt,
+
+    #[clap(flatten)]
+    run: RunOpt,
+
+    #[clap(flatten)]
+    rate: RateOpt,
+
+    #[clap(flatten)]
+    congestion: CongestionOpt,
 
-    /// peer, router, or client
-    #[clap(short, long)]
-    mode: WhatAmI,
+    #[clap(flatten)]
+    link: LinkOpt,
 
-    /// payload size (bytes)
-    #[clap(short, long)]
-    payload: usize,
+    #[clap(flatten)]
+    sweep: SweepOpt,
+
+    #[cfg(feature = "shared-memory")]
+    #[clap(flatten)]
+    shm: ShmOpt,
+
+    #[clap(flatten)]
+    keys: KeysOpt,
 
     /// print the counter
     #[clap(short = 't', long)]
     print: bool,
 
-    /// configuration file (json5 or yaml)
-    #[clap(long = "conf", parse(from_os_str))]
-    config: Option<PathBuf>,
-
     /// declare a numerical Id for the publisher's key expression
     #[clap(long)]
     use_expr: bool,
@@ -51,85 +64,177 @@ struct Opt {
     /// declare publication before the publisher
     #[clap(long)]
     declare_publication: bool,
+
+    /// number of concurrent publishers, at most 256
+    #[clap(long, default_value_t = 1, value_parser = parse_publishers)]
+    publishers: usize,
+
+    /// open a session per publisher instead of sharing a single one
+    #[clap(long)]
+    separate: bool,
+
+    /// time the calls publishing the messages and report the share of the time
+    /// the publishers are blocked, e.g. by the congestion control
+    #[clap(long)]
+    stalls: bool,
+
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
 
 const KEY_EXPR: &str = "/test/thr";
 
+// One of the publishers of the process
+struct Publisher {
+    producer_id: u32,
+    session: Arc<Session>,
+    meter: Arc<Meter>,
+    stalls: Option<Arc<Stalls>>,
+}
+
+impl Publisher {
+    async fn publish(self, opt: Arc<Opt>, run: Arc<Run>) {
+        let session = &self.session;
+        let mut key_exprs: Vec<KeyExpr> = vec![];
+        for key in opt.keys.keys(KEY_EXPR) {
+            let key_expr: KeyExpr = if opt.use_expr {
+                let expr_id = session.declare_expr(key.as_str()).await.unwrap();
+                if opt.declare_publication {
+                    session.declare_publication(expr_id);
+                }
+                expr_id.into()
+            } else {
+                if opt.declare_publication {
+                    session.declare_publication(key.as_str());
+                }
+                key.into()
+            };
+            key_exprs.push(key_expr);
+        }
+
+        #[cfg(feature = "shared-memory")]
+        let mut segment = opt.shm.segment(session).await;
+
+        // The sequence numbers go on across the payload sizes of a sweep
+        let mut sequence = 0;
+        for &payload in opt.sweep.sizes() {
+            // The producer id lets the subscribers tell the publishers apart
+            let data = Payload::new(self.producer_id, payload, false);
+
+            let phase = Run::new(opt.sweep.phase(), None, None);
+            let mut pacer = opt.rate.pacer();
+            while run.is_running() && phase.is_running() {
+                pacer.wait().await;
+                // Cycle over the keys to exercise the matching of the routers
+                let key_expr = &key_exprs[sequence as usize % key_exprs.len()];
+                #[cfg(not(feature = "shared-memory"))]
+                let value: Value = data.zbuf(sequence).into();
+                #[cfg(feature = "shared-memory")]
+                let value: Value = match &mut segment {
+                    Some(segment) => segment.value(&[&data.header(sequence), data.body()]).await,
+                    None => data.zbuf(sequence).into(),
+                };
+                sequence += 1;
+                let start = Instant::now();
+                session
+                    .put(key_expr.clone(), value)
+                    .congestion_control(opt.congestion.congestion)
+                    .await
+                    .unwrap();
+                if let Some(stalls) = &self.stalls {
+                    stalls.add(payload, start.elapsed());
+                }
+                self.meter.add(payload);
+                run.tick();
+            }
+            if !run.is_running() {
+                break;
+            }
+        }
+    }
+}
+
 #[async_std::main]
 async fn main() {
     // Initiate logging
     env_logger::init();
 
     // Parse the args
-    let Opt {
-        endpoint,
-        mode,
-        payload,
-        print,
-        config,
-        use_expr,
-        declare_publication,
-    } = Opt::parse();
-    let config = {
-        let mut config: Config = if let Some(path) = config {
-            Config::from_file(path).unwrap()
-        } else {
-            Config::default()
-        };
-        config.set_mode(Some(mode)).unwrap();
+    let opt = Arc::new(Opt::parse());
+    let config = || {
+        let mut config = opt.link.apply(opt.common.config());
         config.set_add_timestamp(Some(false)).unwrap();
-        config.scouting.multicast.set_enabled(Some(false)).unwrap();
-        config.connect.endpoints.extend(endpoint);
+        #[cfg(feature = "shared-memory")]
+        let config = opt.shm.apply(config);
         config
     };
 
-    let value: Value = (0usize..payload)
-        .map(|i| (i % 10) as u8)
-        .collect::<Vec<u8>>()
-        .into();
-
-    let session = zenoh::open(config).await.unwrap();
-    let writer = if use_expr {
-        let expr_id = session.declare_expr(KEY_EXPR).await.unwrap();
-        if declare_publication {
-            session.declare_publication(expr_id);
-        }
-        session.put(expr_id, value.clone())
-    } else {
-        if declare_publication {
-            session.declare_publication(KEY_EXPR);
-        }
-        session.put(KEY_EXPR, value.clone())
-    };
+    let run = opt.run.start();
+    let output = Output::new(opt.output, "z_put_thr", "session");
+    let output = opt.congestion.label(opt.rate.label(output));
+    let output = opt.link.label(opt.keys.label(output));
+    #[cfg(feature = "shared-memory")]
+    let output = opt.shm.label(output);
+    let publishers = Arc::new(Meters::publishers(&run, opt.publishers));
+    if opt.print {
+        let c_publishers = publishers.clone();
+        let c_output = output.clone();
+        task::spawn(async move {
+            loop {
+                let now = Instant::now();
+                task::sleep(Duration::from_secs(1)).await;
+                let elapsed = now.elapsed().as_micros() as f64;
+                c_publishers.record(&c_output, elapsed);
+            }
+        });
+    }
 
-    if print {
-        let count = Arc::new(AtomicUsize::new(0));
-        let c_count = count.clone();
+    let stalls = opt
+        .stalls
+        .then(|| Arc::new(Stalls::new(&run, opt.publishers)));
+    if let Some(stalls) = &stalls {
+        let c_stalls = stalls.clone();
+        let c_output = output.clone();
         task::spawn(async move {
             loop {
+                let now = Instant::now();
                 task::sleep(Duration::from_secs(1)).await;
-                let c = count.swap(0, Ordering::Relaxed);
-                if c > 0 {
-                    println!("{} msg/s", c);
-                }
+                let elapsed = now.elapsed().as_micros() as f64;
+                c_stalls.record(&c_output, elapsed);
             }
         });
+    }
 
-        loop {
-            writer
-                .clone()
-                .congestion_control(CongestionControl::Block)
-                .await
-                .unwrap();
-            c_count.fetch_add(1, Ordering::Relaxed);
-        }
+    let shared = if opt.separate {
+        None
     } else {
-        loop {
-            writer
-                .clone()
-                .congestion_control(CongestionControl::Block)
-                .await
-                .unwrap();
-        }
+        Some(Arc::new(zenoh::open(config()).await.unwrap()))
+    };
+    let mut tasks = vec![];
+    for i in 0..opt.publishers {
+        let session = match &shared {
+            Some(session) => session.clone(),
+            None => Arc::new(zenoh::open(config()).await.unwrap()),
+        };
+        let (producer_id, meter) = publishers.get(i);
+        let publisher = Publisher {
+            producer_id,
+            session,
+            meter,
+            stalls: stalls.clone(),
+        };
+        tasks.push(task::spawn(publisher.publish(opt.clone(), run.clone())));
+    }
+    for t in tasks {
+        t.await;
+    }
+
+    if opt.print {
+        publishers.emit(&output);
+    }
+    if let Some(stalls) = &stalls {
+        stalls.emit(&output);
     }
+    run.emit_totals(&output, opt.sweep.payload());
 }
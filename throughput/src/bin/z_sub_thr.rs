@@ -14,27 +14,46 @@
 use async_std::{sync::Arc, task};
 use clap::Parser;
 use std::{
-    path::PathBuf,
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
     time::{Duration, Instant},
 };
-use zenoh::{config::Config, prelude::Receiver};
-use zenoh_protocol_core::{EndPoint, WhatAmI};
+use zenoh::net::protocol::io::SplitBuffer;
+use zenoh::{
+    prelude::{Receiver, Sample},
+    Session,
+};
+#[cfg(feature = "shared-memory")]
+use zenoh_perf_common::ShmOpt;
+use zenoh_perf_common::{
+    parse_secs, CommonOpt, Header, KeysOpt, Losses, Matching, Meter, Meters, Output, OutputFormat,
+    Producers, ReliabilityOpt, Run, RunOpt, Sequences, Summaries, Summary,
+};
 
 #[derive(Debug, Parser)]
 #[clap(name = "z_sub_thr")]
 struct Opt {
-    /// endpoint(s), e.g. --endpoint tcp/127.0.0.1:7447,tcp/127.0.0.1:7448
-    #[clap(short, long, required(true), value_delimiter = ',')]
-    endpoint: Vec<EndPoint>,
+    #[clap(flatten)]
+    common: CommonOpt,
 
-    /// peer, router, or client
-    #[clap(short, long, possible_values = ["peer", "client"])]
-    mode: WhatAmI,
+    #[clap(flatten)]
+    run: RunOpt,
 
-    /// payload size (bytes)
-    #[clap(short, long)]
-    payload: usize,
+    #[clap(flatten)]
+    reliability: ReliabilityOpt,
+
+    #[clap(flatten)]
+    keys: KeysOpt,
+
+    #[cfg(feature = "shared-memory")]
+    #[clap(flatten)]
+    shm: ShmOpt,
+
+    /// how the subscribers match the keys: exact (a subscriber per key), star or double-star
+    #[clap(long = "match", default_value_t)]
+    matching: Matching,
 
     #[clap(short, long)]
     name: String,
@@ -42,10 +61,6 @@ struct Opt {
     #[clap(short, long)]
     scenario: String,
 
-    /// configuration file (json5 or yaml)
-    #[clap(long = "conf", parse(from_os_str))]
-    config: Option<PathBuf>,
-
     /// declare a numerical Id for the subscribed key expression
     #[clap(long)]
     use_expr: bool,
@@ -53,93 +68,404 @@ struct Opt {
     /// do not use callback for subscriber
     #[clap(long)]
     no_callback: bool,
+
+    /// pull the samples every --pull-period instead of having them pushed
+    #[clap(long, conflicts_with = "no_callback")]
+    pull: bool,
+
+    /// how often the pull subscribers pull (seconds)
+    #[clap(long, default_value = "0.01", value_parser = parse_period)]
+    pull_period: Duration,
+
+    /// maximum number of samples counted per pull, the following ones are
+    /// still delivered by zenoh but dropped by the subscriber, e.g. to
+    /// emulate a consumer of a bounded capacity
+    #[clap(long, requires = "pull")]
+    max_counted: Option<usize>,
+
+    /// break down the throughput per producer and report their fairness index
+    #[clap(long)]
+    producers: bool,
+
+    /// track the sequence numbers of the producers and report the lost,
+    /// duplicated and reordered messages
+    #[clap(long)]
+    losses: bool,
+
+    /// number of concurrent subscribers
+    #[clap(long, default_value_t = 1)]
+    subscribers: usize,
+
+    /// open a session per subscriber instead of sharing a single one
+    #[clap(long)]
+    separate: bool,
+
+    /// output format of the results: csv, jsonl or prom
+    #[clap(long, default_value_t)]
+    output: OutputFormat,
 }
 
 const KEY_EXPR: &str = "/test/thr";
 
+// A pull period above 0, a subscriber pulling in a busy loop otherwise
+fn parse_period(s: &str) -> Result<Duration, String> {
+    match parse_secs(s)? {
+        period if period.is_zero() => Err("The pull period must be above 0".to_string()),
+        period => Ok(period),
+    }
+}
+
+// The pulls of all the subscribers, reported every second
+struct Pulls {
+    max_counted: Option<usize>,
+    pulls: AtomicUsize,
+    samples: AtomicUsize,
+    discarded: AtomicUsize,
+    rtt_sum: AtomicU64,
+    rtt_count: AtomicU64,
+    samples_per_pull: Arc<Summaries>,
+    rtt: Arc<Summaries>,
+}
+
+impl Pulls {
+    fn new(run: &Arc<Run>, max_counted: Option<usize>) -> Self {
+        Self {
+            max_counted,
+            pulls: AtomicUsize::new(0),
+            samples: AtomicUsize::new(0),
+            discarded: AtomicUsize::new(0),
+            rtt_sum: AtomicU64::new(0),
+            rtt_count: AtomicU64::new(0),
+            samples_per_pull: run.summaries(),
+            rtt: run.summaries(),
+        }
+    }
+
+    // Emits the mean samples per pull and the mean round-trip time of the
+    // pulls since the last call, skipped for the intervals mixing several
+    // payload sizes
+    fn record(&self, output: &Output, payload: Option<usize>) {
+        let pulls = self.pulls.swap(0, Ordering::Relaxed);
+        let samples = self.samples.swap(0, Ordering::Relaxed);
+        let discarded = self.discarded.swap(0, Ordering::Relaxed);
+        let rtt_sum = self.rtt_sum.swap(0, Ordering::Relaxed);
+        let rtt_count = self.rtt_count.swap(0, Ordering::Relaxed);
+        let payload = match payload {
+            Some(payload) if pulls > 0 => payload,
+            _ => return,
+        };
+        let mean = samples as f64 / pulls as f64;
+        self.samples_per_pull
+            .record(output, "pull.samples", payload, mean, "msg/pull", &[]);
+        if rtt_count > 0 {
+            let mean = rtt_sum as f64 / rtt_count as f64;
+            self.rtt
+                .record(output, "pull.rtt", payload, mean, "us", &[]);
+        }
+        if self.max_counted.is_some() {
+            output.emit("pull.discarded", payload, discarded as f64, "msg");
+        }
+    }
+
+    fn emit(&self, output: &Output) {
+        self.samples_per_pull
+            .emit(output, "pull.samples", "msg/pull");
+        self.rtt.emit(output, "pull.rtt", "us");
+    }
+}
+
+// The latest pull of a subscription: the samples arriving until its next
+// pull are its reply, the first one giving its round-trip time
+struct Round {
+    start: Instant,
+    samples: usize,
+}
+
+struct Puller {
+    pulls: Arc<Pulls>,
+    round: Mutex<Option<Round>>,
+}
+
+impl Puller {
+    fn new(pulls: Arc<Pulls>) -> Self {
+        Self {
+            pulls,
+            round: Mutex::new(None),
+        }
+    }
+
+    // Closes the round of the previous pull and opens the one of a new pull
+    fn start(&self) {
+        let round = Round {
+            start: Instant::now(),
+            samples: 0,
+        };
+        if let Some(previous) = self.round.lock().unwrap().replace(round) {
+            self.pulls.pulls.fetch_add(1, Ordering::Relaxed);
+            self.pulls
+                .samples
+                .fetch_add(previous.samples, Ordering::Relaxed);
+        }
+    }
+
+    // Accounts for a sample, false when it exceeds --max-counted
+    fn add(&self) -> bool {
+        let mut round = self.round.lock().unwrap();
+        let round = match round.as_mut() {
+            Some(round) => round,
+            None => return true,
+        };
+        if round.samples == 0 {
+            let rtt = round.start.elapsed().as_micros() as u64;
+            self.pulls.rtt_sum.fetch_add(rtt, Ordering::Relaxed);
+            self.pulls.rtt_count.fetch_add(1, Ordering::Relaxed);
+        }
+        if self
+            .pulls
+            .max_counted
+            .is_some_and(|max| round.samples >= max)
+        {
+            self.pulls.discarded.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        round.samples += 1;
+        true
+    }
+}
+
+// One of the subscribers of the process
+struct Subscriber {
+    session: Arc<Session>,
+    meter: Arc<Meter>,
+    producers: Option<Arc<Producers>>,
+    sequences: Option<Arc<Sequences>>,
+    pulls: Option<Arc<Pulls>>,
+}
+
+impl Subscriber {
+    async fn subscribe(self, opt: Arc<Opt>, run: Arc<Run>) {
+        let session = &self.session;
+        let mut subscriptions = opt.keys.subscriptions(KEY_EXPR, opt.matching);
+
+        // The callback of a subscription, along with its puller if any
+        let on_sample = |puller: Option<Arc<Puller>>| {
+            let meter = self.meter.clone();
+            let producers = self.producers.clone();
+            let sequences = self.sequences.clone();
+            let run = run.clone();
+            move |sample: Sample| {
+                if let Some(puller) = &puller {
+                    if !puller.add() {
+                        return;
+                    }
+                }
+                meter.add(sample.value.payload.len());
+                if producers.is_some() || sequences.is_some() {
+                    match Header::decode_value(&sample.value) {
+                        Ok(header) => {
+                            if let Some(producers) = &producers {
+                                producers.add(header.producer_id);
+                            }
+                            if let Some(sequences) = &sequences {
+                                sequences.add(header.producer_id, header.sequence);
+                            }
+                        }
+                        Err(_) => {
+                            if let Some(sequences) = &sequences {
+                                sequences.add_unstamped();
+                            }
+                        }
+                    }
+                }
+                run.tick();
+            }
+        };
+
+        if opt.no_callback {
+            assert!(
+                subscriptions.len() == 1,
+                "--no-callback needs a single subscription, see --match"
+            );
+            let key = subscriptions.remove(0);
+            let sub_builder = if opt.use_expr {
+                session.subscribe(key)
+            } else {
+                session.subscribe(session.declare_expr(key).await.unwrap())
+            };
+            let mut subscriber = sub_builder
+                .reliability(opt.reliability.reliability)
+                .push_mode()
+                .await
+                .unwrap();
+            let receiver = subscriber.receiver();
+
+            let on_sample = on_sample(None);
+            // Use a timeout to notice the end of the run even without traffic
+            while run.is_running() {
+                if let Ok(sample) = receiver.recv_timeout(Duration::from_millis(10)) {
+                    on_sample(sample);
+                }
+            }
+        } else {
+            // Every subscription has its own puller, so that the reply of
+            // each pull request is told apart from the others
+            let mut subscribers = vec![];
+            for key in subscriptions {
+                let sub_builder = if opt.use_expr {
+                    session.subscribe(key)
+                } else {
+                    session.subscribe(session.declare_expr(key).await.unwrap())
+                };
+                let puller = self
+                    .pulls
+                    .as_ref()
+                    .map(|pulls| Arc::new(Puller::new(pulls.clone())));
+                let sub_builder = sub_builder
+                    .callback(on_sample(puller.clone()))
+                    .reliability(opt.reliability.reliability);
+                let subscriber = if opt.pull {
+                    sub_builder.pull_mode().await.unwrap()
+                } else {
+                    sub_builder.push_mode().await.unwrap()
+                };
+                subscribers.push((subscriber, puller));
+            }
+
+            if opt.pull {
+                while run.is_running() {
+                    for (subscriber, puller) in subscribers.iter() {
+                        if let Some(puller) = puller {
+                            puller.start();
+                        }
+                        subscriber.pull().await.unwrap();
+                    }
+                    task::sleep(opt.pull_period).await;
+                }
+            } else {
+                run.wait().await;
+            }
+        }
+    }
+}
+
 #[async_std::main]
 async fn main() {
     // initiate logging
     env_logger::init();
 
     // Parse the args
-    let Opt {
-        endpoint,
-        mode,
-        payload,
-        name,
-        scenario,
-        config,
-        use_expr,
-        no_callback,
-    } = Opt::parse();
-
-    let config = {
-        let mut config: Config = if let Some(path) = config {
-            Config::from_file(path).unwrap()
-        } else {
-            Config::default()
-        };
-        config.set_mode(Some(mode)).unwrap();
-        config.scouting.multicast.set_enabled(Some(false)).unwrap();
-        match mode {
-            WhatAmI::Peer => config.listen.endpoints.extend(endpoint),
-            WhatAmI::Client => config.connect.endpoints.extend(endpoint),
-            _ => panic!("Unsupported mode: {}", mode),
-        };
+    let opt = Arc::new(Opt::parse());
+    let output = Output::new(opt.output, "z_sub_thr", "session")
+        .label("scenario", &opt.scenario)
+        .label("name", &opt.name);
+    let output = opt.keys.label(opt.reliability.label(output));
+    let output = match opt.keys.count() {
+        1 => output,
+        _ => output.label("match", opt.matching),
+    };
+    let output = match (opt.pull, opt.max_counted) {
+        (false, _) => output,
+        (true, None) => output.label("pull", opt.pull_period.as_secs_f64()),
+        (true, Some(max)) => output
+            .label("pull", opt.pull_period.as_secs_f64())
+            .label("max_counted", max),
+    };
+    #[cfg(feature = "shared-memory")]
+    let output = opt.shm.label(output);
+    // The payloads published from shared memory are read without copy
+    let config = || {
+        let config = opt.common.config();
+        #[cfg(feature = "shared-memory")]
+        let config = opt.shm.apply(config);
         config
     };
 
-    let messages = Arc::new(AtomicUsize::new(0));
-    let c_messages = messages.clone();
+    let run = opt.run.start();
+    let fairness = run.summary();
+    let subscribers = Arc::new(Meters::subscribers(&run, opt.subscribers));
+    let producers = opt.producers.then(|| Arc::new(Producers::new()));
+    let losses = opt
+        .losses
+        .then(|| Arc::new(Losses::new(&run, opt.subscribers)));
+    let pulls = opt
+        .pull
+        .then(|| Arc::new(Pulls::new(&run, opt.max_counted)));
+
+    task::spawn(measure(
+        subscribers.clone(),
+        output.clone(),
+        run.clone(),
+        producers.clone(),
+        fairness.clone(),
+        losses.clone(),
+        pulls.clone(),
+    ));
 
-    let session = zenoh::open(config).await.unwrap();
-    let sub_builder = if use_expr {
-        session.subscribe(KEY_EXPR)
+    let shared = if opt.separate {
+        None
     } else {
-        session.subscribe(session.declare_expr(KEY_EXPR).await.unwrap())
+        Some(Arc::new(zenoh::open(config()).await.unwrap()))
     };
+    let mut tasks = vec![];
+    for i in 0..opt.subscribers {
+        let session = match &shared {
+            Some(session) => session.clone(),
+            None => Arc::new(zenoh::open(config()).await.unwrap()),
+        };
+        let (_, meter) = subscribers.get(i);
+        let subscriber = Subscriber {
+            session,
+            meter,
+            producers: producers.clone(),
+            sequences: losses.as_ref().map(|losses| losses.get(i)),
+            pulls: pulls.clone(),
+        };
+        // The receivers without callback block their thread
+        let (opt, run) = (opt.clone(), run.clone());
+        tasks.push(task::spawn_blocking(move || {
+            task::block_on(subscriber.subscribe(opt, run))
+        }));
+    }
+    for t in tasks {
+        t.await;
+    }
 
-    if no_callback {
-        task::spawn(async move {
-            measure(c_messages, scenario, name, payload).await;
-        });
-
-        let mut subscriber = sub_builder.reliable().push_mode().await.unwrap();
-
-        while subscriber.receiver().recv().is_ok() {
-            messages.fetch_add(1, Ordering::Relaxed);
-        }
-    } else {
-        let _subscriber = sub_builder
-            .callback(move |_| {
-                c_messages.fetch_add(1, Ordering::Relaxed);
-            })
-            .reliable()
-            .push_mode()
-            .await
-            .unwrap();
-
-        measure(messages, scenario, name, payload).await;
+    subscribers.emit(&output);
+    if producers.is_some() {
+        fairness.emit(&output, "fairness", subscribers.payload(), "index");
     }
+    if let Some(losses) = &losses {
+        losses.emit(&output);
+    }
+    if let Some(pulls) = &pulls {
+        pulls.emit(&output);
+    }
+    run.emit_totals(&output, subscribers.payload());
 }
 
-async fn measure(messages: Arc<AtomicUsize>, scenario: String, name: String, payload: usize) {
-    loop {
+async fn measure(
+    subscribers: Arc<Meters>,
+    output: Output,
+    run: Arc<Run>,
+    producers: Option<Arc<Producers>>,
+    fairness: Arc<Summary>,
+    losses: Option<Arc<Losses>>,
+    pulls: Option<Arc<Pulls>>,
+) {
+    while run.is_running() {
         let now = Instant::now();
         task::sleep(Duration::from_secs(1)).await;
         let elapsed = now.elapsed().as_micros() as f64;
 
-        let c = messages.swap(0, Ordering::Relaxed);
-        if c > 0 {
-            let interval = 1_000_000.0 / elapsed;
-            println!(
-                "zenoh,{},throughput,{},{},{}",
-                scenario,
-                name,
-                payload,
-                (c as f64 / interval).floor() as usize
-            );
+        let payload = subscribers.record(&output, elapsed);
+        if let Some(producers) = &producers {
+            producers.record(&output, payload, elapsed, &fairness);
+        }
+        if let Some(losses) = &losses {
+            losses.record(&output, payload);
+        }
+        if let Some(pulls) = &pulls {
+            pulls.record(&output, payload);
         }
     }
 }